@@ -0,0 +1,372 @@
+//! Oracle ASM instances: diskgroups, client databases, and the processes serving them
+
+use oracle::Connection;
+use serde::{Deserialize, Serialize};
+
+/// A diskgroup of v$asm_diskgroup_stat
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AsmDiskgroup {
+    pub group_number: u32,
+    pub name: String,
+    pub state: String,
+    /// EXTERN, NORMAL, HIGH, FLEX...
+    pub redundancy: Option<String>,
+    pub total_mb: u64,
+    pub free_mb: u64,
+    /// negative when a failure can't be absorbed
+    pub usable_file_mb: i64,
+    pub allocation_unit_size: u64,
+}
+
+/// A database instance using a diskgroup, from v$asm_client
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AsmClient {
+    pub group_number: u32,
+    pub instance_name: String,
+    pub db_name: Option<String>,
+    pub status: Option<String>,
+    pub software_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AsmInfo {
+    pub diskgroups: Vec<AsmDiskgroup>,
+    pub clients: Vec<AsmClient>,
+}
+
+impl AsmInfo {
+    /// v$asm_diskgroup would rediscover the disks, the _stat view only reads the SGA
+    pub fn query(conn: &Connection) -> Result<Self, oracle::Error> {
+        let sql = "select group_number, name, state, type, total_mb, free_mb, usable_file_mb,
+            allocation_unit_size from v$asm_diskgroup_stat";
+        let mut diskgroups = Vec::new();
+        for row in
+            conn.query_as::<(u32, String, String, Option<String>, u64, u64, i64, u64)>(sql, &[])?
+        {
+            let (
+                group_number,
+                name,
+                state,
+                redundancy,
+                total_mb,
+                free_mb,
+                usable_file_mb,
+                allocation_unit_size,
+            ) = row?;
+            diskgroups.push(AsmDiskgroup {
+                group_number,
+                name,
+                state,
+                redundancy,
+                total_mb,
+                free_mb,
+                usable_file_mb,
+                allocation_unit_size,
+            });
+        }
+
+        let sql = "select group_number, instance_name, db_name, status, software_version
+            from v$asm_client";
+        let mut clients = Vec::new();
+        for row in conn
+            .query_as::<(u32, String, Option<String>, Option<String>, Option<String>)>(sql, &[])?
+        {
+            let (group_number, instance_name, db_name, status, software_version) = row?;
+            clients.push(AsmClient {
+                group_number,
+                instance_name,
+                db_name,
+                status,
+                software_version,
+            });
+        }
+
+        Ok(Self {
+            diskgroups,
+            clients,
+        })
+    }
+}
+
+/// Client of an ASM foreground process: `oracle+ASM1_asmb_orcl1 (DESCRIPTION=...)` is the ASMB
+/// connection of orcl1
+pub fn asm_process_client<'a>(asm_sid: &str, cmdline: &'a [String]) -> Option<&'a str> {
+    let first = cmdline.first()?.split_whitespace().next()?;
+    let (_, client) = first
+        .strip_prefix("oracle")?
+        .strip_prefix(asm_sid)?
+        .strip_prefix('_')?
+        .split_once('_')?;
+    Some(client)
+}
+
+/// Processes of a database talking to ASM: ASMB, RBAL, and the Onnn connection pool
+pub fn is_asm_related(process: &crate::OracleProcess) -> bool {
+    match process.pname.as_deref() {
+        Some("ASMB") | Some("RBAL") => true,
+        Some(pname) => {
+            pname.len() == 4
+                && pname.starts_with('O')
+                && pname[1..].chars().all(|c| c.is_ascii_digit())
+        }
+        None => false,
+    }
+}
+
+/// Disk space of a diskgroup, in binary units to tell it apart from memory sizes
+#[cfg(unix)]
+fn format_storage(bytes: &u64) -> String {
+    humansize::format_size(*bytes, humansize::BINARY)
+}
+
+/// Diskgroups of an ASM instance, its client databases, and the processes on both sides
+#[cfg(unix)]
+pub fn display(asm: &crate::SmonInfo, instances: &[crate::SmonInfo]) {
+    use crate::tmpfs::format_units_MiB;
+    use procfs::process::Process;
+    use tabled::Tabled;
+
+    /// Disk storage, nothing here is memory
+    #[derive(Tabled)]
+    struct DiskgroupDisplayRow {
+        diskgroup: String,
+        state: String,
+        redundancy: String,
+        #[tabled(rename = "disk total", display = "format_storage")]
+        total: u64,
+        #[tabled(rename = "disk free", display = "format_storage")]
+        free: u64,
+        #[tabled(rename = "usable file", display = "format_storage")]
+        usable: u64,
+        #[tabled(rename = "AU size", display = "format_storage")]
+        au_size: u64,
+        clients: usize,
+    }
+
+    #[derive(Tabled)]
+    struct ClientDisplayRow {
+        instance: String,
+        db_name: String,
+        status: String,
+        version: String,
+        diskgroups: String,
+        #[tabled(rename = "ASM processes")]
+        asm_processes: usize,
+        #[tabled(rename = "ASM pga", display = "format_units_MiB")]
+        asm_pga: u64,
+        #[tabled(rename = "DB processes")]
+        db_processes: String,
+        #[tabled(rename = "DB pga")]
+        db_pga: String,
+    }
+
+    let Some(info) = &asm.asm else {
+        return;
+    };
+    let sid = asm.sid.to_string_lossy();
+    const MB: u64 = 1024 * 1024;
+
+    let diskgroup_rows: Vec<DiskgroupDisplayRow> = info
+        .diskgroups
+        .iter()
+        .map(|diskgroup| DiskgroupDisplayRow {
+            diskgroup: diskgroup.name.clone(),
+            state: diskgroup.state.clone(),
+            redundancy: diskgroup.redundancy.clone().unwrap_or_default(),
+            total: diskgroup.total_mb * MB,
+            free: diskgroup.free_mb * MB,
+            usable: diskgroup.usable_file_mb.max(0) as u64 * MB,
+            au_size: diskgroup.allocation_unit_size,
+            clients: info
+                .clients
+                .iter()
+                .filter(|client| client.group_number == diskgroup.group_number)
+                .count(),
+        })
+        .collect();
+
+    // ASM processes, by client instance
+    let asm_processes: Vec<(String, &crate::OracleProcess)> = asm
+        .pga_processes
+        .iter()
+        .filter_map(|process| {
+            let cmdline = Process::new(process.spid).ok()?.cmdline().ok()?;
+            Some((asm_process_client(&sid, &cmdline)?.to_string(), process))
+        })
+        .collect();
+
+    let mut client_names: Vec<&str> = info
+        .clients
+        .iter()
+        .map(|client| client.instance_name.as_str())
+        .collect();
+    client_names.sort();
+    client_names.dedup();
+
+    let client_rows: Vec<ClientDisplayRow> = client_names
+        .iter()
+        .map(|name| {
+            let clients: Vec<&AsmClient> = info
+                .clients
+                .iter()
+                .filter(|client| client.instance_name == *name)
+                .collect();
+            let diskgroups: Vec<&str> = clients
+                .iter()
+                .filter_map(|client| {
+                    info.diskgroups
+                        .iter()
+                        .find(|diskgroup| diskgroup.group_number == client.group_number)
+                })
+                .map(|diskgroup| diskgroup.name.as_str())
+                .collect();
+            let served: Vec<&crate::OracleProcess> = asm_processes
+                .iter()
+                .filter(|(client, _)| client.eq_ignore_ascii_case(name))
+                .map(|(_, process)| *process)
+                .collect();
+            // the DB side is known if the client instance runs on this host
+            let db_side: Option<Vec<&crate::OracleProcess>> = instances
+                .iter()
+                .find(|instance| instance.sid.to_string_lossy().eq_ignore_ascii_case(name))
+                .map(|instance| {
+                    instance
+                        .pga_processes
+                        .iter()
+                        .filter(|process| is_asm_related(process))
+                        .collect()
+                });
+
+            ClientDisplayRow {
+                instance: name.to_string(),
+                db_name: clients[0].db_name.clone().unwrap_or_default(),
+                status: clients[0].status.clone().unwrap_or_default(),
+                version: clients[0].software_version.clone().unwrap_or_default(),
+                diskgroups: diskgroups.join(" "),
+                asm_processes: served.len(),
+                asm_pga: served.iter().map(|process| process.pga_alloc_mem).sum(),
+                db_processes: db_side
+                    .as_ref()
+                    .map(|processes| {
+                        processes
+                            .iter()
+                            .filter_map(|process| process.pname.as_deref())
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .unwrap_or_else(|| "-".to_string()),
+                db_pga: db_side
+                    .map(|processes| {
+                        format_units_MiB(&processes.iter().map(|p| p.pga_alloc_mem).sum())
+                    })
+                    .unwrap_or_else(|| "-".to_string()),
+            }
+        })
+        .collect();
+
+    println!("ASM diskgroups of {sid}, disk storage capacity (not memory):");
+    let mut table = tabled::Table::new(&diskgroup_rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+
+    // metadata blocks of all diskgroups, there is no memory per diskgroup
+    let cache = asm
+        .sga_layout
+        .components
+        .iter()
+        .find(|component| component.name.eq_ignore_ascii_case("ASM Buffer Cache"))
+        .map(|component| component.current_size)
+        .filter(|size| *size > 0);
+    if let Some(cache) = cache {
+        println!(
+            "ASM Buffer Cache of {sid} (SGA component, metadata blocks of all diskgroups): {}",
+            format_units_MiB(&cache)
+        );
+    }
+
+    println!("ASM clients of {sid}, memory (PGA of the ASM processes serving each client, and of its ASMB, RBAL, Onnn processes):");
+    let mut table = tabled::Table::new(&client_rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn process(pname: Option<&str>) -> crate::OracleProcess {
+        crate::OracleProcess {
+            spid: 1234,
+            pname: pname.map(str::to_string),
+            program: None,
+            background: pname.is_some(),
+            pga_used_mem: 0,
+            pga_alloc_mem: 0,
+            pga_max_mem: 0,
+            con_id: None,
+        }
+    }
+
+    #[test]
+    fn process_client() {
+        let asmb =
+            cmdline(&["oracle+ASM1_asmb_orcl1 (DESCRIPTION=(LOCAL=YES)(ADDRESS=(PROTOCOL=beq)))"]);
+        assert_eq!(asm_process_client("+ASM1", &asmb), Some("orcl1"));
+        let o000 = cmdline(&["oracle+ASM1_o000_orcl1"]);
+        assert_eq!(asm_process_client("+ASM1", &o000), Some("orcl1"));
+        let user = cmdline(&["oracle+ASM1_user_my_db1", "(LOCAL=NO)"]);
+        assert_eq!(asm_process_client("+ASM1", &user), Some("my_db1"));
+    }
+
+    #[test]
+    fn sid_prefix() {
+        // +ASM1 is a prefix of +ASM10, their processes must not be mixed up
+        let asmb = cmdline(&["oracle+ASM10_asmb_orcl10"]);
+        assert_eq!(asm_process_client("+ASM1", &asmb), None);
+        assert_eq!(asm_process_client("+ASM10", &asmb), Some("orcl10"));
+        let asmb = cmdline(&["oracle+ASM1_asmb_orcl1"]);
+        assert_eq!(asm_process_client("+ASM10", &asmb), None);
+    }
+
+    #[test]
+    fn not_a_client() {
+        // foreground without a client instance, background process, other instance
+        let foreground =
+            cmdline(&["oracle+ASM1 (DESCRIPTION=(LOCAL=YES)(ADDRESS=(PROTOCOL=beq)))"]);
+        assert_eq!(asm_process_client("+ASM1", &foreground), None);
+        assert_eq!(
+            asm_process_client("+ASM1", &cmdline(&["asm_rbal_+ASM1"])),
+            None
+        );
+        assert_eq!(
+            asm_process_client("+ASM1", &cmdline(&["oracleorcl1 (LOCAL=NO)"])),
+            None
+        );
+        assert_eq!(
+            asm_process_client("+ASM1", &cmdline(&["oracle+ASM1_asmb"])),
+            None
+        );
+        assert_eq!(asm_process_client("+ASM1", &[]), None);
+        assert_eq!(asm_process_client("+ASM1", &cmdline(&[""])), None);
+    }
+
+    #[test]
+    fn asm_related() {
+        assert!(is_asm_related(&process(Some("ASMB"))));
+        assert!(is_asm_related(&process(Some("RBAL"))));
+        assert!(is_asm_related(&process(Some("O000"))));
+        assert!(is_asm_related(&process(Some("O123"))));
+
+        assert!(!is_asm_related(&process(Some("PMON"))));
+        assert!(!is_asm_related(&process(Some("OFSD"))));
+        assert!(!is_asm_related(&process(Some("O00"))));
+        assert!(!is_asm_related(&process(Some("O0000"))));
+        assert!(!is_asm_related(&process(Some("o000"))));
+        assert!(!is_asm_related(&process(None)));
+    }
+}
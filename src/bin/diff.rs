@@ -28,7 +28,7 @@ impl Snapshot {
         let mut snap_dir: PathBuf = if path
             .as_ref()
             .components()
-            .last()
+            .next_back()
             .map(|p| p.as_os_str().to_string_lossy().to_string())
             .unwrap()
             .ends_with(".tar.gz")
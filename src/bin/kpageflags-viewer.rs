@@ -2,31 +2,130 @@
 // Uses /proc/iomem and /proc/kpageflags
 //
 
-use std::{net::SocketAddr, process::Command, thread, time::Duration};
+use std::{path::Path, process::Command, thread, time::Duration};
 
 use messages::*;
+use transport::Endpoint;
 
 pub mod messages {
     use std::{
-        collections::HashSet,
+        collections::{HashMap, HashSet},
         io::{Read, Write},
-        net::TcpStream,
         path::PathBuf,
+        str::FromStr,
+        sync::Arc,
+        time::Duration,
     };
 
     use procfs_core::{process::Pfn, PhysicalMemoryMap, PhysicalPageFlags};
     use serde::{Deserialize, Serialize};
+    use snap::memory_layout::MemoryLayout;
+
+    /// Must be increased on every incompatible change of the messages or framing
+    pub const PROTOCOL_VERSION: u16 = 11;
+
+    /// Largest overview of the map, 2^11 = 2048 pixels wide
+    pub const MAX_OVERVIEW_ORDER: u8 = 11;
+    /// Largest tile the server sends, see TileRequestMessage
+    pub const MAX_TILE_PAGES: u64 = 1 << 20;
+
+    /// Unchanged pages between 2 changed runs are resent if the gap is smaller than this,
+    /// to avoid sending lots of tiny runs
+    const RUN_MERGE_GAP: usize = 16;
+
+    #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum Compression {
+        None,
+        Deflate,
+        Zstd,
+    }
+
+    impl Compression {
+        fn to_u8(self) -> u8 {
+            match self {
+                Compression::None => 0,
+                Compression::Deflate => 1,
+                Compression::Zstd => 2,
+            }
+        }
+
+        fn from_u8(value: u8) -> Result<Self, Box<dyn std::error::Error>> {
+            match value {
+                0 => Ok(Compression::None),
+                1 => Ok(Compression::Deflate),
+                2 => Ok(Compression::Zstd),
+                _ => Err(format!("Unknown compression {}", value).into()),
+            }
+        }
+
+        /// Read from env variable COMPRESSION, defaults to no compression
+        pub fn from_env() -> Result<Self, Box<dyn std::error::Error>> {
+            match std::env::var("COMPRESSION") {
+                Ok(value) => value.parse(),
+                Err(_) => Ok(Compression::None),
+            }
+        }
+    }
+
+    impl FromStr for Compression {
+        type Err = Box<dyn std::error::Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "none" => Ok(Compression::None),
+                "deflate" => Ok(Compression::Deflate),
+                "zstd" => Ok(Compression::Zstd),
+                _ => Err(format!("Unknown compression {:?}, expected none/deflate/zstd", s).into()),
+            }
+        }
+    }
+
+    /// Maximum message size in MiB, from env variable MAX_MESSAGE_SIZE
+    fn max_message_size() -> u64 {
+        std::env::vars()
+            .filter_map(|(k, v)| {
+                if k == "MAX_MESSAGE_SIZE" {
+                    let value: Option<u64> = v.parse().ok();
+                    value
+                } else {
+                    None
+                }
+            })
+            .next()
+            .unwrap_or(100)
+    }
 
     #[derive(Serialize, Deserialize)]
     pub enum Message {
+        /// First message from the client
+        ClientHello(ClientHelloMessage),
+        /// Answer to ClientHello
+        ServerHello(ServerHelloMessage),
         FirstUpdate(FirstUpdateMessage),
-        Update(UpdateMessage),
+        Update(Arc<UpdateMessage>),
+        Delta(Box<DeltaUpdateMessage>),
+        /// Server is shutting down
         Finish,
+        /// Sent by the server before closing the connection, e.g. failed authentication
+        Error(String),
+        /// Sent by the client, for the layers of pages not in the overviews
+        TileRequest(TileRequestMessage),
+        /// Answer to TileRequest
+        Tile(TileMessage),
+        /// Sent by the client when a delta can't be applied, the next update is complete
+        KeyframeRequest,
         //ServerParams(ServerParamsMessage),
     }
 
     impl Message {
-        pub fn send(&self, socket: &mut TcpStream) -> Result<usize, Box<dyn std::error::Error>> {
+        /// Frame: [payload size: u64][protocol version: u16][compression: u8][payload]
+        ///
+        /// Return the size of the payload after compression
+        pub fn send(
+            &self,
+            socket: &mut (impl Write + ?Sized),
+            compression: Compression,
+        ) -> Result<usize, Box<dyn std::error::Error>> {
             let buf = rmp_serde::to_vec(&self)?;
 
             //let buf = bincode::serialize(&self)?;
@@ -36,332 +135,3990 @@ pub mod messages {
             //ciborium::into_writer(&self, &mut buf).unwrap();
             //dbg!(buf.len());
 
+            let buf = match compression {
+                Compression::None => buf,
+                Compression::Deflate => {
+                    let mut encoder =
+                        flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::fast());
+                    encoder.write_all(&buf)?;
+                    encoder.finish()?
+                }
+                Compression::Zstd => zstd::encode_all(&buf[..], 3)?,
+            };
+
             let size = (buf.len() as u64).to_le_bytes();
             socket.write_all(&size)?;
+            socket.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+            socket.write_all(&[compression.to_u8()])?;
             socket.write_all(&buf)?;
+            socket.flush()?;
 
             Ok(buf.len())
         }
 
-        pub fn recv(socket: &mut TcpStream) -> Result<Self, Box<dyn std::error::Error>> {
+        pub fn recv(socket: &mut (impl Read + ?Sized)) -> Result<Self, Box<dyn std::error::Error>> {
             let mut size = [0u8; 8];
             socket.read_exact(&mut size)?;
             let size = u64::from_le_bytes(size);
-            dbg!(size);
-
-            let max_message_size = std::env::vars()
-                .filter_map(|(k, v)| {
-                    if k == "MAX_MESSAGE_SIZE" {
-                        let value: Option<u64> = v.parse().ok();
-                        value
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .unwrap_or(100);
 
-            if size > max_message_size * 1024 * 1024 {
+            let mut version = [0u8; 2];
+            socket.read_exact(&mut version)?;
+            let version = u16::from_le_bytes(version);
+            if version != PROTOCOL_VERSION {
+                return Err(format!(
+                    "Protocol version mismatch: remote {}, local {}. Use the same version of kpageflags-viewer on both sides",
+                    version, PROTOCOL_VERSION
+                )
+                .into());
+            }
+
+            let mut compression = [0u8; 1];
+            socket.read_exact(&mut compression)?;
+            let compression = Compression::from_u8(compression[0])?;
+
+            let max_message_size = max_message_size() * 1024 * 1024;
+            if size > max_message_size {
                 return Err(format!(
                     "Message is too big! ({} MiB) (max {} MiB). Try to increase env variable MAX_MESSAGE_SIZE",
-                    size / 1024 / 1024, max_message_size
+                    size / 1024 / 1024, max_message_size / 1024 / 1024
                 )
                 .into());
             }
             let mut buf: Vec<u8> = vec![0u8; size as usize];
             socket.read_exact(&mut buf)?;
 
-            let message: Message = match rmp_serde::from_slice(&buf) {
-                Ok(m) => m,
-                Err(e) => panic!("deserialization failed {:?}", e),
+            // the limit also applies to the decompressed size
+            let buf = match compression {
+                Compression::None => buf,
+                Compression::Deflate => {
+                    let mut decompressed = Vec::new();
+                    flate2::read::DeflateDecoder::new(&buf[..])
+                        .take(max_message_size + 1)
+                        .read_to_end(&mut decompressed)?;
+                    decompressed
+                }
+                Compression::Zstd => {
+                    let mut decompressed = Vec::new();
+                    zstd::Decoder::new(&buf[..])?
+                        .take(max_message_size + 1)
+                        .read_to_end(&mut decompressed)?;
+                    decompressed
+                }
             };
+            if buf.len() as u64 > max_message_size {
+                return Err(format!(
+                    "Decompressed message is too big! (max {} MiB). Try to increase env variable MAX_MESSAGE_SIZE",
+                    max_message_size / 1024 / 1024
+                )
+                .into());
+            }
+
+            let message: Message = rmp_serde::from_slice(&buf)
+                .map_err(|e| format!("deserialization failed {:?}", e))?;
 
             Ok(message)
         }
     }
 
+    /// Frame size, protocol version and compression, see Message::send
+    const FRAME_HEADER_SIZE: usize = 8 + 2 + 1;
+
+    /// Read messages from a socket with a short read timeout, see transport::POLL_INTERVAL
+    ///
+    /// Partial frames are kept between calls, so reads and writes can be interleaved on the same
+    /// thread
+    #[derive(Default)]
+    pub struct MessageReader {
+        buf: Vec<u8>,
+    }
+
+    impl MessageReader {
+        /// Read from the socket once, return None if no complete message was received
+        pub fn poll(
+            &mut self,
+            socket: &mut (impl Read + ?Sized),
+        ) -> Result<Option<Message>, Box<dyn std::error::Error>> {
+            if let Some(message) = self.take_message()? {
+                return Ok(Some(message));
+            }
+
+            let len = self.buf.len();
+            self.buf.resize(len + 1024 * 1024, 0);
+            let read = socket.read(&mut self.buf[len..]);
+            self.buf.truncate(len + *read.as_ref().unwrap_or(&0));
+            match read {
+                Ok(0) => Err("Connection closed".into()),
+                Ok(_) => self.take_message(),
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    Ok(None)
+                }
+                Err(e) => Err(e.into()),
+            }
+        }
+
+        /// Wait for a complete message
+        pub fn recv_timeout(
+            &mut self,
+            socket: &mut (impl Read + ?Sized),
+            timeout: Duration,
+        ) -> Result<Message, Box<dyn std::error::Error>> {
+            let deadline = std::time::Instant::now() + timeout;
+            loop {
+                if let Some(message) = self.poll(socket)? {
+                    return Ok(message);
+                }
+                if std::time::Instant::now() >= deadline {
+                    return Err("Timeout".into());
+                }
+            }
+        }
+
+        fn take_message(&mut self) -> Result<Option<Message>, Box<dyn std::error::Error>> {
+            if self.buf.len() < FRAME_HEADER_SIZE {
+                return Ok(None);
+            }
+            let size = u64::from_le_bytes(self.buf[..8].try_into().unwrap());
+            // checked again by Message::recv, but don't buffer more than the limit
+            if size > max_message_size() * 1024 * 1024 {
+                return Message::recv(&mut &self.buf[..]).map(Some);
+            }
+            let frame_size = FRAME_HEADER_SIZE + size as usize;
+            if self.buf.len() < frame_size {
+                return Ok(None);
+            }
+            let message = Message::recv(&mut &self.buf[..frame_size]);
+            self.buf.drain(..frame_size);
+            message.map(Some)
+        }
+    }
+
+    /// Order of the Hilbert curve with a point per page: 2^order pages wide
+    pub fn map_order(iomem: &[PhysicalMemoryMap], page_size: u64) -> u8 {
+        let pfns = snap::get_pfn_count(iomem, page_size);
+        ((pfns as f64).log2() / 2.).ceil() as u8
+    }
+
+    /// Pages per overview pixel are 4^level, a 2^level wide square of the Hilbert curve
+    pub fn detail_level(order: u8) -> u8 {
+        order.saturating_sub(MAX_OVERVIEW_ORDER)
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ClientHelloMessage {
+        pub protocol_version: u16,
+        /// Requested interval between updates
+        pub update_interval: Duration,
+        /// Shared secret, from env variable AUTH_TOKEN
+        pub token: Option<String>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    pub struct ServerHelloMessage {
+        pub protocol_version: u16,
+        /// Actual interval between updates, can be larger than requested
+        pub update_interval: Duration,
+    }
+
     #[derive(Serialize, Deserialize)]
     pub struct FirstUpdateMessage {
         pub page_size: u64,
-        pub update_message: UpdateMessage,
+        /// Zones, NUMA nodes and memory blocks
+        pub memory_layout: MemoryLayout,
+        /// Shared between clients on the server side
+        pub update_message: Arc<UpdateMessage>,
         //pub processes_info: Vec<ProcessInfo>,
         //pub memory_segments: Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)>,
         //pub iomem: Vec<PhysicalMemoryMap>,
     }
 
+    /// Layers of pages in `index..index + pages`, where index is the position of a page in RAM
+    /// (see snap::pfn_to_index), up to MAX_TILE_PAGES
+    ///
+    /// Answered with the layers of the last update sent to the client, only sent when the
+    /// update has overviews
+    #[derive(Serialize, Deserialize)]
+    pub struct TileRequestMessage {
+        pub index: u64,
+        pub pages: u64,
+        /// Processes whose pages are highlighted
+        pub pids: Vec<i32>,
+    }
+
     #[derive(Serialize, Deserialize)]
+    pub struct TileMessage {
+        pub index: u64,
+        /// Shorter than requested at the end of RAM
+        pub flags: Vec<PhysicalPageFlags>,
+        /// Same length as flags, if map counts are sent
+        pub map_counts: Option<Vec<u64>>,
+        /// Same length as flags, if memcgs are sent
+        pub memcgs: Option<Vec<u64>>,
+        /// Same length as flags, see page_owners
+        pub owners: Vec<u32>,
+        /// Offsets in the tile of the pages of each requested process, in request order
+        pub processes: Vec<(i32, Vec<u32>)>,
+    }
+
+    /// Distinct values and their number of pages
+    pub type PixelValues<T> = Vec<(T, u32)>;
+
+    /// A per-page layer of the map overview, sent instead of the value of each page on large
+    /// hosts
+    ///
+    /// A pixel is 4^level consecutive pages of the Hilbert curve, the values of each page are
+    /// sent on demand, see TileRequestMessage
+    #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+    pub struct Overview<T> {
+        pub level: u8,
+        /// Distinct values of each pixel with their number of pages, pixel after pixel
+        counts: Vec<(T, u32)>,
+        /// End of the counts of each pixel in `counts`
+        ends: Vec<u32>,
+    }
+
+    pub type FlagsOverview = Overview<PhysicalPageFlags>;
+
+    impl<T: Copy + Ord> Overview<T> {
+        /// `segments`: values of each RAM segment, in /proc/iomem order
+        pub fn new(segments: &[Vec<T>], level: u8) -> Self {
+            let pixel_pages = 1 << (2 * level);
+            let mut overview = Self {
+                level,
+                counts: Vec::new(),
+                ends: Vec::new(),
+            };
+            let mut pixel = Vec::with_capacity(pixel_pages);
+            for &value in segments.iter().flatten() {
+                pixel.push(value);
+                if pixel.len() == pixel_pages {
+                    overview.push_pixel(&mut pixel);
+                }
+            }
+            if !pixel.is_empty() {
+                overview.push_pixel(&mut pixel);
+            }
+            overview
+        }
+
+        fn push_pixel(&mut self, pixel: &mut Vec<T>) {
+            pixel.sort_unstable();
+            let start = self.counts.len();
+            for &value in pixel.iter() {
+                match self.counts[start..].last_mut() {
+                    Some((last, count)) if *last == value => *count += 1,
+                    _ => self.counts.push((value, 1)),
+                }
+            }
+            self.ends.push(self.counts.len() as u32);
+            pixel.clear();
+        }
+
+        pub fn pixels(&self) -> usize {
+            self.ends.len()
+        }
+
+        pub fn pixel(&self, idx: usize) -> &[(T, u32)] {
+            let start = idx.checked_sub(1).map(|prev| self.ends[prev]).unwrap_or(0);
+            &self.counts[start as usize..self.ends[idx] as usize]
+        }
+
+        /// Values of all pixels with their number of pages, a value appears once per pixel
+        pub fn values(&self) -> impl Iterator<Item = (T, u32)> + '_ {
+            self.counts.iter().copied()
+        }
+
+        /// Pixels changed from `self` to `new`, with the same number of pixels
+        fn changed_pixels(&self, new: &Overview<T>) -> Vec<(u32, PixelValues<T>)> {
+            (0..new.pixels())
+                .filter(|&idx| self.pixel(idx) != new.pixel(idx))
+                .map(|idx| (idx as u32, new.pixel(idx).to_vec()))
+                .collect()
+        }
+
+        fn apply_pixels(
+            &mut self,
+            changed: Vec<(u32, PixelValues<T>)>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            if changed.is_empty() {
+                return Ok(());
+            }
+            let mut changed = changed.into_iter().peekable();
+            let mut counts = Vec::with_capacity(self.counts.len());
+            let mut ends = Vec::with_capacity(self.ends.len());
+            for idx in 0..self.pixels() {
+                match changed.next_if(|(changed_idx, _)| *changed_idx as usize == idx) {
+                    Some((_, pixel)) => counts.extend(pixel),
+                    None => counts.extend_from_slice(self.pixel(idx)),
+                }
+                ends.push(counts.len() as u32);
+            }
+            if let Some((idx, _)) = changed.next() {
+                return Err(format!("Delta for unknown pixel {}", idx).into());
+            }
+            self.counts = counts;
+            self.ends = ends;
+            Ok(())
+        }
+    }
+
+    /// Page owners, see page_owners
+    pub const NO_OWNER: u32 = 0;
+    /// Mapped by processes of several groups
+    pub const SHARED_OWNER: u32 = 1;
+    /// Owner of the first group of group_names
+    pub const FIRST_GROUP_OWNER: u32 = 2;
+
+    /// Distinct groups of the processes, sorted by name
+    pub fn group_names(processes_info: &[ProcessInfo]) -> Vec<&str> {
+        let mut names: Vec<&str> = processes_info.iter().map(|p| p.group.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    /// Owner of each page mapped by a process: FIRST_GROUP_OWNER + index of its group in
+    /// group_names, or SHARED_OWNER. Pages without owner are missing
+    pub fn page_owners(processes_info: &[ProcessInfo]) -> HashMap<Pfn, u32> {
+        let names = group_names(processes_info);
+        let mut owners = HashMap::new();
+        for process_info in processes_info {
+            let Ok(group_idx) = names.binary_search(&process_info.group.as_str()) else {
+                continue;
+            };
+            let owner = FIRST_GROUP_OWNER + group_idx as u32;
+            for &pfn in &process_info.pfns {
+                owners
+                    .entry(pfn)
+                    .and_modify(|other| {
+                        if *other != owner {
+                            *other = SHARED_OWNER
+                        }
+                    })
+                    .or_insert(owner);
+            }
+        }
+        owners
+    }
+
+    /// Pages mapped by any process of each group
+    pub fn group_pages(processes_info: &[ProcessInfo]) -> HashMap<String, u64> {
+        let mut pfns: HashMap<&str, HashSet<Pfn>> = HashMap::new();
+        for process_info in processes_info {
+            pfns.entry(&process_info.group)
+                .or_default()
+                .extend(&process_info.pfns);
+        }
+        pfns.into_iter()
+            .map(|(group, pfns)| (group.to_string(), pfns.len() as u64))
+            .collect()
+    }
+
+    /// Segment index and offset in the segment of a PFN
+    pub fn page_position(
+        memory_segments: &[(Pfn, Pfn, Vec<PhysicalPageFlags>)],
+        pfn: Pfn,
+    ) -> Option<(usize, usize)> {
+        memory_segments
+            .iter()
+            .position(|(start, end, _)| pfn >= *start && pfn < *end)
+            .map(|segment_idx| {
+                (
+                    segment_idx,
+                    (pfn.0 - memory_segments[segment_idx].0 .0) as usize,
+                )
+            })
+    }
+
+    /// Values of the pages in `index..end`, segments are in /proc/iomem order like page indices
+    fn slice_pages<T: Clone>(segments: &[Vec<T>], index: u64, end: u64) -> Vec<T> {
+        let mut values = Vec::new();
+        let mut index = index;
+        let mut segment_start = 0;
+        for segment in segments {
+            let segment_end = segment_start + segment.len() as u64;
+            if index >= segment_start && index < segment_end && index < end {
+                let stop = end.min(segment_end);
+                values.extend_from_slice(
+                    &segment[(index - segment_start) as usize..(stop - segment_start) as usize],
+                );
+                index = stop;
+            }
+            segment_start = segment_end;
+        }
+        values
+    }
+
+    /// Layers of each page, moved out of an update sent with overviews, to answer tile requests
+    pub struct PageLayers {
+        flags: Vec<Vec<PhysicalPageFlags>>,
+        map_counts: Option<Vec<Vec<u64>>>,
+        memcgs: Option<Vec<Vec<u64>>>,
+        /// By page index, see page_owners
+        owners: Vec<u32>,
+        /// Sorted page indices of each process
+        processes: HashMap<i32, Vec<u64>>,
+    }
+
+    impl PageLayers {
+        pub fn tile(&self, request: &TileRequestMessage) -> TileMessage {
+            let end = request
+                .index
+                .saturating_add(request.pages.min(MAX_TILE_PAGES));
+            let flags = slice_pages(&self.flags, request.index, end);
+            let end = request.index + flags.len() as u64;
+            let processes = request
+                .pids
+                .iter()
+                .map(|&pid| {
+                    let indices = self.processes.get(&pid).map(Vec::as_slice).unwrap_or(&[]);
+                    let first = indices.partition_point(|&index| index < request.index);
+                    let offsets = indices[first..]
+                        .iter()
+                        .take_while(|&&index| index < end)
+                        .map(|&index| (index - request.index) as u32)
+                        .collect();
+                    (pid, offsets)
+                })
+                .collect();
+            TileMessage {
+                index: request.index,
+                map_counts: self
+                    .map_counts
+                    .as_ref()
+                    .map(|map_counts| slice_pages(map_counts, request.index, end)),
+                memcgs: self
+                    .memcgs
+                    .as_ref()
+                    .map(|memcgs| slice_pages(memcgs, request.index, end)),
+                owners: self
+                    .owners
+                    .get(request.index as usize..end as usize)
+                    .unwrap_or(&[])
+                    .to_vec(),
+                processes,
+                flags,
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize, Clone)]
     pub struct UpdateMessage {
         pub processes_info: Vec<ProcessInfo>,
+        /// Flags are empty when `flags_overview` is sent
         pub memory_segments: Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)>,
+        /// Sent instead of the flags of each page when the map is an overview
+        pub flags_overview: Option<FlagsOverview>,
         pub iomem: Vec<PhysicalMemoryMap>,
+        /// From /proc/kpagecount, one Vec per memory segment. See EXTRA_LAYERS
+        ///
+        /// None when `map_counts_overview` is sent
+        pub map_counts: Option<Vec<Vec<u64>>>,
+        /// Memory cgroup inode from /proc/kpagecgroup, one Vec per memory segment
+        ///
+        /// None when `memcgs_overview` is sent
+        pub memcgs: Option<Vec<Vec<u64>>>,
+        /// Memory cgroup inode -> cgroup path, resolved by the server
+        pub cgroup_paths: HashMap<u64, String>,
+        /// Sent with `flags_overview`, if map counts are read
+        pub map_counts_overview: Option<Overview<u64>>,
+        /// Sent with `flags_overview`, if memcgs are read
+        pub memcgs_overview: Option<Overview<u64>>,
+        /// Owners of the pages (see page_owners), sent with `flags_overview` instead of the
+        /// PFNs of the processes
+        pub owners_overview: Option<Overview<u32>>,
+        /// Pages of each group (see group_pages), sent with `owners_overview`
+        pub group_pages: HashMap<String, u64>,
     }
 
-    #[derive(Serialize, Deserialize)]
-    pub struct ProcessInfo {
-        pub pid: i32,
-        pub exe: PathBuf,
-        pub pfns: HashSet<Pfn>,
+    /// Find runs of changed values between 2 scans of a memory segment
+    ///
+    /// Runs are (segment index, offset of the first page in the segment, new values)
+    fn changed_runs<T: PartialEq + Clone>(
+        segment_idx: usize,
+        old: &[T],
+        new: &[T],
+        runs: &mut Vec<(usize, u64, Vec<T>)>,
+    ) {
+        // (start, end) of changed pages
+        let mut run: Option<(usize, usize)> = None;
+        for idx in 0..new.len() {
+            if old[idx] == new[idx] {
+                continue;
+            }
+            run = match run {
+                Some((start, end)) if idx - end <= RUN_MERGE_GAP => Some((start, idx + 1)),
+                Some((start, end)) => {
+                    runs.push((segment_idx, start as u64, new[start..end].to_vec()));
+                    Some((idx, idx + 1))
+                }
+                None => Some((idx, idx + 1)),
+            };
+        }
+        if let Some((start, end)) = run {
+            runs.push((segment_idx, start as u64, new[start..end].to_vec()));
+        }
     }
-}
 
-#[cfg(unix)]
-pub mod server {
-    use std::collections::HashSet;
-    use std::net::{SocketAddr, TcpListener};
-    use std::thread;
-    use std::time::{Duration, Instant};
+    fn apply_runs<T: Clone>(
+        segments: &mut [&mut Vec<T>],
+        runs: Vec<(usize, u64, Vec<T>)>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (segment_idx, offset, values) in runs {
+            let segment = segments
+                .get_mut(segment_idx)
+                .ok_or_else(|| format!("Delta for unknown segment {}", segment_idx))?;
+            let offset = offset as usize;
+            segment
+                .get_mut(offset..offset + values.len())
+                .ok_or_else(|| format!("Delta out of segment {}", segment_idx))?
+                .clone_from_slice(&values);
+        }
+        Ok(())
+    }
 
-    //use procfs::prelude::*;
-    use procfs::process::{MMapPath, Pfn, Process};
-    use procfs::{KPageFlags, PhysicalMemoryMap, WithCurrentSystemInfo};
-    use procfs_core::PhysicalPageFlags;
+    /// Runs of changed values for optional per-page data, None if unchanged
+    ///
+    /// Both sides must have the same layout, see UpdateMessage::delta
+    fn optional_runs<T: PartialEq + Clone>(
+        old: &Option<Vec<Vec<T>>>,
+        new: &Option<Vec<Vec<T>>>,
+    ) -> Option<Vec<(usize, u64, Vec<T>)>> {
+        let (old, new) = (old.as_ref()?, new.as_ref()?);
+        let mut runs = Vec::new();
+        for (segment_idx, (old, new)) in old.iter().zip(new).enumerate() {
+            changed_runs(segment_idx, old, new, &mut runs);
+        }
+        Some(runs)
+    }
+
+    /// Same number of segments and pages
+    fn same_layout<T>(old: &Option<Vec<Vec<T>>>, new: &Option<Vec<Vec<T>>>) -> bool {
+        match (old, new) {
+            (None, None) => true,
+            (Some(old), Some(new)) => {
+                old.len() == new.len() && old.iter().zip(new).all(|(a, b)| a.len() == b.len())
+            }
+            _ => false,
+        }
+    }
+
+    /// Same level and number of pixels
+    fn same_overview<T>(old: &Option<Overview<T>>, new: &Option<Overview<T>>) -> bool {
+        old.as_ref().map(|o| (o.level, o.ends.len()))
+            == new.as_ref().map(|o| (o.level, o.ends.len()))
+    }
 
-    use crate::{FirstUpdateMessage, Message, ProcessInfo, UpdateMessage};
+    /// Changed pixels of an optional overview, None if not sent
+    fn optional_pixels<T: Copy + Ord>(
+        old: &Option<Overview<T>>,
+        new: &Option<Overview<T>>,
+    ) -> Option<Vec<(u32, PixelValues<T>)>> {
+        Some(old.as_ref()?.changed_pixels(new.as_ref()?))
+    }
+
+    fn apply_optional_pixels<T: Copy + Ord>(
+        overview: &mut Option<Overview<T>>,
+        pixels: Option<Vec<(u32, PixelValues<T>)>>,
+        name: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match pixels {
+            Some(pixels) => overview
+                .as_mut()
+                .ok_or_else(|| format!("Delta for missing {} overview", name))?
+                .apply_pixels(pixels),
+            None => Ok(()),
+        }
+    }
+
+    impl UpdateMessage {
+        /// Compute changes from `self` to `new`
+        ///
+        /// Return None if the memory layout changed (memory hotplug), a full update is required
+        pub fn delta(&self, new: &UpdateMessage) -> Option<DeltaUpdateMessage> {
+            if self.iomem != new.iomem
+                || self.memory_segments.len() != new.memory_segments.len()
+                || self
+                    .memory_segments
+                    .iter()
+                    .zip(&new.memory_segments)
+                    .any(|(old, new)| {
+                        old.0 != new.0 || old.1 != new.1 || old.2.len() != new.2.len()
+                    })
+                || !same_layout(&self.map_counts, &new.map_counts)
+                || !same_layout(&self.memcgs, &new.memcgs)
+                || !same_overview(&self.flags_overview, &new.flags_overview)
+                || !same_overview(&self.map_counts_overview, &new.map_counts_overview)
+                || !same_overview(&self.memcgs_overview, &new.memcgs_overview)
+                || !same_overview(&self.owners_overview, &new.owners_overview)
+            {
+                return None;
+            }
+
+            let mut flag_runs = Vec::new();
+            for (segment_idx, (old, new)) in self
+                .memory_segments
+                .iter()
+                .zip(&new.memory_segments)
+                .enumerate()
+            {
+                changed_runs(segment_idx, &old.2, &new.2, &mut flag_runs);
+            }
+            let overview_pixels = optional_pixels(&self.flags_overview, &new.flags_overview);
+            let map_count_runs = optional_runs(&self.map_counts, &new.map_counts);
+            let memcg_runs = optional_runs(&self.memcgs, &new.memcgs);
+            let map_count_pixels =
+                optional_pixels(&self.map_counts_overview, &new.map_counts_overview);
+            let memcg_pixels = optional_pixels(&self.memcgs_overview, &new.memcgs_overview);
+            let owner_pixels = optional_pixels(&self.owners_overview, &new.owners_overview);
+            let cgroup_paths = if self.cgroup_paths != new.cgroup_paths {
+                Some(new.cgroup_paths.clone())
+            } else {
+                None
+            };
+            let group_pages = if self.group_pages != new.group_pages {
+                Some(new.group_pages.clone())
+            } else {
+                None
+            };
 
-    pub fn get_process_pfns(process: &Process) -> Result<HashSet<Pfn>, Box<dyn std::error::Error>> {
-        let mut pfn_set = HashSet::new();
+            let old_processes: HashMap<i32, &ProcessInfo> =
+                self.processes_info.iter().map(|p| (p.pid, p)).collect();
+            let new_processes_by_pid: HashMap<i32, &ProcessInfo> =
+                new.processes_info.iter().map(|p| (p.pid, p)).collect();
 
-        let page_size = procfs::page_size();
+            let mut new_processes = Vec::new();
+            let mut changed_processes = Vec::new();
+            // pid was reused if exe changed, a process changing group is also sent again
+            let mut exited_processes: Vec<i32> = self
+                .processes_info
+                .iter()
+                .filter(|old| {
+                    !new_processes_by_pid
+                        .get(&old.pid)
+                        .is_some_and(|new| old.same_process(new))
+                })
+                .map(|p| p.pid)
+                .collect();
+            exited_processes.sort();
 
-        let mut pagemap = process.pagemap()?;
-        let memmap = process.maps()?;
+            for process_info in &new.processes_info {
+                match old_processes.get(&process_info.pid) {
+                    Some(old) if old.same_process(process_info) => {
+                        let added_pfns: Vec<Pfn> =
+                            process_info.pfns.difference(&old.pfns).copied().collect();
+                        let removed_pfns: Vec<Pfn> =
+                            old.pfns.difference(&process_info.pfns).copied().collect();
+                        let pixels = if process_info.pixels != old.pixels {
+                            Some(process_info.pixels.clone())
+                        } else {
+                            None
+                        };
+                        if !added_pfns.is_empty() || !removed_pfns.is_empty() || pixels.is_some() {
+                            changed_processes.push(ProcessDelta {
+                                pid: process_info.pid,
+                                added_pfns,
+                                removed_pfns,
+                                pixels,
+                            });
+                        }
+                    }
+                    _ => new_processes.push(process_info.clone()),
+                }
+            }
 
-        for memory_map in memmap {
-            let mem_start = memory_map.address.0;
-            let mem_end = memory_map.address.1;
+            Some(DeltaUpdateMessage {
+                flag_runs,
+                overview_pixels,
+                map_count_runs,
+                memcg_runs,
+                map_count_pixels,
+                memcg_pixels,
+                owner_pixels,
+                cgroup_paths,
+                group_pages,
+                new_processes,
+                changed_processes,
+                exited_processes,
+            })
+        }
 
-            let page_start = (mem_start / page_size) as usize;
-            let page_end = (mem_end / page_size) as usize;
+        /// Apply changes sent by the server
+        pub fn apply_delta(
+            &mut self,
+            delta: DeltaUpdateMessage,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let mut segments: Vec<&mut Vec<PhysicalPageFlags>> = self
+                .memory_segments
+                .iter_mut()
+                .map(|segment| &mut segment.2)
+                .collect();
+            apply_runs(&mut segments, delta.flag_runs)?;
+            apply_optional_pixels(&mut self.flags_overview, delta.overview_pixels, "flags")?;
+            apply_optional_pixels(
+                &mut self.map_counts_overview,
+                delta.map_count_pixels,
+                "map counts",
+            )?;
+            apply_optional_pixels(&mut self.memcgs_overview, delta.memcg_pixels, "memcgs")?;
+            apply_optional_pixels(&mut self.owners_overview, delta.owner_pixels, "owners")?;
 
-            // can't scan Vsyscall, so skip it
-            if memory_map.pathname == MMapPath::Vsyscall {
-                continue;
+            if let Some(runs) = delta.map_count_runs {
+                let map_counts = self
+                    .map_counts
+                    .as_mut()
+                    .ok_or("Delta for missing map counts")?;
+                apply_runs(&mut map_counts.iter_mut().collect::<Vec<_>>(), runs)?;
+            }
+            if let Some(runs) = delta.memcg_runs {
+                let memcgs = self.memcgs.as_mut().ok_or("Delta for missing memcgs")?;
+                apply_runs(&mut memcgs.iter_mut().collect::<Vec<_>>(), runs)?;
+            }
+            if let Some(cgroup_paths) = delta.cgroup_paths {
+                self.cgroup_paths = cgroup_paths;
             }
+            if let Some(group_pages) = delta.group_pages {
+                self.group_pages = group_pages;
+            }
+
+            let exited: HashSet<i32> = delta.exited_processes.into_iter().collect();
+            self.processes_info.retain(|p| !exited.contains(&p.pid));
 
-            for page_info in pagemap.get_range_info(page_start..page_end)? {
-                match page_info {
-                    procfs::process::PageInfo::MemoryPage(memory_page) => {
-                        let pfn = memory_page.get_page_frame_number();
-                        pfn_set.insert(pfn);
+            let mut changed: HashMap<i32, ProcessDelta> = delta
+                .changed_processes
+                .into_iter()
+                .map(|p| (p.pid, p))
+                .collect();
+            for process_info in self.processes_info.iter_mut() {
+                if let Some(process_delta) = changed.remove(&process_info.pid) {
+                    for pfn in &process_delta.removed_pfns {
+                        process_info.pfns.remove(pfn);
+                    }
+                    process_info.pfns.extend(process_delta.added_pfns);
+                    if let Some(pixels) = process_delta.pixels {
+                        process_info.pixels = pixels;
                     }
-                    procfs::process::PageInfo::SwapPage(_) => (),
                 }
             }
+            if let Some(pid) = changed.keys().next() {
+                return Err(format!("Delta for unknown process {}", pid).into());
+            }
+
+            self.processes_info.extend(delta.new_processes);
+
+            Ok(())
+        }
+
+        pub fn has_map_counts(&self) -> bool {
+            self.map_counts.is_some() || self.map_counts_overview.is_some()
         }
 
-        Ok(pfn_set)
+        pub fn has_memcgs(&self) -> bool {
+            self.memcgs.is_some() || self.memcgs_overview.is_some()
+        }
+
+        /// Replace the layers of each page with overviews of 4^level pages per pixel, and the
+        /// PFNs of the processes with their pages per pixel
+        ///
+        /// The layers moved out answer tile requests
+        pub fn split_overview(&mut self, page_size: u64, level: u8) -> PageLayers {
+            let flags: Vec<Vec<PhysicalPageFlags>> = self
+                .memory_segments
+                .iter_mut()
+                .map(|segment| std::mem::take(&mut segment.2))
+                .collect();
+            let pages = flags.iter().map(|flags| flags.len()).sum();
+
+            let mut owners = vec![NO_OWNER; pages];
+            for (pfn, owner) in page_owners(&self.processes_info) {
+                if let Some(index) = snap::pfn_to_index(&self.iomem, page_size, pfn) {
+                    if let Some(page_owner) = owners.get_mut(index as usize) {
+                        *page_owner = owner;
+                    }
+                }
+            }
+            self.group_pages = group_pages(&self.processes_info);
+
+            let mut processes = HashMap::new();
+            for process_info in self.processes_info.iter_mut() {
+                let mut indices: Vec<u64> = std::mem::take(&mut process_info.pfns)
+                    .into_iter()
+                    .filter_map(|pfn| snap::pfn_to_index(&self.iomem, page_size, pfn))
+                    .collect();
+                indices.sort_unstable();
+                process_info.pixels.clear();
+                for pixel in indices.iter().map(|index| (index >> (2 * level)) as u32) {
+                    match process_info.pixels.last_mut() {
+                        Some((last, pages)) if *last == pixel => *pages += 1,
+                        _ => process_info.pixels.push((pixel, 1)),
+                    }
+                }
+                processes.insert(process_info.pid, indices);
+            }
+
+            self.flags_overview = Some(Overview::new(&flags, level));
+            self.map_counts_overview = self
+                .map_counts
+                .as_ref()
+                .map(|map_counts| Overview::new(map_counts, level));
+            self.memcgs_overview = self
+                .memcgs
+                .as_ref()
+                .map(|memcgs| Overview::new(memcgs, level));
+            self.owners_overview = Some(Overview::new(std::slice::from_ref(&owners), level));
+
+            PageLayers {
+                flags,
+                map_counts: self.map_counts.take(),
+                memcgs: self.memcgs.take(),
+                owners,
+                processes,
+            }
+        }
     }
 
-    pub fn get_process_info(process: &Process) -> Result<ProcessInfo, Box<dyn std::error::Error>> {
-        let pfns = get_process_pfns(process)?;
-        let exe = process.exe()?;
-        let pid = process.pid;
+    /// Changes since the previous update
+    #[derive(Serialize, Deserialize)]
+    pub struct DeltaUpdateMessage {
+        /// Runs of changed pages: (segment index, offset of the first page in the segment, new flags)
+        pub flag_runs: Vec<(usize, u64, Vec<PhysicalPageFlags>)>,
+        /// Changed pixels of the overview, if sent instead of flag_runs
+        pub overview_pixels: Option<Vec<(u32, PixelValues<PhysicalPageFlags>)>>,
+        /// Same as flag_runs, if map counts are sent
+        pub map_count_runs: Option<Vec<(usize, u64, Vec<u64>)>>,
+        /// Same as flag_runs, if memcgs are sent
+        pub memcg_runs: Option<Vec<(usize, u64, Vec<u64>)>>,
+        /// Same as overview_pixels, for each overview
+        pub map_count_pixels: Option<Vec<(u32, PixelValues<u64>)>>,
+        pub memcg_pixels: Option<Vec<(u32, PixelValues<u64>)>>,
+        pub owner_pixels: Option<Vec<(u32, PixelValues<u32>)>>,
+        /// Only sent if changed
+        pub cgroup_paths: Option<HashMap<u64, String>>,
+        /// Only sent if changed
+        pub group_pages: Option<HashMap<String, u64>>,
+        /// Processes that didn't exist in the previous update
+        pub new_processes: Vec<ProcessInfo>,
+        pub changed_processes: Vec<ProcessDelta>,
+        pub exited_processes: Vec<i32>,
+    }
 
-        Ok(ProcessInfo { pid, exe, pfns })
+    #[derive(Serialize, Deserialize)]
+    pub struct ProcessDelta {
+        pub pid: i32,
+        pub added_pfns: Vec<Pfn>,
+        pub removed_pfns: Vec<Pfn>,
+        /// New pages per pixel, if changed
+        pub pixels: Option<Vec<(u32, u32)>>,
     }
 
-    pub fn get_all_processes_info() -> Vec<ProcessInfo> {
-        use rayon::prelude::*;
-        let all_processes: Vec<Process> = procfs::process::all_processes()
-            .unwrap()
-            .filter_map(|p| p.ok())
-            .collect();
-        let processes_info: Vec<ProcessInfo> = all_processes
-            .par_iter()
-            .filter_map(|p| get_process_info(p).ok())
-            .collect();
+    #[derive(Serialize, Deserialize, Clone)]
+    pub struct ProcessInfo {
+        pub pid: i32,
+        pub exe: PathBuf,
+        /// Arguments joined with spaces
+        pub cmdline: String,
+        /// Computed by the server, see GROUP_BY
+        pub group: String,
+        /// Empty when the update has overviews
+        pub pfns: HashSet<Pfn>,
+        /// (overview pixel, pages), sorted by pixel, sent instead of `pfns` with overviews
+        pub pixels: Vec<(u32, u32)>,
+    }
 
-        processes_info
+    impl ProcessInfo {
+        /// Mapped pages
+        pub fn pages(&self) -> u64 {
+            if self.pixels.is_empty() {
+                self.pfns.len() as u64
+            } else {
+                self.pixels.iter().map(|&(_, pages)| pages as u64).sum()
+            }
+        }
+
+        /// Same pid, exe and group, only PFNs can differ
+        fn same_process(&self, other: &ProcessInfo) -> bool {
+            self.pid == other.pid && self.exe == other.exe && self.group == other.group
+        }
     }
 
-    pub fn get_memory_zones_flags(
-        iomem: &[PhysicalMemoryMap],
-        kpageflags: &mut KPageFlags,
-    ) -> Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)> {
-        let segments: Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)> = iomem
-            .iter()
-            .map(|segment| {
-                let (start_pfn, end_pfn) = segment.get_range().get();
-                let flags: Vec<PhysicalPageFlags> =
-                    kpageflags.get_range_info(start_pfn, end_pfn).unwrap();
+    #[cfg(test)]
+    mod tests {
+        use super::*;
 
-                (start_pfn, end_pfn, flags)
-            })
-            .collect();
+        fn segment(start: u64, pages: u64) -> (Pfn, Pfn, Vec<PhysicalPageFlags>) {
+            (
+                Pfn(start),
+                Pfn(start + pages),
+                vec![PhysicalPageFlags::empty(); pages as usize],
+            )
+        }
 
-        segments
+        fn process(pid: i32, exe: &str, pfns: &[u64]) -> ProcessInfo {
+            ProcessInfo {
+                pid,
+                exe: PathBuf::from(exe),
+                cmdline: exe.to_string(),
+                group: exe.to_string(),
+                pfns: pfns.iter().map(|pfn| Pfn(*pfn)).collect(),
+                pixels: Vec::new(),
+            }
+        }
+
+        /// 2 RAM segments of 64 and 32 pages, with map counts and memcgs
+        fn update() -> UpdateMessage {
+            let memory_segments = vec![segment(0x100, 64), segment(0x1000, 32)];
+            UpdateMessage {
+                processes_info: vec![
+                    process(1, "/sbin/init", &[0x100, 0x101]),
+                    process(2, "/usr/bin/bash", &[0x102]),
+                    process(3, "/usr/bin/sleep", &[0x103]),
+                    process(4, "/usr/bin/cat", &[0x104]),
+                ],
+                iomem: memory_segments
+                    .iter()
+                    .map(|(start, end, _)| PhysicalMemoryMap {
+                        address: (start.0 * 4096, end.0 * 4096 - 1),
+                        name: "System RAM".to_string(),
+                    })
+                    .collect(),
+                map_counts: Some(vec![vec![1; 64], vec![0; 32]]),
+                memcgs: Some(vec![vec![10; 64], vec![20; 32]]),
+                memory_segments,
+                flags_overview: None,
+                cgroup_paths: HashMap::from([(10, "/".to_string())]),
+                map_counts_overview: None,
+                memcgs_overview: None,
+                owners_overview: None,
+                group_pages: HashMap::new(),
+            }
+        }
+
+        /// Processes sorted by pid, with sorted PFNs and their pixels
+        #[allow(clippy::type_complexity)]
+        fn processes(
+            update: &UpdateMessage,
+        ) -> Vec<(i32, PathBuf, String, Vec<u64>, Vec<(u32, u32)>)> {
+            let mut processes: Vec<_> = update
+                .processes_info
+                .iter()
+                .map(|p| {
+                    let mut pfns: Vec<u64> = p.pfns.iter().map(|pfn| pfn.0).collect();
+                    pfns.sort();
+                    (
+                        p.pid,
+                        p.exe.clone(),
+                        p.group.clone(),
+                        pfns,
+                        p.pixels.clone(),
+                    )
+                })
+                .collect();
+            processes.sort();
+            processes
+        }
+
+        fn assert_same(a: &UpdateMessage, b: &UpdateMessage) {
+            assert_eq!(a.memory_segments, b.memory_segments);
+            assert!(a.flags_overview == b.flags_overview);
+            assert_eq!(a.iomem, b.iomem);
+            assert_eq!(a.map_counts, b.map_counts);
+            assert_eq!(a.memcgs, b.memcgs);
+            assert_eq!(a.cgroup_paths, b.cgroup_paths);
+            assert!(a.map_counts_overview == b.map_counts_overview);
+            assert!(a.memcgs_overview == b.memcgs_overview);
+            assert!(a.owners_overview == b.owners_overview);
+            assert_eq!(a.group_pages, b.group_pages);
+            assert_eq!(processes(a), processes(b));
+        }
+
+        fn flags(bits: &[u64]) -> Vec<PhysicalPageFlags> {
+            bits.iter()
+                .map(|bits| PhysicalPageFlags::from_bits_retain(*bits))
+                .collect()
+        }
+
+        #[test]
+        fn runs() {
+            let old = vec![0u64; 40];
+            let mut new = old.clone();
+            new[3] = 1;
+            new[5] = 2;
+            new[30] = 3;
+            new[39] = 4;
+
+            let mut runs = Vec::new();
+            changed_runs(1, &old, &new, &mut runs);
+            // pages 3 and 5 are merged, the gap to 30 is too large
+            assert_eq!(
+                runs,
+                [(1, 3, vec![1, 0, 2]), (1, 30, new[30..40].to_vec()),]
+            );
+
+            let mut segments = [vec![9u64; 10], old.clone()];
+            apply_runs(&mut segments.iter_mut().collect::<Vec<_>>(), runs).unwrap();
+            assert_eq!(segments[0], [9; 10]);
+            assert_eq!(segments[1], new);
+
+            let mut runs = Vec::new();
+            changed_runs(0, &old, &old, &mut runs);
+            assert!(runs.is_empty());
+        }
+
+        #[test]
+        fn invalid_runs() {
+            let mut segment = vec![0u64; 10];
+            assert!(apply_runs(&mut [&mut segment], vec![(1, 0, vec![1])]).is_err());
+            assert!(apply_runs(&mut [&mut segment], vec![(0, 8, vec![1, 2, 3])]).is_err());
+            assert!(apply_runs(&mut [&mut segment], vec![(0, 7, vec![1, 2, 3])]).is_ok());
+            assert_eq!(segment, [0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+        }
+
+        #[test]
+        fn delta() {
+            let old = update();
+            let mut new = update();
+            new.memory_segments[0].2[0] = PhysicalPageFlags::LRU;
+            new.memory_segments[1].2[31] = PhysicalPageFlags::BUDDY;
+            new.map_counts.as_mut().unwrap()[0][63] = 2;
+            new.memcgs.as_mut().unwrap()[1][0] = 30;
+            new.cgroup_paths.insert(30, "/system.slice".to_string());
+            // unchanged, changed PFNs, exited, pid reused, new process
+            new.processes_info = vec![
+                process(1, "/sbin/init", &[0x100, 0x101]),
+                process(2, "/usr/bin/bash", &[0x102, 0x1000]),
+                process(4, "/usr/bin/vim", &[0x104]),
+                process(5, "/usr/bin/top", &[0x105]),
+            ];
+
+            let delta = old.delta(&new).unwrap();
+            assert_eq!(delta.flag_runs.len(), 2);
+            assert!(delta.overview_pixels.is_none());
+            assert_eq!(delta.map_count_runs.as_ref().unwrap(), &[(0, 63, vec![2])]);
+            assert_eq!(delta.memcg_runs.as_ref().unwrap(), &[(1, 0, vec![30])]);
+            assert_eq!(delta.exited_processes, [3, 4]);
+            assert_eq!(delta.changed_processes.len(), 1);
+            assert_eq!(delta.changed_processes[0].pid, 2);
+            assert_eq!(delta.changed_processes[0].added_pfns, [Pfn(0x1000)]);
+            let mut new_pids: Vec<i32> = delta.new_processes.iter().map(|p| p.pid).collect();
+            new_pids.sort();
+            assert_eq!(new_pids, [4, 5]);
+
+            // through the wire
+            let mut buf = Vec::new();
+            Message::Delta(Box::new(delta))
+                .send(&mut buf, Compression::None)
+                .unwrap();
+            let Message::Delta(delta) = Message::recv(&mut &buf[..]).unwrap() else {
+                panic!("expected a delta");
+            };
+
+            let mut applied = old.clone();
+            applied.apply_delta(*delta).unwrap();
+            assert_same(&applied, &new);
+
+            let delta = new.delta(&new).unwrap();
+            assert!(delta.flag_runs.is_empty());
+            assert!(delta.cgroup_paths.is_none());
+            assert!(delta.new_processes.is_empty() && delta.exited_processes.is_empty());
+        }
+
+        #[test]
+        fn layout_change() {
+            let old = update();
+
+            // memory hotplug
+            let mut new = update();
+            new.memory_segments.push(segment(0x2000, 8));
+            assert!(old.delta(&new).is_none());
+
+            let mut new = update();
+            new.iomem[1].address.1 += 4096;
+            assert!(old.delta(&new).is_none());
+
+            // a layer can't be read anymore
+            let mut new = update();
+            new.memcgs = None;
+            assert!(old.delta(&new).is_none());
+            assert!(new.delta(&old).is_none());
+        }
+
+        #[test]
+        fn invalid_delta() {
+            let old = update();
+            let mut new = update();
+            new.processes_info[0].pfns.insert(Pfn(0x110));
+            let delta = old.delta(&new).unwrap();
+
+            // the client missed an update: process 1 is unknown
+            let mut state = update();
+            state.processes_info.remove(0);
+            assert!(state.apply_delta(delta).is_err());
+
+            let mut new = update();
+            new.memcgs.as_mut().unwrap()[0][0] = 30;
+            let delta = old.delta(&new).unwrap();
+            let mut state = update();
+            state.memcgs = None;
+            assert!(state.apply_delta(delta).is_err());
+        }
+
+        #[test]
+        fn overview() {
+            let lru = PhysicalPageFlags::LRU.bits();
+            let buddy = PhysicalPageFlags::BUDDY.bits();
+            // 4 pages per pixel, the segments are joined, the last pixel is partial
+            let segments = vec![
+                flags(&[lru, 0, lru, buddy, buddy, buddy, buddy, buddy, 0, 0]),
+                flags(&[lru, lru, 0]),
+            ];
+            let overview = FlagsOverview::new(&segments, 1);
+            assert_eq!(overview.pixels(), 4);
+            assert_eq!(
+                overview.pixel(0),
+                [
+                    (PhysicalPageFlags::empty(), 1),
+                    (PhysicalPageFlags::LRU, 2),
+                    (PhysicalPageFlags::BUDDY, 1)
+                ]
+            );
+            assert_eq!(overview.pixel(1), [(PhysicalPageFlags::BUDDY, 4)]);
+            assert_eq!(
+                overview.pixel(2),
+                [(PhysicalPageFlags::empty(), 2), (PhysicalPageFlags::LRU, 2)]
+            );
+            assert_eq!(overview.pixel(3), [(PhysicalPageFlags::empty(), 1)]);
+
+            let mut changed_segments = segments.clone();
+            changed_segments[0][5] = PhysicalPageFlags::LRU;
+            changed_segments[1][2] = PhysicalPageFlags::BUDDY;
+            let new = FlagsOverview::new(&changed_segments, 1);
+            let changed = overview.changed_pixels(&new);
+            assert_eq!(
+                changed.iter().map(|(idx, _)| *idx).collect::<Vec<_>>(),
+                [1, 3]
+            );
+
+            let mut applied = overview.clone();
+            applied.apply_pixels(changed).unwrap();
+            assert!(applied == new);
+
+            assert!(overview.changed_pixels(&overview).is_empty());
+            let mut applied = overview.clone();
+            assert!(applied.apply_pixels(vec![(4, Vec::new())]).is_err());
+        }
+
+        #[test]
+        fn overview_delta() {
+            let mut old = update();
+            let segments: Vec<_> = old
+                .memory_segments
+                .iter_mut()
+                .map(|segment| std::mem::take(&mut segment.2))
+                .collect();
+            old.flags_overview = Some(FlagsOverview::new(&segments, 2));
+
+            let mut new = old.clone();
+            let mut changed_segments = segments.clone();
+            changed_segments[1][0] = PhysicalPageFlags::SLAB;
+            new.flags_overview = Some(FlagsOverview::new(&changed_segments, 2));
+
+            let delta = old.delta(&new).unwrap();
+            assert!(delta.flag_runs.is_empty());
+            assert_eq!(delta.overview_pixels.as_ref().unwrap().len(), 1);
+            let mut applied = old.clone();
+            applied.apply_delta(delta).unwrap();
+            assert_same(&applied, &new);
+
+            // a different level is a new layout
+            new.flags_overview = Some(FlagsOverview::new(&changed_segments, 1));
+            assert!(old.delta(&new).is_none());
+        }
+
+        #[test]
+        fn owners() {
+            let mut update = update();
+            // 2 groups share page 0x100, bash and sleep are in the same group
+            update.processes_info[1].pfns.insert(Pfn(0x100));
+            update.processes_info[2].group = "/usr/bin/bash".to_string();
+            update.processes_info[2].pfns.insert(Pfn(0x102));
+            let processes_info = &update.processes_info;
+
+            assert_eq!(
+                group_names(processes_info),
+                ["/sbin/init", "/usr/bin/bash", "/usr/bin/cat"]
+            );
+            let owners = page_owners(processes_info);
+            assert_eq!(owners.len(), 5);
+            assert_eq!(owners[&Pfn(0x100)], SHARED_OWNER);
+            assert_eq!(owners[&Pfn(0x101)], FIRST_GROUP_OWNER);
+            assert_eq!(owners[&Pfn(0x102)], FIRST_GROUP_OWNER + 1);
+            assert_eq!(owners[&Pfn(0x103)], FIRST_GROUP_OWNER + 1);
+            assert_eq!(owners[&Pfn(0x104)], FIRST_GROUP_OWNER + 2);
+            assert_eq!(
+                group_pages(processes_info),
+                HashMap::from([
+                    ("/sbin/init".to_string(), 2),
+                    ("/usr/bin/bash".to_string(), 3),
+                    ("/usr/bin/cat".to_string(), 1),
+                ])
+            );
+        }
+
+        #[test]
+        fn split_overview() {
+            let mut update = update();
+            update.map_counts.as_mut().unwrap()[1][1] = 3;
+            update.processes_info[0].pfns.insert(Pfn(0x1001));
+            let pages: Vec<u64> = update.processes_info.iter().map(|p| p.pages()).collect();
+            let full = update.clone();
+
+            // 4 pages per pixel
+            let layers = update.split_overview(4096, 1);
+            assert!(update
+                .memory_segments
+                .iter()
+                .all(|segment| segment.2.is_empty()));
+            assert!(update.map_counts.is_none() && update.memcgs.is_none());
+            assert!(update.has_map_counts() && update.has_memcgs());
+            for pixels in [
+                update.flags_overview.as_ref().unwrap().pixels(),
+                update.map_counts_overview.as_ref().unwrap().pixels(),
+                update.memcgs_overview.as_ref().unwrap().pixels(),
+                update.owners_overview.as_ref().unwrap().pixels(),
+            ] {
+                assert_eq!(pixels, 24);
+            }
+            assert_eq!(
+                update.memcgs_overview.as_ref().unwrap().pixel(16),
+                [(20, 4)]
+            );
+            assert_eq!(update.group_pages, group_pages(&full.processes_info));
+            assert!(update.processes_info.iter().all(|p| p.pfns.is_empty()));
+            assert_eq!(update.processes_info[0].pixels, [(0, 2), (16, 1)]);
+            let split_pages: Vec<u64> = update.processes_info.iter().map(|p| p.pages()).collect();
+            assert_eq!(split_pages, pages);
+
+            // across the 2 segments, the last one is shorter than requested
+            let tile = layers.tile(&TileRequestMessage {
+                index: 62,
+                pages: 64,
+                pids: vec![1, 6],
+            });
+            assert_eq!(tile.index, 62);
+            assert_eq!(tile.flags.len(), 34);
+            assert_eq!(tile.map_counts.as_ref().unwrap()[..4], [1, 1, 0, 3]);
+            assert_eq!(tile.memcgs.as_ref().unwrap()[..4], [10, 10, 20, 20]);
+            assert_eq!(tile.owners.len(), 34);
+            assert_eq!(tile.owners[3], FIRST_GROUP_OWNER);
+            assert_eq!(tile.processes, [(1, vec![3]), (6, Vec::new())]);
+
+            // a changed process is sent with its pixels
+            let mut new = full.clone();
+            new.processes_info[1].pfns.insert(Pfn(0x1002));
+            let new_layers = new.split_overview(4096, 1);
+            let delta = update.delta(&new).unwrap();
+            assert_eq!(delta.owner_pixels.as_ref().unwrap().len(), 1);
+            assert!(delta.map_count_pixels.as_ref().unwrap().is_empty());
+            assert_eq!(delta.group_pages.as_ref().unwrap()["/usr/bin/bash"], 2);
+            assert_eq!(delta.changed_processes.len(), 1);
+            assert_eq!(
+                delta.changed_processes[0].pixels.as_deref(),
+                Some(&[(0, 1), (16, 1)][..])
+            );
+            let mut applied = update.clone();
+            applied.apply_delta(delta).unwrap();
+            assert_same(&applied, &new);
+            assert_eq!(
+                new_layers
+                    .tile(&TileRequestMessage {
+                        index: 64,
+                        pages: 4,
+                        pids: vec![2],
+                    })
+                    .processes,
+                [(2, vec![2])]
+            );
+        }
+
+        #[test]
+        fn frames() {
+            for compression in [Compression::None, Compression::Deflate, Compression::Zstd] {
+                let mut buf = Vec::new();
+                let size = Message::Update(Arc::new(update()))
+                    .send(&mut buf, compression)
+                    .unwrap();
+                assert_eq!(buf.len(), FRAME_HEADER_SIZE + size);
+                assert_eq!(buf[..8], (size as u64).to_le_bytes());
+                assert_eq!(buf[8..10], PROTOCOL_VERSION.to_le_bytes());
+                assert_eq!(buf[10], compression.to_u8());
+
+                let Message::Update(received) = Message::recv(&mut &buf[..]).unwrap() else {
+                    panic!("expected an update");
+                };
+                assert_same(&received, &update());
+            }
+        }
+
+        #[test]
+        fn invalid_frames() {
+            let mut buf = Vec::new();
+            Message::Finish.send(&mut buf, Compression::Zstd).unwrap();
+
+            let mut version = buf.clone();
+            version[8..10].copy_from_slice(&(PROTOCOL_VERSION - 1).to_le_bytes());
+            let e = Message::recv(&mut &version[..]).err().unwrap();
+            assert!(
+                e.to_string().starts_with("Protocol version mismatch"),
+                "{e}"
+            );
+
+            let mut compression = buf.clone();
+            compression[10] = 3;
+            let e = Message::recv(&mut &compression[..]).err().unwrap();
+            assert_eq!(e.to_string(), "Unknown compression 3");
+
+            assert!(Message::recv(&mut &buf[..buf.len() - 1]).is_err());
+            assert!(Message::recv(&mut &buf[..4]).is_err());
+        }
+
+        #[test]
+        fn size_cap() {
+            let max = max_message_size() * 1024 * 1024;
+
+            // the header is enough to refuse the frame
+            let mut header = Vec::new();
+            header.extend((max + 1).to_le_bytes());
+            header.extend(PROTOCOL_VERSION.to_le_bytes());
+            header.push(Compression::None.to_u8());
+            let e = Message::recv(&mut &header[..]).err().unwrap();
+            assert!(e.to_string().starts_with("Message is too big"), "{e}");
+            let e = MessageReader::default()
+                .poll(&mut &header[..])
+                .err()
+                .unwrap();
+            assert!(e.to_string().starts_with("Message is too big"), "{e}");
+
+            // small when compressed
+            let payload = zstd::encode_all(&vec![0u8; max as usize + 1][..], 3).unwrap();
+            let mut frame = Vec::new();
+            frame.extend((payload.len() as u64).to_le_bytes());
+            frame.extend(PROTOCOL_VERSION.to_le_bytes());
+            frame.push(Compression::Zstd.to_u8());
+            frame.extend(payload);
+            let e = Message::recv(&mut &frame[..]).err().unwrap();
+            assert!(
+                e.to_string().starts_with("Decompressed message is too big"),
+                "{e}"
+            );
+        }
+
+        /// Returns a few bytes per read, then WouldBlock
+        struct Trickle<'a> {
+            data: &'a [u8],
+            chunk: usize,
+        }
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.data.is_empty() {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                let len = self.chunk.min(self.data.len()).min(buf.len());
+                buf[..len].copy_from_slice(&self.data[..len]);
+                self.data = &self.data[len..];
+                Ok(len)
+            }
+        }
+
+        #[test]
+        fn reader() {
+            let mut buf = Vec::new();
+            Message::Error("first".to_string())
+                .send(&mut buf, Compression::Deflate)
+                .unwrap();
+            Message::KeyframeRequest
+                .send(&mut buf, Compression::None)
+                .unwrap();
+
+            let mut socket = Trickle {
+                data: &buf,
+                chunk: 5,
+            };
+            let mut reader = MessageReader::default();
+            let mut messages = Vec::new();
+            for _ in 0..buf.len() {
+                if let Some(message) = reader.poll(&mut socket).unwrap() {
+                    messages.push(message);
+                }
+            }
+            assert!(matches!(
+                &messages[..],
+                [Message::Error(e), Message::KeyframeRequest] if e == "first"
+            ));
+            assert!(reader.poll(&mut socket).unwrap().is_none());
+
+            let e = reader.poll(&mut &[][..]).err().unwrap();
+            assert_eq!(e.to_string(), "Connection closed");
+        }
+    }
+}
+
+/// TLS, plain TCP and Unix domain sockets
+pub mod transport {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        path::PathBuf,
+        str::FromStr,
+        sync::Arc,
+        time::Duration,
+    };
+
+    #[cfg(unix)]
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    use rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{ring::default_provider, CryptoProvider},
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+        ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection,
+        SignatureScheme, StreamOwned,
+    };
+
+    /// Read timeout of sockets, messages are read with a MessageReader
+    pub const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    pub trait Stream: Read + Write + Send {}
+    impl<T: Read + Write + Send> Stream for T {}
+
+    /// `host:port` (TLS), `tcp://host:port` (plain TCP) or `unix:/path` (Unix domain socket)
+    #[derive(Debug, Clone)]
+    pub enum Endpoint {
+        Tls(SocketAddr),
+        /// Only for loopback addresses, for `ssh -L` forwarding
+        Tcp(SocketAddr),
+        Unix(PathBuf),
+    }
+
+    impl FromStr for Endpoint {
+        type Err = Box<dyn std::error::Error>;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            if let Some(path) = s.strip_prefix("unix:") {
+                if cfg!(unix) {
+                    Ok(Endpoint::Unix(path.into()))
+                } else {
+                    Err("Unix domain sockets are not supported on this platform".into())
+                }
+            } else if let Some(addr) = s.strip_prefix("tcp://") {
+                let addr: SocketAddr = addr.parse()?;
+                if !addr.ip().is_loopback() {
+                    return Err(format!(
+                        "Plain TCP is only allowed on loopback addresses, use TLS instead of {}",
+                        s
+                    )
+                    .into());
+                }
+                Ok(Endpoint::Tcp(addr))
+            } else {
+                Ok(Endpoint::Tls(
+                    s.strip_prefix("tls://").unwrap_or(s).parse()?,
+                ))
+            }
+        }
+    }
+
+    /// SHA-256 of the DER certificate, as hex pairs separated by ':'
+    pub fn fingerprint(cert: &CertificateDer) -> String {
+        ring::digest::digest(&ring::digest::SHA256, cert)
+            .as_ref()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(":")
+    }
+
+    /// Constant time comparison, to not leak the token length of the common prefix
+    pub fn token_eq(a: &str, b: &str) -> bool {
+        a.len() == b.len()
+            && a.bytes()
+                .zip(b.bytes())
+                .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+                == 0
+    }
+
+    /// Random token, used when env variable AUTH_TOKEN is not set on the server
+    pub fn generate_token() -> String {
+        use rand::Rng;
+        let bytes: [u8; 16] = rand::rng().random();
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Certificate from env variables TLS_CERT and TLS_KEY (PEM files),
+    /// or a generated self-signed certificate
+    pub fn server_tls_config() -> Result<Arc<ServerConfig>, Box<dyn std::error::Error>> {
+        let (certs, key): (Vec<CertificateDer>, PrivateKeyDer) =
+            match (std::env::var("TLS_CERT"), std::env::var("TLS_KEY")) {
+                (Ok(cert), Ok(key)) => (
+                    CertificateDer::pem_file_iter(&cert)?.collect::<Result<_, _>>()?,
+                    PrivateKeyDer::from_pem_file(&key)?,
+                ),
+                (Err(_), Err(_)) => {
+                    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")?;
+                    let certified_key =
+                        rcgen::generate_simple_self_signed(vec![hostname.trim().to_string()])?;
+                    (
+                        vec![certified_key.cert.der().clone()],
+                        PrivateKeyDer::try_from(certified_key.key_pair.serialize_der())?,
+                    )
+                }
+                _ => return Err("TLS_CERT and TLS_KEY must be set together".into()),
+            };
+        println!(
+            "TLS certificate fingerprint (SHA-256): {}",
+            fingerprint(certs.first().ok_or("No certificate")?)
+        );
+
+        let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        Ok(Arc::new(config))
+    }
+
+    /// Accept only the server certificate with the expected fingerprint
+    ///
+    /// The certificate is usually self-signed, so there is no CA to check
+    #[derive(Debug)]
+    struct PinnedCertVerifier {
+        fingerprint: String,
+        provider: Arc<CryptoProvider>,
+    }
+
+    impl ServerCertVerifier for PinnedCertVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let actual = fingerprint(end_entity);
+            if token_eq(&actual, &self.fingerprint) {
+                Ok(ServerCertVerified::assertion())
+            } else {
+                Err(rustls::Error::General(format!(
+                    "Server certificate fingerprint mismatch: expected {}, got {}",
+                    self.fingerprint, actual
+                )))
+            }
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls12_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            rustls::crypto::verify_tls13_signature(
+                message,
+                cert,
+                dss,
+                &self.provider.signature_verification_algorithms,
+            )
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.provider
+                .signature_verification_algorithms
+                .supported_schemes()
+        }
+    }
+
+    /// Server certificate is pinned with env variable TLS_FINGERPRINT,
+    /// or TLS_CERT (same PEM file as the server)
+    pub fn client_tls_config() -> Result<Arc<ClientConfig>, Box<dyn std::error::Error>> {
+        let expected = match (std::env::var("TLS_FINGERPRINT"), std::env::var("TLS_CERT")) {
+            (Ok(fingerprint), _) => fingerprint.trim().to_lowercase(),
+            (Err(_), Ok(cert)) => fingerprint(&CertificateDer::from_pem_file(cert)?),
+            _ => {
+                return Err(
+                    "Set TLS_FINGERPRINT (printed by the server) or TLS_CERT to check the server certificate"
+                        .into(),
+                )
+            }
+        };
+
+        let provider = Arc::new(default_provider());
+        let config = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                fingerprint: expected,
+                provider,
+            }))
+            .with_no_client_auth();
+        Ok(Arc::new(config))
+    }
+
+    pub fn connect(endpoint: &Endpoint) -> Result<Box<dyn Stream>, Box<dyn std::error::Error>> {
+        let timeout = Duration::from_secs(5);
+        match endpoint {
+            Endpoint::Tls(addr) => {
+                let socket = TcpStream::connect_timeout(addr, timeout)?;
+                socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                let connection =
+                    ClientConnection::new(client_tls_config()?, ServerName::from(addr.ip()))?;
+                Ok(Box::new(StreamOwned::new(connection, socket)))
+            }
+            Endpoint::Tcp(addr) => {
+                let socket = TcpStream::connect_timeout(addr, timeout)?;
+                socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                Ok(Box::new(socket))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let socket = UnixStream::connect(path)?;
+                socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                Ok(Box::new(socket))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => unreachable!(),
+        }
+    }
+
+    #[cfg(unix)]
+    pub enum Listener {
+        Tls(TcpListener, Arc<ServerConfig>),
+        Tcp(TcpListener),
+        Unix(UnixListener, PathBuf),
+    }
+
+    #[cfg(unix)]
+    impl Listener {
+        /// Listeners are non blocking, to be able to stop on signals
+        pub fn bind(endpoint: &Endpoint) -> Result<Self, Box<dyn std::error::Error>> {
+            let listener = match endpoint {
+                Endpoint::Tls(addr) => {
+                    let config = server_tls_config()?;
+                    Listener::Tls(TcpListener::bind(addr)?, config)
+                }
+                Endpoint::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr)?),
+                Endpoint::Unix(path) => {
+                    // only the owner (root) can connect
+                    let umask = unsafe { libc::umask(0o177) };
+                    let listener = UnixListener::bind(path);
+                    unsafe { libc::umask(umask) };
+                    Listener::Unix(listener?, path.clone())
+                }
+            };
+            match &listener {
+                Listener::Tls(l, _) | Listener::Tcp(l) => l.set_nonblocking(true)?,
+                Listener::Unix(l, _) => l.set_nonblocking(true)?,
+            }
+            Ok(listener)
+        }
+
+        /// Return None if no client is waiting
+        ///
+        /// The TLS handshake is done on first read or write, in the client thread
+        pub fn accept(&self) -> std::io::Result<Option<(Box<dyn Stream>, String)>> {
+            let result = match self {
+                Listener::Tls(listener, config) => listener.accept().and_then(|(socket, addr)| {
+                    socket.set_nonblocking(false)?;
+                    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                    let connection =
+                        ServerConnection::new(config.clone()).map_err(std::io::Error::other)?;
+                    let stream: Box<dyn Stream> = Box::new(StreamOwned::new(connection, socket));
+                    Ok((stream, addr.to_string()))
+                }),
+                Listener::Tcp(listener) => listener.accept().and_then(|(socket, addr)| {
+                    socket.set_nonblocking(false)?;
+                    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                    let stream: Box<dyn Stream> = Box::new(socket);
+                    Ok((stream, addr.to_string()))
+                }),
+                Listener::Unix(listener, path) => listener.accept().and_then(|(socket, _)| {
+                    socket.set_nonblocking(false)?;
+                    socket.set_read_timeout(Some(POLL_INTERVAL))?;
+                    let stream: Box<dyn Stream> = Box::new(socket);
+                    Ok((stream, path.display().to_string()))
+                }),
+            };
+            match result {
+                Ok(client) => Ok(Some(client)),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+                Err(e) => Err(e),
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    impl Drop for Listener {
+        fn drop(&mut self) {
+            if let Listener::Unix(_, path) = self {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Record messages to a file, and replay them in the client
+///
+/// File: [magic][protocol version: u16][start time, unix secs: u64], then records:
+/// [timestamp, micros since start: u64][keyframe: u8][message frame, see Message::send]
+pub mod recording {
+    use std::{
+        fs::File,
+        io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+        path::Path,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{Compression, Message, UpdateMessage, PROTOCOL_VERSION};
+
+    const MAGIC: &[u8; 8] = b"KPFVREC\0";
+
+    /// A full update is written every KEYFRAME_INTERVAL records, to be able to seek without
+    /// applying all deltas since the beginning
+    pub const KEYFRAME_INTERVAL: usize = 30;
+
+    pub struct RecordWriter {
+        file: BufWriter<File>,
+        start: Instant,
+    }
+
+    impl RecordWriter {
+        pub fn create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut file = BufWriter::new(File::create(path)?);
+            file.write_all(MAGIC)?;
+            file.write_all(&PROTOCOL_VERSION.to_le_bytes())?;
+            let start_time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+            file.write_all(&start_time.to_le_bytes())?;
+
+            Ok(Self {
+                file,
+                start: Instant::now(),
+            })
+        }
+
+        /// Return the compressed size of the message
+        pub fn write(&mut self, message: &Message) -> Result<usize, Box<dyn std::error::Error>> {
+            let keyframe = matches!(message, Message::FirstUpdate(_) | Message::Update(_));
+            let timestamp = self.start.elapsed().as_micros() as u64;
+            self.file.write_all(&timestamp.to_le_bytes())?;
+            self.file.write_all(&[keyframe as u8])?;
+            let size = message.send(&mut self.file, Compression::Zstd)?;
+            Ok(size)
+        }
+
+        pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error>> {
+            self.write(&Message::Finish)?;
+            self.file.flush()?;
+            Ok(())
+        }
+    }
+
+    pub struct RecordIndex {
+        pub timestamp: Duration,
+        pub keyframe: bool,
+        /// Start of the message frame in the file
+        offset: u64,
+    }
+
+    pub struct Recording {
+        file: BufReader<File>,
+        /// Start of the recording, unix secs
+        pub start_time: u64,
+        /// Without the final Finish message
+        pub index: Vec<RecordIndex>,
+    }
+
+    impl Recording {
+        /// Read the headers of all records, messages are read on demand
+        pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+            let mut file = BufReader::new(File::open(path)?);
+
+            let mut magic = [0u8; 8];
+            file.read_exact(&mut magic)?;
+            if &magic != MAGIC {
+                return Err(format!("{:?} is not a kpageflags-viewer recording", path).into());
+            }
+            let mut version = [0u8; 2];
+            file.read_exact(&mut version)?;
+            let version = u16::from_le_bytes(version);
+            if version != PROTOCOL_VERSION {
+                return Err(format!(
+                    "Recording protocol version {} is not supported (expected {})",
+                    version, PROTOCOL_VERSION
+                )
+                .into());
+            }
+            let mut start_time = [0u8; 8];
+            file.read_exact(&mut start_time)?;
+            let start_time = u64::from_le_bytes(start_time);
+
+            let mut index = Vec::new();
+            let mut header = [0u8; 9];
+            // recording can be truncated if the recorder was killed
+            while file.read_exact(&mut header).is_ok() {
+                let timestamp =
+                    Duration::from_micros(u64::from_le_bytes(header[..8].try_into().unwrap()));
+                let keyframe = header[8] == 1;
+                let offset = file.stream_position()?;
+
+                // frame: [payload size: u64][protocol version: u16][compression: u8][payload]
+                let mut size = [0u8; 8];
+                if file.read_exact(&mut size).is_err() {
+                    break;
+                }
+                let size = u64::from_le_bytes(size);
+                let end = offset + 8 + 2 + 1 + size;
+                if end > file.get_ref().metadata()?.len() {
+                    break;
+                }
+                file.seek(SeekFrom::Start(end))?;
+
+                index.push(RecordIndex {
+                    timestamp,
+                    keyframe,
+                    offset,
+                });
+            }
+            // drop Finish
+            if index.len() > 1 && !index.last().unwrap().keyframe {
+                let last = index.len() - 1;
+                if matches!(
+                    Self::read_at(&mut file, index[last].offset)?,
+                    Message::Finish
+                ) {
+                    index.pop();
+                }
+            }
+            if index.is_empty() || !index[0].keyframe {
+                return Err(format!("{:?} is empty", path).into());
+            }
+
+            Ok(Self {
+                file,
+                start_time,
+                index,
+            })
+        }
+
+        fn read_at(
+            file: &mut BufReader<File>,
+            offset: u64,
+        ) -> Result<Message, Box<dyn std::error::Error>> {
+            file.seek(SeekFrom::Start(offset))?;
+            Message::recv(file)
+        }
+
+        pub fn read(&mut self, idx: usize) -> Result<Message, Box<dyn std::error::Error>> {
+            Self::read_at(&mut self.file, self.index[idx].offset)
+        }
+
+        /// Full state at record `idx`: last keyframe, plus the following deltas
+        pub fn state_at(
+            &mut self,
+            idx: usize,
+        ) -> Result<UpdateMessage, Box<dyn std::error::Error>> {
+            let keyframe = (0..=idx)
+                .rev()
+                .find(|&i| self.index[i].keyframe)
+                .ok_or("No keyframe")?;
+
+            let mut state = match self.read(keyframe)? {
+                Message::FirstUpdate(message) => Arc::unwrap_or_clone(message.update_message),
+                Message::Update(message) => Arc::unwrap_or_clone(message),
+                _ => return Err(format!("Record {} is not a keyframe", keyframe).into()),
+            };
+            for i in keyframe + 1..=idx {
+                match self.read(i)? {
+                    Message::Delta(delta) => state.apply_delta(*delta)?,
+                    _ => return Err(format!("Record {} is not a delta", i).into()),
+                }
+            }
+            Ok(state)
+        }
+    }
+
+    pub enum ReplayCommand {
+        Pause(bool),
+        /// Record index
+        Seek(usize),
+        /// +1 or -1 record
+        Step(isize),
+        Speed(f64),
+    }
+
+    /// Used by the client UI
+    pub struct ReplayControl {
+        pub start_time: u64,
+        pub timestamps: Vec<Duration>,
+        /// Record being displayed
+        pub position: Arc<AtomicUsize>,
+        pub commands: Sender<ReplayCommand>,
+        pub paused: bool,
+        pub speed: f64,
+    }
+
+    /// Send recorded messages to the client at the recorded pace, divided by `speed`
+    ///
+    /// After seeking, the full state is sent as an Update message
+    pub fn replay(
+        mut recording: Recording,
+        tx: SyncSender<Message>,
+        commands: Receiver<ReplayCommand>,
+        position: Arc<AtomicUsize>,
+        mut speed: f64,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut pos = 0;
+        let mut paused = false;
+        // the UI is closed if sending fails
+        if tx.send(recording.read(0)?).is_err() {
+            return Ok(());
+        }
+
+        loop {
+            position.store(pos, Ordering::SeqCst);
+            let at_end = pos + 1 >= recording.index.len();
+
+            let command = if paused || at_end {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return Ok(()),
+                }
+            } else {
+                let wait = (recording.index[pos + 1].timestamp - recording.index[pos].timestamp)
+                    .div_f64(speed);
+                match commands.recv_timeout(wait) {
+                    Ok(command) => Some(command),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+            };
+
+            let target = match command {
+                None | Some(ReplayCommand::Step(1)) => pos + 1,
+                Some(ReplayCommand::Step(step)) => pos.saturating_add_signed(step),
+                Some(ReplayCommand::Seek(idx)) => idx,
+                Some(ReplayCommand::Pause(pause)) => {
+                    paused = pause;
+                    continue;
+                }
+                Some(ReplayCommand::Speed(new_speed)) => {
+                    speed = new_speed;
+                    continue;
+                }
+            };
+            let target = target.min(recording.index.len() - 1);
+            let message = if target == pos + 1 {
+                recording.read(target)?
+            } else if target != pos {
+                Message::Update(Arc::new(recording.state_at(target)?))
+            } else {
+                continue;
+            };
+            if tx.send(message).is_err() {
+                return Ok(());
+            }
+            pos = target;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::{collections::HashMap, path::PathBuf, sync::mpsc};
+
+        use procfs_core::{process::Pfn, PhysicalMemoryMap, PhysicalPageFlags};
+        use snap::memory_layout::MemoryLayout;
+
+        use super::*;
+        use crate::FirstUpdateMessage;
+
+        /// A segment of 16 pages, page `step` is in the LRU
+        fn update(step: usize) -> UpdateMessage {
+            let mut flags = vec![PhysicalPageFlags::empty(); 16];
+            flags[step] = PhysicalPageFlags::LRU;
+            UpdateMessage {
+                processes_info: Vec::new(),
+                memory_segments: vec![(Pfn(0x100), Pfn(0x110), flags)],
+                flags_overview: None,
+                iomem: vec![PhysicalMemoryMap {
+                    address: (0x100000, 0x10ffff),
+                    name: "System RAM".to_string(),
+                }],
+                map_counts: None,
+                memcgs: None,
+                cgroup_paths: HashMap::new(),
+                map_counts_overview: None,
+                memcgs_overview: None,
+                owners_overview: None,
+                group_pages: HashMap::new(),
+            }
+        }
+
+        fn lru_page(update: &UpdateMessage) -> Option<usize> {
+            update.memory_segments[0]
+                .2
+                .iter()
+                .position(|flags| *flags == PhysicalPageFlags::LRU)
+        }
+
+        /// Removed when dropped
+        struct TempFile(PathBuf);
+
+        impl TempFile {
+            fn new(name: &str) -> Self {
+                Self(std::env::temp_dir().join(format!(
+                    "kpageflags-viewer-{}-{}.rec",
+                    name,
+                    std::process::id()
+                )))
+            }
+        }
+
+        impl Drop for TempFile {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.0);
+            }
+        }
+
+        /// A first update, deltas, and a keyframe at `keyframe`, like server::record
+        fn write_recording(path: &Path, records: usize, keyframe: usize) {
+            let mut writer = RecordWriter::create(path).unwrap();
+            for step in 0..records {
+                let message = match step {
+                    0 => Message::FirstUpdate(FirstUpdateMessage {
+                        page_size: 4096,
+                        memory_layout: MemoryLayout::default(),
+                        update_message: Arc::new(update(0)),
+                    }),
+                    _ if step == keyframe => Message::Update(Arc::new(update(step))),
+                    _ => Message::Delta(Box::new(update(step - 1).delta(&update(step)).unwrap())),
+                };
+                writer.write(&message).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        #[test]
+        fn keyframes() {
+            let file = TempFile::new("keyframes");
+            write_recording(&file.0, 6, 3);
+
+            let mut recording = Recording::open(&file.0).unwrap();
+            // without Finish
+            assert_eq!(recording.index.len(), 6);
+            let keyframes: Vec<bool> = recording.index.iter().map(|r| r.keyframe).collect();
+            assert_eq!(keyframes, [true, false, false, true, false, false]);
+            assert!(recording
+                .index
+                .windows(2)
+                .all(|records| records[0].timestamp <= records[1].timestamp));
+            assert!(matches!(
+                recording.read(0).unwrap(),
+                Message::FirstUpdate(_)
+            ));
+            assert!(matches!(recording.read(3).unwrap(), Message::Update(_)));
+            assert!(matches!(recording.read(4).unwrap(), Message::Delta(_)));
+        }
+
+        #[test]
+        fn seek() {
+            let file = TempFile::new("seek");
+            write_recording(&file.0, 6, 3);
+
+            let mut recording = Recording::open(&file.0).unwrap();
+            // forward from the first update, from the keyframe, and backward
+            for idx in [2, 5, 3, 0, 4, 1] {
+                assert_eq!(lru_page(&recording.state_at(idx).unwrap()), Some(idx));
+            }
+        }
+
+        #[test]
+        fn truncated() {
+            let file = TempFile::new("truncated");
+            write_recording(&file.0, 4, 2);
+            // the recorder was killed while writing the last delta
+            let len = std::fs::metadata(&file.0).unwrap().len();
+            let finish = {
+                let mut buf = Vec::new();
+                Message::Finish.send(&mut buf, Compression::Zstd).unwrap();
+                buf.len() as u64 + 9
+            };
+            std::fs::File::options()
+                .write(true)
+                .open(&file.0)
+                .unwrap()
+                .set_len(len - finish - 1)
+                .unwrap();
+
+            let mut recording = Recording::open(&file.0).unwrap();
+            assert_eq!(recording.index.len(), 3);
+            assert_eq!(lru_page(&recording.state_at(2).unwrap()), Some(2));
+
+            std::fs::write(&file.0, b"KPFVREC").unwrap();
+            assert!(Recording::open(&file.0).is_err());
+        }
+
+        #[test]
+        fn replay_seek() {
+            let file = TempFile::new("replay");
+            write_recording(&file.0, 6, 3);
+            let recording = Recording::open(&file.0).unwrap();
+
+            let (tx, rx) = mpsc::sync_channel(16);
+            let (commands, commands_rx) = mpsc::channel();
+            commands.send(ReplayCommand::Pause(true)).unwrap();
+            commands.send(ReplayCommand::Seek(4)).unwrap();
+            commands.send(ReplayCommand::Step(-3)).unwrap();
+            commands.send(ReplayCommand::Step(1)).unwrap();
+            drop(commands);
+            let position = Arc::new(AtomicUsize::new(0));
+            replay(recording, tx, commands_rx, position.clone(), 1.).unwrap();
+
+            let messages: Vec<Message> = rx.into_iter().collect();
+            assert_eq!(messages.len(), 4);
+            assert!(matches!(messages[0], Message::FirstUpdate(_)));
+            // seeking sends the full state, the next record is sent as is
+            let states: Vec<Option<usize>> = messages[1..3]
+                .iter()
+                .map(|message| match message {
+                    Message::Update(update) => lru_page(update),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(states, [Some(4), Some(1)]);
+            assert!(matches!(messages[3], Message::Delta(_)));
+            assert_eq!(position.load(Ordering::SeqCst), 2);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub mod server {
+    use std::collections::{HashMap, HashSet};
+    use std::fs::File;
+    use std::io::{BufReader, Read, Seek, SeekFrom};
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    //use procfs::prelude::*;
+    use procfs::process::{Pfn, Process};
+    use procfs::{KPageCount, KPageFlags, PhysicalMemoryMap, WithCurrentSystemInfo};
+    use procfs_core::PhysicalPageFlags;
+    use snap::groups::GroupBy;
+    use snap::memory_layout::MemoryLayout;
+
+    use crate::recording::{RecordWriter, KEYFRAME_INTERVAL};
+    use crate::transport::{generate_token, token_eq, Endpoint, Listener, Stream};
+    use crate::{
+        detail_level, map_order, Compression, FirstUpdateMessage, Message, MessageReader,
+        PageLayers, ProcessInfo, ServerHelloMessage, UpdateMessage, PROTOCOL_VERSION,
+    };
+
+    pub fn get_process_info(
+        process: &Process,
+        group: String,
+    ) -> Result<ProcessInfo, Box<dyn std::error::Error>> {
+        let pfns = snap::get_process_pfns(process, procfs::page_size())?;
+        let exe = process.exe()?;
+        let cmdline = process.cmdline().unwrap_or_default().join(" ");
+        let pid = process.pid;
+
+        Ok(ProcessInfo {
+            pid,
+            exe,
+            cmdline,
+            group,
+            pfns,
+            pixels: Vec::new(),
+        })
+    }
+
+    pub fn get_all_processes_info(group_by: &GroupBy) -> Vec<ProcessInfo> {
+        use rayon::prelude::*;
+        let all_processes: Vec<Process> = procfs::process::all_processes()
+            .unwrap()
+            .filter_map(|p| p.ok())
+            .collect();
+
+        let groups: Vec<String> = group_by.groups(&all_processes);
+        let processes_info: Vec<ProcessInfo> = all_processes
+            .par_iter()
+            .zip(groups)
+            .filter_map(|(p, group)| get_process_info(p, group).ok())
+            .collect();
+
+        processes_info
+    }
+
+    pub fn get_memory_zones_flags(
+        iomem: &[PhysicalMemoryMap],
+        kpageflags: &mut KPageFlags,
+    ) -> Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)> {
+        let segments: Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)> = iomem
+            .iter()
+            .map(|segment| {
+                let (start_pfn, end_pfn) = segment.get_range().get();
+                let flags: Vec<PhysicalPageFlags> =
+                    kpageflags.get_range_info(start_pfn, end_pfn).unwrap();
+
+                (start_pfn, end_pfn, flags)
+            })
+            .collect();
+
+        segments
+    }
+
+    /// /proc/kpagecgroup: memory cgroup inode of each page, 0 if none
+    ///
+    /// Requires CONFIG_MEMCG
+    pub struct KPageCgroup {
+        reader: BufReader<File>,
+    }
+
+    impl KPageCgroup {
+        pub fn new() -> std::io::Result<Self> {
+            let reader = BufReader::new(File::open("/proc/kpagecgroup")?);
+            Ok(Self { reader })
+        }
+
+        pub fn get_memcg_in_range(&mut self, start: Pfn, end: Pfn) -> std::io::Result<Vec<u64>> {
+            let mut buf = vec![0u8; (end.0 - start.0) as usize * 8];
+            self.reader.seek(SeekFrom::Start(start.0 * 8))?;
+            self.reader.read_exact(&mut buf)?;
+            Ok(buf
+                .chunks_exact(8)
+                .map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap()))
+                .collect())
+        }
+    }
+
+    /// Resolve memory cgroup inodes to paths, relative to the cgroup mount point
+    ///
+    /// Walks the cgroup v2 hierarchy, and the v1 memory controller if mounted
+    pub fn get_cgroup_paths(inodes: &HashSet<u64>) -> HashMap<u64, String> {
+        fn walk(root: &Path, dir: &Path, inodes: &HashSet<u64>, paths: &mut HashMap<u64, String>) {
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                return;
+            };
+            for entry in entries.filter_map(|entry| entry.ok()) {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if !metadata.is_dir() {
+                    continue;
+                }
+                let path = entry.path();
+                if inodes.contains(&metadata.ino()) {
+                    let relative = path.strip_prefix(root).unwrap_or(&path);
+                    paths.insert(metadata.ino(), format!("/{}", relative.display()));
+                }
+                walk(root, &path, inodes, paths);
+            }
+        }
+
+        let mut paths = HashMap::new();
+        let mut roots: Vec<PathBuf> = snap::pressure::cgroup2_mount_point().into_iter().collect();
+        let v1_memory = PathBuf::from("/sys/fs/cgroup/memory");
+        if v1_memory.is_dir() {
+            roots.push(v1_memory);
+        }
+        for root in roots {
+            if let Ok(metadata) = root.metadata() {
+                if inodes.contains(&metadata.ino()) {
+                    paths.insert(metadata.ino(), "/".to_string());
+                }
+            }
+            walk(&root, &root, inodes, &mut paths);
+        }
+        paths
+    }
+
+    /// Everything needed to build an UpdateMessage
+    pub struct Scanner {
+        iomem: Vec<PhysicalMemoryMap>,
+        group_by: GroupBy,
+        kpageflags: KPageFlags,
+        kpagecount: Option<KPageCount>,
+        kpagecgroup: Option<KPageCgroup>,
+    }
+
+    impl Scanner {
+        /// Read GROUP_BY and EXTRA_LAYERS env variables
+        ///
+        /// EXTRA_LAYERS: comma separated list of `kpagecount`, `kpagecgroup`. Both files are
+        /// as big as kpageflags, and increase scan time and messages size
+        pub fn from_env() -> Self {
+            let group_by = match std::env::var("GROUP_BY") {
+                Ok(value) => value.parse().expect("Invalid GROUP_BY env variable"),
+                Err(_) => GroupBy::Uid,
+            };
+            let extra_layers = std::env::var("EXTRA_LAYERS").unwrap_or_default();
+            let extra_layers: Vec<&str> = extra_layers
+                .split(',')
+                .map(|layer| layer.trim())
+                .filter(|layer| !layer.is_empty())
+                .collect();
+            if let Some(layer) = extra_layers
+                .iter()
+                .find(|layer| !["kpagecount", "kpagecgroup"].contains(layer))
+            {
+                panic!(
+                    "Unknown EXTRA_LAYERS {:?}, expected kpagecount and/or kpagecgroup",
+                    layer
+                );
+            }
+
+            let kpagecount = if extra_layers.contains(&"kpagecount") {
+                KPageCount::new()
+                    .map_err(|e| eprintln!("Can't open /proc/kpagecount: {}", e))
+                    .ok()
+            } else {
+                None
+            };
+            let kpagecgroup = if extra_layers.contains(&"kpagecgroup") {
+                KPageCgroup::new()
+                    .map_err(|e| eprintln!("Can't open /proc/kpagecgroup: {}", e))
+                    .ok()
+            } else {
+                None
+            };
+
+            Self {
+                iomem: get_iomem(),
+                group_by,
+                kpageflags: KPageFlags::new().unwrap(),
+                kpagecount,
+                kpagecgroup,
+            }
+        }
+
+        pub fn scan(&mut self) -> UpdateMessage {
+            let ranges: Vec<(Pfn, Pfn)> = self
+                .iomem
+                .iter()
+                .map(|segment| segment.get_range().get())
+                .collect();
+
+            // a layer that can't be read is not sent, clients fall back to flags
+            let map_counts = self.kpagecount.as_mut().and_then(|kpagecount| {
+                ranges
+                    .iter()
+                    .map(|&(start, end)| kpagecount.get_count_in_range(start, end))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| eprintln!("Can't read /proc/kpagecount: {}", e))
+                    .ok()
+            });
+            let memcgs: Option<Vec<Vec<u64>>> = self.kpagecgroup.as_mut().and_then(|kpagecgroup| {
+                ranges
+                    .iter()
+                    .map(|&(start, end)| kpagecgroup.get_memcg_in_range(start, end))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| eprintln!("Can't read /proc/kpagecgroup: {}", e))
+                    .ok()
+            });
+            let cgroup_paths = match &memcgs {
+                Some(memcgs) => {
+                    let inodes: HashSet<u64> = memcgs.iter().flatten().copied().collect();
+                    get_cgroup_paths(&inodes)
+                }
+                None => HashMap::new(),
+            };
+
+            UpdateMessage {
+                processes_info: get_all_processes_info(&self.group_by),
+                memory_segments: get_memory_zones_flags(&self.iomem, &mut self.kpageflags),
+                flags_overview: None,
+                iomem: self.iomem.clone(),
+                map_counts,
+                memcgs,
+                cgroup_paths,
+                map_counts_overview: None,
+                memcgs_overview: None,
+                owners_overview: None,
+                group_pages: HashMap::new(),
+            }
+        }
+    }
+
+    /// Clients can't request updates faster than this
+    const MIN_UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+    /// Clients must say hello before this delay
+    const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Set by SIGINT/SIGTERM
+    static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+    extern "C" fn on_signal(_signal: libc::c_int) {
+        SHUTDOWN.store(true, Ordering::SeqCst);
+    }
+
+    pub fn install_signal_handlers() {
+        // SAFETY: the handler only stores to an atomic
+        unsafe {
+            libc::signal(libc::SIGINT, on_signal as *const () as libc::sighandler_t);
+            libc::signal(libc::SIGTERM, on_signal as *const () as libc::sighandler_t);
+        }
+    }
+
+    pub fn shutdown_requested() -> bool {
+        SHUTDOWN.load(Ordering::SeqCst)
+    }
+
+    /// "System RAM" segments
+    pub fn get_iomem() -> Vec<PhysicalMemoryMap> {
+        procfs::iomem()
+            .unwrap()
+            .iter()
+            .filter_map(|(ident, map)| if *ident == 0 { Some(map.clone()) } else { None })
+            .filter(|map| map.name == "System RAM")
+            .collect()
+    }
+
+    /// A scan, as sent to clients
+    #[derive(Clone)]
+    struct Snapshot {
+        update: Arc<UpdateMessage>,
+        /// Layers of each page, moved out of `update` when it only has overviews
+        pages: Option<Arc<PageLayers>>,
+    }
+
+    impl Snapshot {
+        /// When the map is an overview, only overviews are sent, tiles are sent on demand
+        fn new(mut update: UpdateMessage) -> Self {
+            let page_size = procfs::page_size();
+            let level = detail_level(map_order(&update.iomem, page_size));
+            let pages = (level > 0).then(|| Arc::new(update.split_overview(page_size, level)));
+            Self {
+                update: Arc::new(update),
+                pages,
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct State {
+        /// Last scan, with a generation number
+        snapshot: Option<(u64, Snapshot)>,
+        /// Update interval requested by each connected client
+        clients: HashMap<u64, Duration>,
+    }
+
+    /// Scan processes and kpageflags, shared by all clients
+    ///
+    /// The scan interval is the smallest interval requested by connected clients.
+    /// Nothing is scanned when no client is connected
+    fn scanner(state: &Mutex<State>, mut scanner: Scanner) {
+        let mut generation = 0;
+
+        while !SHUTDOWN.load(Ordering::SeqCst) {
+            let interval = state.lock().unwrap().clients.values().min().copied();
+            let Some(interval) = interval else {
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            };
+
+            let chrono = Instant::now();
+            let snapshot = Snapshot::new(scanner.scan());
+            generation += 1;
+            state.lock().unwrap().snapshot = Some((generation, snapshot));
+
+            let update_duration = chrono.elapsed();
+            eprintln!("Scan duration: {:?}", update_duration);
+            thread::sleep(interval.saturating_sub(update_duration));
+        }
+    }
+
+    /// Handshake, then send a full update followed by deltas until the client disconnects.
+    /// Tile requests are answered between updates
+    ///
+    /// Nothing is sent before the client is authenticated
+    fn serve_client(
+        socket: &mut dyn Stream,
+        client_id: u64,
+        client_name: &str,
+        state: &Mutex<State>,
+        compression: Compression,
+        token: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut reader = MessageReader::default();
+        let hello = match reader.recv_timeout(socket, HELLO_TIMEOUT)? {
+            Message::ClientHello(hello) => hello,
+            _ => return Err("Expected ClientHello".into()),
+        };
+        let refusal = if hello.protocol_version != PROTOCOL_VERSION {
+            Some(format!(
+                "Client protocol version {} is not supported (server {})",
+                hello.protocol_version, PROTOCOL_VERSION
+            ))
+        } else if token.is_some_and(|token| {
+            !hello
+                .token
+                .as_deref()
+                .is_some_and(|client_token| token_eq(token, client_token))
+        }) {
+            Some("Authentication failed".to_string())
+        } else {
+            None
+        };
+        if let Some(refusal) = refusal {
+            Message::Error(refusal.clone()).send(socket, Compression::None)?;
+            return Err(refusal.into());
+        }
+        let update_interval = hello.update_interval.max(MIN_UPDATE_INTERVAL);
+        Message::ServerHello(ServerHelloMessage {
+            protocol_version: PROTOCOL_VERSION,
+            update_interval,
+        })
+        .send(socket, compression)?;
+        eprintln!(
+            "Client {} connected, update interval {:?}",
+            client_name, update_interval
+        );
+
+        // wait for a scan started after the client connected
+        let mut last_generation = {
+            let mut locked = state.lock().unwrap();
+            locked.clients.insert(client_id, update_interval);
+            locked
+                .snapshot
+                .as_ref()
+                .map(|(generation, _)| *generation)
+                .unwrap_or(0)
+        };
+        // last snapshot sent, tiles are consistent with it
+        let mut previous: Option<Snapshot> = None;
+        let mut last_sent = Instant::now();
+        // the client is out of sync, send a complete update now
+        let mut keyframe = false;
+
+        loop {
+            // waits up to POLL_INTERVAL
+            while let Some(message) = reader.poll(socket)? {
+                match (message, &previous) {
+                    (Message::TileRequest(request), Some(sent)) => {
+                        let pages = sent.pages.as_ref().ok_or("Tile request without overview")?;
+                        Message::Tile(pages.tile(&request)).send(socket, compression)?;
+                    }
+                    (Message::KeyframeRequest, _) => keyframe = true,
+                    _ => return Err("Unexpected message".into()),
+                }
+            }
+
+            let (generation, current) = {
+                let locked = state.lock().unwrap();
+                if SHUTDOWN.load(Ordering::SeqCst) {
+                    drop(locked);
+                    Message::Finish.send(socket, compression)?;
+                    return Ok(());
+                }
+                // other clients can request more frequent scans, the scanner doesn't
+                // wake up exactly on time, so accept a small margin
+                let due = previous.is_none() || last_sent.elapsed() >= update_interval.mul_f64(0.9);
+                match &locked.snapshot {
+                    Some((generation, snapshot))
+                        if (*generation > last_generation && due) || keyframe =>
+                    {
+                        (*generation, snapshot.clone())
+                    }
+                    _ => continue,
+                }
+            };
+
+            // only send what changed since the previous update
+            let message = match &previous {
+                None => Message::FirstUpdate(FirstUpdateMessage {
+                    page_size: procfs::page_size(),
+                    memory_layout: MemoryLayout::read(procfs::page_size()),
+                    update_message: current.update.clone(),
+                }),
+                Some(_) if keyframe => Message::Update(current.update.clone()),
+                Some(previous) => match previous.update.delta(&current.update) {
+                    Some(delta) => Message::Delta(Box::new(delta)),
+                    None => Message::Update(current.update.clone()),
+                },
+            };
+            let message_size = message.send(socket, compression)?;
+            keyframe = false;
+            eprintln!("Sent {} KiB to {}", message_size / 1024, client_name);
+
+            previous = Some(current);
+            last_generation = generation;
+            last_sent = Instant::now();
+        }
+    }
+
+    /// Scan locally and write messages to a file, until SIGINT/SIGTERM
+    pub fn record(path: &Path, update_interval: Duration) {
+        let mut writer =
+            RecordWriter::create(path).unwrap_or_else(|e| panic!("Can't create {:?}: {}", path, e));
+        install_signal_handlers();
+        let mut scanner = Scanner::from_env();
+        let update_interval = update_interval.max(MIN_UPDATE_INTERVAL);
+        println!(
+            "Recording to {:?} every {:?}, stop with Ctrl-C",
+            path, update_interval
+        );
+
+        let mut previous: Option<Arc<UpdateMessage>> = None;
+        let mut count = 0;
+        while !shutdown_requested() {
+            let chrono = Instant::now();
+            let current = Arc::new(scanner.scan());
+
+            // regular keyframes, to be able to seek
+            let message = match &previous {
+                None => Message::FirstUpdate(FirstUpdateMessage {
+                    page_size: procfs::page_size(),
+                    memory_layout: MemoryLayout::read(procfs::page_size()),
+                    update_message: current.clone(),
+                }),
+                Some(_) if count % KEYFRAME_INTERVAL == 0 => Message::Update(current.clone()),
+                Some(previous) => match previous.delta(&current) {
+                    Some(delta) => Message::Delta(Box::new(delta)),
+                    None => Message::Update(current.clone()),
+                },
+            };
+            let size = writer.write(&message).expect("Can't write recording");
+            eprintln!("Record {}: {} KiB", count, size / 1024);
+            count += 1;
+            previous = Some(current);
+
+            thread::sleep(update_interval.saturating_sub(chrono.elapsed()));
+        }
+
+        writer.finish().expect("Can't write recording");
+        println!("Recorded {} updates to {:?}", count, path);
+    }
+
+    pub fn server(endpoint: Endpoint) {
+        let listener = Listener::bind(&endpoint)
+            .unwrap_or_else(|e| panic!("Can't listen on {:?}: {}", endpoint, e));
+        println!("Listening on {:?}", endpoint);
+
+        // the Unix socket is only accessible by root, the token is optional
+        let token = match (std::env::var("AUTH_TOKEN"), &endpoint) {
+            (Ok(token), _) => Some(token),
+            (Err(_), Endpoint::Unix(_)) => None,
+            (Err(_), _) => {
+                let token = generate_token();
+                println!("Auth token: {}", token);
+                Some(token)
+            }
+        };
+
+        install_signal_handlers();
+        let compression = Compression::from_env().expect("Invalid COMPRESSION env variable");
+        let scan = Scanner::from_env();
+
+        let state: Arc<Mutex<State>> = Default::default();
+        let scanner_thread = {
+            let state = state.clone();
+            thread::spawn(move || scanner(&state, scan))
+        };
+
+        let mut client_threads: Vec<thread::JoinHandle<()>> = Vec::new();
+        let mut client_id = 0;
+        while !SHUTDOWN.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok(Some((mut socket, client_name))) => {
+                    client_id += 1;
+                    let state = state.clone();
+                    let token = token.clone();
+                    client_threads.push(thread::spawn(move || {
+                        if let Err(e) = serve_client(
+                            &mut socket,
+                            client_id,
+                            &client_name,
+                            &state,
+                            compression,
+                            token.as_deref(),
+                        ) {
+                            eprintln!("Client {} disconnected: {}", client_name, e);
+                        }
+                        state.lock().unwrap().clients.remove(&client_id);
+                    }));
+                }
+                Ok(None) => thread::sleep(Duration::from_millis(100)),
+                Err(e) => eprintln!("Can't accept client: {}", e),
+            }
+            client_threads.retain(|t| !t.is_finished());
+        }
+
+        eprintln!("Shutting down");
+        for client_thread in client_threads {
+            let _ = client_thread.join();
+        }
+        scanner_thread.join().unwrap();
+    }
+}
+
+mod client {
+    use std::{
+        collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+        fs::File,
+        io::BufReader,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc::{Receiver, Sender},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use colorgrad::Gradient;
+    use itertools::Itertools;
+    use macroquad::{
+        color::*,
+        input::{is_key_pressed, mouse_position, mouse_wheel, KeyCode},
+        math::Vec2,
+        miniquad::FilterMode,
+        texture::{draw_texture_ex, DrawTextureParams, Image, Texture2D},
+        window::{clear_background, next_frame, screen_height, screen_width},
+    };
+    use procfs_core::{process::Pfn, PhysicalPageFlags};
+    use serde::{Deserialize, Serialize};
+    use snap::memory_layout::{is_outline, outline_pixels, MemoryLayout};
+    use snap::{compute_compound_pages, flag_expr::FlagExpr};
+
+    use crate::recording::{self, Recording, ReplayCommand, ReplayControl};
+    use crate::transport::{connect, Endpoint, Stream};
+    use crate::{
+        detail_level, group_names, group_pages, map_order, page_owners, page_position,
+        ClientHelloMessage, Compression, FlagsOverview, Message, MessageReader, Overview,
+        ProcessInfo, ServerHelloMessage, TileMessage, TileRequestMessage, UpdateMessage,
+        FIRST_GROUP_OWNER, NO_OWNER, PROTOCOL_VERSION, SHARED_OWNER,
+    };
+    use egui_macroquad::egui::{self, RichText};
+    use egui_macroquad::egui::{Color32, TextWrapMode};
+
+    /// A colour for pages matching a flag expression
+    #[derive(Serialize, Deserialize, Clone)]
+    struct ColorLayer {
+        expr: String,
+        color: [u8; 3],
+        enabled: bool,
+    }
+
+    impl ColorLayer {
+        fn new(expr: &str, color: [u8; 3]) -> Self {
+            Self {
+                expr: expr.to_string(),
+                color,
+                enabled: true,
+            }
+        }
+    }
+
+    fn default_layers() -> Vec<ColorLayer> {
+        vec![
+            ColorLayer::new("PGTABLE", [255, 0, 0]),
+            ColorLayer::new("SLAB", [255, 0, 255]),
+            ColorLayer::new("ANON", [0, 255, 0]),
+            ColorLayer::new("LRU", [255, 255, 0]),
+            ColorLayer::new("BUDDY", [0, 0, 255]),
+        ]
+    }
+
+    /// Enabled layers with a valid expression, in priority order
+    fn compile_layers(layers: &[ColorLayer]) -> Vec<(FlagExpr, Color32)> {
+        layers
+            .iter()
+            .filter(|layer| layer.enabled)
+            .filter_map(|layer| {
+                let expr = layer.expr.parse().ok()?;
+                let [r, g, b] = layer.color;
+                Some((expr, Color32::from_rgb(r, g, b)))
+            })
+            .collect()
+    }
+
+    /// Path from env variable PRESETS_FILE
+    fn presets_path() -> PathBuf {
+        std::env::var_os("PRESETS_FILE")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("kpageflags-viewer-presets.json"))
+    }
+
+    /// Presets of colour layers, by name
+    fn load_presets() -> Result<BTreeMap<String, Vec<ColorLayer>>, Box<dyn std::error::Error>> {
+        let path = presets_path();
+        if !path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let file = File::open(&path)?;
+        Ok(serde_json::from_reader(BufReader::new(file))?)
+    }
+
+    fn save_presets(
+        presets: &BTreeMap<String, Vec<ColorLayer>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File::create(presets_path())?;
+        serde_json::to_writer_pretty(file, presets)?;
+        Ok(())
+    }
+
+    struct LayersEditor {
+        layers: Vec<ColorLayer>,
+        presets: BTreeMap<String, Vec<ColorLayer>>,
+        preset_name: String,
+        /// Result of the last save/load
+        status: String,
+    }
+
+    /// Edit, reorder and save colour layers. Return true if the layers changed
+    fn show_layers_editor(ui: &mut egui::Ui, editor: &mut LayersEditor) -> bool {
+        let mut changed = false;
+        let mut move_up = None;
+        let mut remove = None;
+
+        ui.label("Highest priority first");
+        let layers_count = editor.layers.len();
+        for (idx, layer) in editor.layers.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                changed |= ui.checkbox(&mut layer.enabled, "").changed();
+                changed |= ui.color_edit_button_srgb(&mut layer.color).changed();
+                changed |= ui.text_edit_singleline(&mut layer.expr).changed();
+                if ui.add_enabled(idx > 0, egui::Button::new("^")).clicked() {
+                    move_up = Some(idx);
+                }
+                if ui
+                    .add_enabled(idx + 1 < layers_count, egui::Button::new("v"))
+                    .clicked()
+                {
+                    move_up = Some(idx + 1);
+                }
+                if ui.button("x").clicked() {
+                    remove = Some(idx);
+                }
+            });
+            if let Err(e) = layer.expr.parse::<FlagExpr>() {
+                ui.label(RichText::new(format!("{}", e)).color(Color32::RED));
+            }
+        }
+        if let Some(idx) = move_up {
+            editor.layers.swap(idx - 1, idx);
+            changed = true;
+        }
+        if let Some(idx) = remove {
+            editor.layers.remove(idx);
+            changed = true;
+        }
+        if ui.button("Add layer").clicked() {
+            editor.layers.push(ColorLayer::new("", [255, 255, 255]));
+        }
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            ui.label("Preset:");
+            ui.text_edit_singleline(&mut editor.preset_name);
+            if ui
+                .add_enabled(!editor.preset_name.is_empty(), egui::Button::new("Save"))
+                .clicked()
+            {
+                editor
+                    .presets
+                    .insert(editor.preset_name.clone(), editor.layers.clone());
+                editor.status = match save_presets(&editor.presets) {
+                    Ok(()) => format!("Saved to {:?}", presets_path()),
+                    Err(e) => format!("Can't save to {:?}: {}", presets_path(), e),
+                };
+            }
+        });
+        let mut load = None;
+        egui::ComboBox::from_label("Load preset")
+            .selected_text("")
+            .show_ui(ui, |ui| {
+                ui.style_mut().wrap_mode = Some(TextWrapMode::Extend);
+                if ui.selectable_label(false, "default").clicked() {
+                    load = Some(("default".to_string(), default_layers()));
+                }
+                for (name, layers) in &editor.presets {
+                    if ui.selectable_label(false, name).clicked() {
+                        load = Some((name.clone(), layers.clone()));
+                    }
+                }
+            });
+        if let Some((name, layers)) = load {
+            editor.status = format!("Loaded {:?}", name);
+            editor.preset_name = name;
+            editor.layers = layers;
+            changed = true;
+        }
+        if !editor.status.is_empty() {
+            ui.label(&editor.status);
+        }
+
+        changed
+    }
+
+    /// Full resolution tiles are 2^8 = 256 pages wide
+    const TILE_ORDER: u8 = 8;
+    /// Tiles generated per frame, to keep the UI responsive while zooming and panning
+    const TILES_PER_FRAME: usize = 4;
+    /// Pixels without RAM pages
+    const NO_RAM_COLOR: Color32 = Color32::from_rgb(79, 79, 79);
+
+    fn to_color(c: Color32) -> Color {
+        Color::from_rgba(c.r(), c.g(), c.b(), 255)
+    }
+
+    /// Layers of a page, from the update or from a tile. Layers that are not sent are not
+    /// used by the colour modes
+    #[derive(Clone, Copy)]
+    struct PageValues {
+        flags: PhysicalPageFlags,
+        owner: u32,
+        map_count: u64,
+        memcg: u64,
+    }
+
+    impl PageValues {
+        /// From an update with the layers of each page
+        ///
+        /// `owners`: see GroupsView::owners
+        fn new(
+            update: &UpdateMessage,
+            owners: Option<&HashMap<Pfn, u32>>,
+            segment_idx: usize,
+            offset: usize,
+            pfn: Pfn,
+        ) -> Self {
+            Self {
+                flags: update.memory_segments[segment_idx]
+                    .2
+                    .get(offset)
+                    .copied()
+                    .unwrap_or(PhysicalPageFlags::empty()),
+                owner: owners
+                    .and_then(|owners| owners.get(&pfn))
+                    .copied()
+                    .unwrap_or(NO_OWNER),
+                map_count: update
+                    .map_counts
+                    .as_ref()
+                    .map_or(0, |map_counts| map_counts[segment_idx][offset]),
+                memcg: update
+                    .memcgs
+                    .as_ref()
+                    .map_or(0, |memcgs| memcgs[segment_idx][offset]),
+            }
+        }
+
+        /// From a tile sent by the server, None past the end of RAM
+        fn from_tile(tile: &TileMessage, offset: usize) -> Option<Self> {
+            Some(Self {
+                flags: *tile.flags.get(offset)?,
+                owner: tile.owners.get(offset).copied().unwrap_or(NO_OWNER),
+                map_count: tile
+                    .map_counts
+                    .as_ref()
+                    .and_then(|map_counts| map_counts.get(offset).copied())
+                    .unwrap_or(0),
+                memcg: tile
+                    .memcgs
+                    .as_ref()
+                    .and_then(|memcgs| memcgs.get(offset).copied())
+                    .unwrap_or(0),
+            })
+        }
+    }
+
+    /// Colour of each page in the current mode
+    struct PageColors<'a> {
+        mode_view: &'a ModeView,
+        layers: &'a [(FlagExpr, Color32)],
+    }
+
+    impl PageColors<'_> {
+        /// Flags are coloured by the first matching layer, black if none matches
+        fn flags_color(&self, flags: PhysicalPageFlags) -> Color32 {
+            self.layers
+                .iter()
+                .find(|(expr, _)| expr.eval(flags))
+                .map(|&(_, color)| color)
+                .unwrap_or(Color32::BLACK)
+        }
+
+        fn color(&self, page: &PageValues) -> Color32 {
+            match self.mode_view {
+                ModeView::Flags => self.flags_color(page.flags),
+                ModeView::Groups(view) => view.color(page.owner),
+                ModeView::MapCount(view) => view.color(page.map_count),
+                ModeView::Cgroups(view) => view.color(page.memcg),
+            }
+        }
+    }
+
+    /// r, g, b and pages of each overview pixel, from an overview of the layer of the mode
+    ///
+    /// Layers of the highlighted pages are unknown, they are assumed to be distributed like the
+    /// other pages of their pixel
+    fn overview_sums<T: Copy + Ord>(
+        overview: &Overview<T>,
+        color: impl Fn(T) -> Color32,
+        highlighted: &HashMap<u32, [u32; 4]>,
+        order: u8,
+        sums: &mut [[u32; 4]],
+    ) {
+        let level = overview.level;
+        let side = 2usize.pow((order - level) as u32);
+        for pixel in 0..overview.pixels() {
+            let mut pixel_sum = [0u32; 4];
+            for &(value, pages) in overview.pixel(pixel) {
+                let c = color(value);
+                pixel_sum[0] += c.r() as u32 * pages;
+                pixel_sum[1] += c.g() as u32 * pages;
+                pixel_sum[2] += c.b() as u32 * pages;
+                pixel_sum[3] += pages;
+            }
+            let (x, y) = fast_hilbert::h2xy::<u64>((pixel as u64).into(), order - level);
+            let sum = &mut sums[y as usize * side + x as usize];
+            match highlighted.get(&(pixel as u32)) {
+                Some(highlighted) => {
+                    let pages = pixel_sum[3] as u64;
+                    // pages of several selected processes are counted for each of them
+                    let highlighted_pages = (highlighted[3] as u64).min(pages);
+                    let others = pages - highlighted_pages;
+                    for i in 0..3 {
+                        sum[i] = (pixel_sum[i] as u64 * others / pages.max(1)
+                            + highlighted[i] as u64 * highlighted_pages
+                                / (highlighted[3] as u64).max(1))
+                            as u32;
+                    }
+                    sum[3] = pages as u32;
+                }
+                None => *sum = pixel_sum,
+            }
+        }
+    }
+
+    /// Whole map, one pixel per block of 4^level pages
+    ///
+    /// A pixel is the average colour of its pages: the share of pages matching each colour.
+    /// With overviews, `highlighted` are the pages of the selected processes in each pixel,
+    /// see highlighted_pixels
+    fn gen_overview(
+        colors: &PageColors,
+        update: &UpdateMessage,
+        highlighted: &Highlighted,
+        page_size: u64,
+        order: u8,
+        level: u8,
+    ) -> Image {
+        let side = 2usize.pow((order - level) as u32);
+        // r, g, b, pages
+        let mut sums = vec![[0u32; 4]; side * side];
+
+        let overviews = (
+            colors.mode_view,
+            &update.flags_overview,
+            &update.owners_overview,
+            &update.map_counts_overview,
+            &update.memcgs_overview,
+        );
+        let pixels = &highlighted.pixels;
+        match overviews {
+            (ModeView::Flags, Some(overview), _, _, _) => overview_sums(
+                overview,
+                |f| colors.flags_color(f),
+                pixels,
+                order,
+                &mut sums,
+            ),
+            (ModeView::Groups(view), _, Some(overview), _, _) => {
+                overview_sums(overview, |o| view.color(o), pixels, order, &mut sums)
+            }
+            (ModeView::MapCount(view), _, _, Some(overview), _) => {
+                overview_sums(overview, |c| view.color(c), pixels, order, &mut sums)
+            }
+            (ModeView::Cgroups(view), _, _, _, Some(overview)) => {
+                overview_sums(overview, |m| view.color(m), pixels, order, &mut sums)
+            }
+            // layers of each page, from a recording or a small host
+            _ => {
+                let owners = colors.mode_view.owners();
+                for (segment_idx, (start_pfn, end_pfn, _)) in
+                    update.memory_segments.iter().enumerate()
+                {
+                    for (offset, pfn) in (start_pfn.0..end_pfn.0).enumerate() {
+                        let Some(index) = snap::pfn_to_index(&update.iomem, page_size, Pfn(pfn))
+                        else {
+                            continue;
+                        };
+                        let (x, y) =
+                            fast_hilbert::h2xy::<u64>((index >> (2 * level)).into(), order - level);
+                        let c = match highlighted.pages.get(&Pfn(pfn)) {
+                            Some(&color) => color,
+                            None => colors.color(&PageValues::new(
+                                update,
+                                owners,
+                                segment_idx,
+                                offset,
+                                Pfn(pfn),
+                            )),
+                        };
+                        let sum = &mut sums[y as usize * side + x as usize];
+                        sum[0] += c.r() as u32;
+                        sum[1] += c.g() as u32;
+                        sum[2] += c.b() as u32;
+                        sum[3] += 1;
+                    }
+                }
+            }
+        }
+
+        let mut img = Image::gen_image_color(side as u16, side as u16, to_color(NO_RAM_COLOR));
+        for (idx, [r, g, b, pages]) in sums.into_iter().enumerate() {
+            if pages == 0 {
+                continue;
+            }
+            img.set_pixel(
+                (idx % side) as u32,
+                (idx / side) as u32,
+                Color::from_rgba((r / pages) as u8, (g / pages) as u8, (b / pages) as u8, 255),
+            );
+        }
+
+        img
+    }
+
+    /// First page index of tile `(tx, ty)`
+    ///
+    /// An aligned square of the Hilbert curve is a contiguous range of indices
+    fn tile_index(order: u8, (tx, ty): (u32, u32)) -> u64 {
+        (fast_hilbert::xy2h::<u64>(tx as u64, ty as u64, order - TILE_ORDER) as u64)
+            << (2 * TILE_ORDER)
+    }
+
+    /// Full resolution tile `(tx, ty)`, 2^TILE_ORDER pages wide
+    ///
+    /// `tile_data` is the tile sent by the server, when the update only has overviews.
+    /// `outlines` are drawn over the pages, the last one on top
+    #[allow(clippy::too_many_arguments)]
+    fn gen_tile(
+        colors: &PageColors,
+        update: &UpdateMessage,
+        highlighted: &Highlighted,
+        page_size: u64,
+        order: u8,
+        tile: (u32, u32),
+        tile_data: Option<&TileMessage>,
+        outlines: &[(&[(Pfn, Pfn)], Color32)],
+    ) -> Image {
+        let (tx, ty) = tile;
+        let tile_side = 2u32.pow(TILE_ORDER as u32);
+        let mut img =
+            Image::gen_image_color(tile_side as u16, tile_side as u16, to_color(NO_RAM_COLOR));
+
+        // offset in the tile -> colour
+        let tile_highlighted: HashMap<u32, Color32> = tile_data
+            .map(|tile_data| {
+                tile_data
+                    .processes
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(selection_idx, (_, offsets))| {
+                        offsets
+                            .iter()
+                            .map(move |&offset| (offset, highlight_color(selection_idx)))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let owners = colors.mode_view.owners();
+
+        let first = tile_index(order, tile);
+        for index in first..first + (tile_side as u64).pow(2) {
+            let Some(pfn) = snap::index_to_pfn(&update.iomem, page_size, index) else {
+                continue;
+            };
+            let offset = (index - first) as usize;
+            let (page, highlight) = match tile_data {
+                Some(tile_data) => (
+                    PageValues::from_tile(tile_data, offset),
+                    tile_highlighted.get(&(offset as u32)),
+                ),
+                None => (
+                    page_position(&update.memory_segments, pfn).map(|(segment_idx, offset)| {
+                        PageValues::new(update, owners, segment_idx, offset, pfn)
+                    }),
+                    highlighted.pages.get(&pfn),
+                ),
+            };
+            let Some(page) = page else {
+                continue;
+            };
+            let (x, y) = fast_hilbert::h2xy::<u64>(index.into(), order);
+            let (x, y) = (x as u32, y as u32);
+
+            let color = outlines
+                .iter()
+                .rev()
+                .find(|(ranges, _)| is_outline(&update.iomem, page_size, order, ranges, (x, y)))
+                .map(|&(_, color)| color)
+                .or(highlight.copied())
+                .unwrap_or_else(|| colors.color(&page));
+            img.set_pixel(x - tx * tile_side, y - ty * tile_side, to_color(color));
+        }
+
+        img
+    }
+
+    /// Pages with each flag, indexed like compute_compound_pages
+    fn overview_flag_counts(overview: &FlagsOverview) -> [u64; snap::FLAG_NAMES.len() + 1] {
+        let mut counters = [0u64; snap::FLAG_NAMES.len() + 1];
+        for (flags, pages) in overview.values() {
+            for (index, counter) in counters.iter_mut().enumerate().take(snap::FLAG_NAMES.len()) {
+                if flags.bits() & (1 << index) != 0 {
+                    *counter += pages as u64;
+                }
+            }
+        }
+        counters
+    }
+
+    /// Colours of the selected processes, avoiding the combinations of the RGB flags
+    const HIGHLIGHT_COLORS: [Color32; 6] = [
+        Color32::from_rgb(255, 140, 0),
+        Color32::from_rgb(255, 105, 180),
+        Color32::from_rgb(148, 103, 189),
+        Color32::from_rgb(135, 206, 250),
+        Color32::from_rgb(154, 205, 50),
+        Color32::from_rgb(210, 180, 140),
+    ];
+
+    fn highlight_color(selection_idx: usize) -> Color32 {
+        HIGHLIGHT_COLORS[selection_idx % HIGHLIGHT_COLORS.len()]
+    }
+
+    struct GroupStats {
+        name: String,
+        color: Color32,
+        processes: usize,
+        /// Pages mapped by any process of the group
+        rss_pages: u64,
+        /// Pages only mapped by processes of the group
+        exclusive_pages: u64,
+    }
+
+    /// Physical memory by process group, as computed by the server (see GROUP_BY)
+    struct GroupsView {
+        /// Sorted by name, like group_names
+        groups: Vec<GroupStats>,
+        /// Owner of each page, see page_owners. Empty when the update has overviews
+        owners: HashMap<Pfn, u32>,
+        shared_pages: u64,
+        /// Pages mapped by any process
+        owned_pages: u64,
+    }
+
+    impl GroupsView {
+        fn new(update: &UpdateMessage) -> Self {
+            let processes_info = &update.processes_info;
+            // pages of each owner
+            let mut owner_pages: HashMap<u32, u64> = HashMap::new();
+            let (owners, rss_pages) = match &update.owners_overview {
+                Some(overview) => {
+                    for (owner, pages) in overview.values() {
+                        *owner_pages.entry(owner).or_default() += pages as u64;
+                    }
+                    (HashMap::new(), update.group_pages.clone())
+                }
+                None => {
+                    let owners = page_owners(processes_info);
+                    for &owner in owners.values() {
+                        *owner_pages.entry(owner).or_default() += 1;
+                    }
+                    (owners, group_pages(processes_info))
+                }
+            };
+
+            let names = group_names(processes_info);
+            let groups = names
+                .iter()
+                .enumerate()
+                .map(|(idx, &name)| GroupStats {
+                    color: rainbow_color(idx, names.len()),
+                    name: name.to_string(),
+                    processes: processes_info.iter().filter(|p| p.group == name).count(),
+                    rss_pages: rss_pages.get(name).copied().unwrap_or(0),
+                    exclusive_pages: owner_pages
+                        .get(&(FIRST_GROUP_OWNER + idx as u32))
+                        .copied()
+                        .unwrap_or(0),
+                })
+                .collect();
+
+            Self {
+                groups,
+                owners,
+                shared_pages: owner_pages.get(&SHARED_OWNER).copied().unwrap_or(0),
+                owned_pages: owner_pages
+                    .iter()
+                    .filter(|(&owner, _)| owner != NO_OWNER)
+                    .map(|(_, pages)| pages)
+                    .sum(),
+            }
+        }
+
+        fn color(&self, owner: u32) -> Color32 {
+            match owner {
+                NO_OWNER => NO_OWNER_COLOR,
+                SHARED_OWNER => SHARED_COLOR,
+                owner => self
+                    .groups
+                    .get((owner - FIRST_GROUP_OWNER) as usize)
+                    .map_or(NO_OWNER_COLOR, |group| group.color),
+            }
+        }
+    }
+
+    /// Distinct colours for `count` items
+    fn rainbow_color(idx: usize, count: usize) -> Color32 {
+        let [r, g, b, _] = colorgrad::preset::rainbow()
+            .at(idx as f32 / count as f32)
+            .to_rgba8();
+        Color32::from_rgb(r, g, b)
+    }
+
+    /// Sharing heatmap from kpagecount
+    struct MapCountView {
+        /// Pages by bucket of map count: 0, 1, 2-3, 4-7, ...
+        buckets: Vec<u64>,
+        /// Colour of each bucket, log scale
+        colors: Vec<Color32>,
+    }
+
+    impl MapCountView {
+        fn bucket(count: u64) -> usize {
+            (u64::BITS - count.leading_zeros()) as usize
+        }
+
+        /// `map_counts`: map count and number of pages
+        fn new(map_counts: impl Iterator<Item = (u64, u64)>) -> Self {
+            let mut buckets = vec![0];
+            for (count, pages) in map_counts {
+                let bucket = Self::bucket(count);
+                if bucket >= buckets.len() {
+                    buckets.resize(bucket + 1, 0);
+                }
+                buckets[bucket] += pages;
+            }
+
+            let gradient = colorgrad::preset::turbo();
+            let colors = (0..buckets.len())
+                .map(|bucket| {
+                    if bucket == 0 {
+                        return Color32::BLACK;
+                    }
+                    let t =
+                        (bucket - 1) as f32 / (buckets.len() - 1).saturating_sub(1).max(1) as f32;
+                    let [r, g, b, _] = gradient.at(t).to_rgba8();
+                    Color32::from_rgb(r, g, b)
+                })
+                .collect();
+
+            Self { buckets, colors }
+        }
+
+        fn color(&self, count: u64) -> Color32 {
+            self.colors[Self::bucket(count)]
+        }
+
+        fn bucket_name(bucket: usize) -> String {
+            match bucket {
+                0 => "0".to_string(),
+                1 => "1".to_string(),
+                bucket => format!("{}-{}", 1u64 << (bucket - 1), (1u64 << bucket) - 1),
+            }
+        }
+    }
+
+    struct CgroupStats {
+        inode: u64,
+        path: String,
+        pages: u64,
+        color: Color32,
+    }
+
+    /// Memory cgroups from kpagecgroup
+    struct CgroupsView {
+        /// Sorted by pages
+        cgroups: Vec<CgroupStats>,
+        colors: HashMap<u64, Color32>,
+    }
+
+    impl CgroupsView {
+        /// `memcgs`: memory cgroup inode and number of pages
+        fn new(
+            memcgs: impl Iterator<Item = (u64, u64)>,
+            cgroup_paths: &HashMap<u64, String>,
+        ) -> Self {
+            let mut pages: HashMap<u64, u64> = HashMap::new();
+            for (inode, inode_pages) in memcgs {
+                *pages.entry(inode).or_default() += inode_pages;
+            }
+
+            // colours by path, to keep them between updates
+            let mut cgroups: Vec<CgroupStats> = pages
+                .into_iter()
+                .map(|(inode, pages)| {
+                    let path = match (inode, cgroup_paths.get(&inode)) {
+                        (0, _) => "no cgroup".to_string(),
+                        (_, Some(path)) => path.clone(),
+                        // removed cgroup still charged for pages
+                        (_, None) => format!("inode {}", inode),
+                    };
+                    CgroupStats {
+                        inode,
+                        path,
+                        pages,
+                        color: NO_OWNER_COLOR,
+                    }
+                })
+                .collect();
+            cgroups.sort_by(|a, b| a.path.cmp(&b.path));
+            let count = cgroups.len();
+            for (idx, cgroup) in cgroups.iter_mut().enumerate() {
+                if cgroup.inode != 0 {
+                    cgroup.color = rainbow_color(idx, count);
+                }
+            }
+            cgroups.sort_by_key(|cgroup| std::cmp::Reverse(cgroup.pages));
+
+            let colors = cgroups
+                .iter()
+                .map(|cgroup| (cgroup.inode, cgroup.color))
+                .collect();
+            Self { cgroups, colors }
+        }
+
+        fn color(&self, inode: u64) -> Color32 {
+            self.colors.get(&inode).copied().unwrap_or(NO_OWNER_COLOR)
+        }
+    }
+
+    /// What pixels show
+    #[derive(Copy, Clone, PartialEq, Eq)]
+    enum ColorMode {
+        /// Colour layers
+        Flags,
+        Groups,
+        MapCount,
+        Cgroups,
+    }
+
+    /// Data for the legend of the current colour mode
+    enum ModeView {
+        Flags,
+        Groups(GroupsView),
+        MapCount(MapCountView),
+        Cgroups(CgroupsView),
+    }
+
+    impl ModeView {
+        /// Owners of each page in group mode, without overviews
+        fn owners(&self) -> Option<&HashMap<Pfn, u32>> {
+            match self {
+                ModeView::Groups(view) => Some(&view.owners),
+                _ => None,
+            }
+        }
+    }
+
+    /// Group view: pages mapped by several groups
+    const SHARED_COLOR: Color32 = Color32::WHITE;
+    /// Group view: pages not mapped by any process
+    const NO_OWNER_COLOR: Color32 = Color32::from_rgb(128, 128, 128);
+
+    /// Zone, node and memory block outlines
+    const OUTLINES: [(&str, Color32); 3] = [
+        ("zones", Color32::WHITE),
+        ("NUMA nodes", Color32::from_rgb(0, 255, 255)),
+        ("memory blocks", Color32::from_rgb(40, 40, 40)),
+    ];
+
+    /// PFN ranges of zones, nodes and memory blocks, in the same order as OUTLINES
+    fn layout_ranges(memory_layout: &MemoryLayout) -> [Vec<(Pfn, Pfn)>; 3] {
+        let zones: Vec<(Pfn, Pfn)> = memory_layout
+            .zones
+            .iter()
+            .map(|zone| (zone.start_pfn, zone.end_pfn))
+            .collect();
+        let nodes: Vec<(Pfn, Pfn)> = memory_layout
+            .nodes()
+            .into_iter()
+            .map(|(_, start, end)| (start, end))
+            .collect();
+        let blocks: Vec<(Pfn, Pfn)> = memory_layout
+            .memory_blocks
+            .iter()
+            .map(|block| (block.start_pfn, block.end_pfn))
+            .collect();
+
+        [zones, nodes, blocks].map(|mut ranges| {
+            ranges.sort();
+            ranges
+        })
+    }
+
+    /// Node, zone and memory block of a PFN
+    fn layout_text(memory_layout: &MemoryLayout, pfn: Pfn) -> String {
+        let zone = match memory_layout.zone_at(pfn) {
+            Some(zone) => format!("node {}, zone {}", zone.node, zone.name),
+            None => "unknown zone".to_string(),
+        };
+        match memory_layout.block_at(pfn) {
+            Some(block) => format!(
+                "{}, memory block {} ({})",
+                zone,
+                block.id,
+                if block.online { "online" } else { "offline" }
+            ),
+            None => zone,
+        }
+    }
+
+    fn mib(pages: u64, page_size: u64) -> String {
+        format!("{} MiB", pages * page_size / 1024 / 1024)
+    }
+
+    /// Legend of the current colour mode
+    fn show_mode_legend(
+        ui: &mut egui::Ui,
+        mode_view: &ModeView,
+        update: &UpdateMessage,
+        page_size: u64,
+    ) {
+        use egui_extras::{Column, TableBuilder};
+        let table = TableBuilder::new(ui)
+            .striped(true)
+            .resizable(false)
+            .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::auto())
+            .column(Column::remainder())
+            .min_scrolled_height(0.0)
+            .max_scroll_height(400.0);
+
+        match mode_view {
+            ModeView::Flags => {
+                ui.label("See Layers tab");
+            }
+            ModeView::Groups(groups_view) => {
+                let total_pages: u64 = update
+                    .memory_segments
+                    .iter()
+                    .map(|(start, end, _)| end.0 - start.0)
+                    .sum();
+
+                table
+                    .header(20.0, |mut header| {
+                        header.col(|ui| {
+                            ui.strong("Group");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Procs");
+                        });
+                        header.col(|ui| {
+                            ui.strong("RSS");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Exclusive");
+                        });
+                    })
+                    .body(|mut body| {
+                        for group in &groups_view.groups {
+                            body.row(20.0, |mut row| {
+                                row.col(|ui| {
+                                    ui.label(RichText::new(&group.name).color(group.color));
+                                });
+                                row.col(|ui| {
+                                    ui.label(format!("{}", group.processes));
+                                });
+                                row.col(|ui| {
+                                    ui.label(mib(group.rss_pages, page_size));
+                                });
+                                row.col(|ui| {
+                                    ui.label(mib(group.exclusive_pages, page_size));
+                                });
+                            });
+                        }
+                        for (name, owner, pages) in [
+                            ("shared", SHARED_OWNER, groups_view.shared_pages),
+                            (
+                                "no process",
+                                NO_OWNER,
+                                total_pages.saturating_sub(groups_view.owned_pages),
+                            ),
+                        ] {
+                            body.row(20.0, |mut row| {
+                                row.col(|ui| {
+                                    ui.label(RichText::new(name).color(groups_view.color(owner)));
+                                });
+                                row.col(|_ui| {});
+                                row.col(|_ui| {});
+                                row.col(|ui| {
+                                    ui.label(mib(pages, page_size));
+                                });
+                            });
+                        }
+                    });
+            }
+            ModeView::MapCount(map_count_view) => {
+                table
+                    .header(20.0, |mut header| {
+                        header.col(|ui| {
+                            ui.strong("Mappings");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Pages");
+                        });
+                        header.col(|_ui| {});
+                        header.col(|ui| {
+                            ui.strong("Size");
+                        });
+                    })
+                    .body(|mut body| {
+                        for (bucket, &pages) in map_count_view.buckets.iter().enumerate() {
+                            body.row(20.0, |mut row| {
+                                row.col(|ui| {
+                                    ui.label(
+                                        RichText::new(MapCountView::bucket_name(bucket))
+                                            .color(map_count_view.colors[bucket]),
+                                    );
+                                });
+                                row.col(|ui| {
+                                    ui.label(format!("{}", pages));
+                                });
+                                row.col(|_ui| {});
+                                row.col(|ui| {
+                                    ui.label(mib(pages, page_size));
+                                });
+                            });
+                        }
+                    });
+            }
+            ModeView::Cgroups(cgroups_view) => {
+                table
+                    .header(20.0, |mut header| {
+                        header.col(|ui| {
+                            ui.strong("Pages");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Size");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Inode");
+                        });
+                        header.col(|ui| {
+                            ui.strong("Cgroup");
+                        });
+                    })
+                    .body(|body| {
+                        body.rows(20.0, cgroups_view.cgroups.len(), |mut row| {
+                            let cgroup = &cgroups_view.cgroups[row.index()];
+                            row.col(|ui| {
+                                ui.label(format!("{}", cgroup.pages));
+                            });
+                            row.col(|ui| {
+                                ui.label(mib(cgroup.pages, page_size));
+                            });
+                            row.col(|ui| {
+                                ui.label(format!("{}", cgroup.inode));
+                            });
+                            row.col(|ui| {
+                                ui.label(RichText::new(&cgroup.path).color(cgroup.color));
+                            });
+                        });
+                    });
+            }
+        }
     }
 
-    pub fn server(socket: SocketAddr) {
-        let listener = TcpListener::bind(socket)
-            .unwrap_or_else(&|_| panic!("Can't bind to socket {}", socket));
-        println!("Listening on :{:?}", socket);
-
-        let (mut socket, _client_addr) = listener.accept().expect("Can't get client");
-
-        let iomem: Vec<PhysicalMemoryMap> = procfs::iomem()
-            .unwrap()
-            .iter()
-            .filter_map(|(ident, map)| if *ident == 0 { Some(map.clone()) } else { None })
-            .filter(|map| map.name == "System RAM")
-            .collect();
-
-        let mut kpageflags = procfs::KPageFlags::new().unwrap();
-
-        let mut processes_info = get_all_processes_info();
-        let mut memory_segments = get_memory_zones_flags(&iomem, &mut kpageflags);
-        let page_size = procfs::page_size();
-
-        let message = Message::FirstUpdate(FirstUpdateMessage {
-            page_size,
-            update_message: UpdateMessage {
-                processes_info,
-                memory_segments,
-                iomem: iomem.clone(),
-            },
-        });
-        message.send(&mut socket).unwrap();
-
-        let target_update_interval = 2.;
-        loop {
-            let chrono = Instant::now();
-            processes_info = get_all_processes_info();
-            memory_segments = get_memory_zones_flags(&iomem, &mut kpageflags);
+    /// Pages of the selected processes, over the mode colours
+    #[derive(Default)]
+    struct Highlighted {
+        /// Colour of each page, without overviews
+        ///
+        /// A page mapped by several selected processes gets the colour of the last one
+        pages: HashMap<Pfn, Color32>,
+        /// r, g, b and pages of the highlighted pages of each overview pixel
+        pixels: HashMap<u32, [u32; 4]>,
+    }
 
-            let message = Message::Update(UpdateMessage {
-                processes_info,
-                memory_segments,
-                iomem: iomem.clone(),
-            });
+    impl Highlighted {
+        fn new(processes_info: &[ProcessInfo], selected_pids: &[i32]) -> Self {
+            let mut highlighted = Self::default();
+            for (selection_idx, pid) in selected_pids.iter().enumerate() {
+                let Some(process_info) = processes_info.iter().find(|p| p.pid == *pid) else {
+                    continue;
+                };
+                let color = highlight_color(selection_idx);
+                for &pfn in &process_info.pfns {
+                    highlighted.pages.insert(pfn, color);
+                }
+                for &(pixel, pages) in &process_info.pixels {
+                    let sum = highlighted.pixels.entry(pixel).or_default();
+                    sum[0] += color.r() as u32 * pages;
+                    sum[1] += color.g() as u32 * pages;
+                    sum[2] += color.b() as u32 * pages;
+                    sum[3] += pages;
+                }
+            }
+            highlighted
+        }
+    }
 
-            let message_size = message.send(&mut socket).unwrap();
-            eprintln!("message_size: {} MiB", message_size / 1024 / 1024);
+    fn process_matches(process_info: &ProcessInfo, search: &str) -> bool {
+        let search = search.to_lowercase();
+        search.is_empty()
+            || process_info.pid.to_string().contains(&search)
+            || process_info
+                .exe
+                .to_string_lossy()
+                .to_lowercase()
+                .contains(&search)
+            || process_info.cmdline.to_lowercase().contains(&search)
+    }
 
-            let update_duration = chrono.elapsed();
-            dbg!(update_duration);
+    fn handshake(
+        socket: &mut dyn Stream,
+        reader: &mut MessageReader,
+        update_interval: Duration,
+    ) -> Result<ServerHelloMessage, Box<dyn std::error::Error>> {
+        Message::ClientHello(ClientHelloMessage {
+            protocol_version: PROTOCOL_VERSION,
+            update_interval,
+            token: std::env::var("AUTH_TOKEN").ok(),
+        })
+        .send(socket, Compression::None)?;
 
-            let sleep = (target_update_interval - update_duration.as_secs_f64()).max(0.);
-            eprintln!("Sleeping for {:.2}", sleep);
-            thread::sleep(Duration::from_secs_f64(sleep));
+        match reader.recv_timeout(socket, Duration::from_secs(5))? {
+            Message::ServerHello(hello) => Ok(hello),
+            Message::Error(e) => Err(format!("Server refused connection: {}", e).into()),
+            _ => Err("Expected ServerHello".into()),
         }
     }
-}
-
-mod client {
-    use std::{
-        net::{SocketAddr, TcpStream},
-        thread,
-        time::Instant,
-    };
 
-    use itertools::Itertools;
-    use macroquad::{
-        color::*,
-        input::{is_key_pressed, mouse_position, mouse_wheel, KeyCode},
-        math::Vec2,
-        miniquad::FilterMode,
-        texture::{draw_texture_ex, DrawTextureParams, Image, Texture2D},
-        window::{clear_background, next_frame},
-    };
-    use procfs_core::{process::Pfn, PhysicalMemoryMap, PhysicalPageFlags};
-    use snap::compute_compound_pages;
+    /// Timeline scrubber, pause and step controls
+    fn show_replay_control(egui_ctx: &egui::Context, control: &mut ReplayControl) {
+        let mut position = control.position.load(Ordering::SeqCst);
+        let last = control.timestamps.len() - 1;
 
-    use crate::{Message, ProcessInfo, UpdateMessage};
-    use egui_macroquad::egui::{self, RichText};
-    use egui_macroquad::egui::{Color32, TextWrapMode};
+        if is_key_pressed(KeyCode::Left) {
+            let _ = control.commands.send(ReplayCommand::Step(-1));
+        }
+        if is_key_pressed(KeyCode::Right) {
+            let _ = control.commands.send(ReplayCommand::Step(1));
+        }
 
-    fn recompute_rgb_data(
-        rgb_offsets: &mut [i8; 3],
-        rgb_flag_names: &mut [String; 3],
-        rgb_selector: usize,
-        flags_count: usize,
-    ) {
-        rgb_offsets[rgb_selector] = rgb_offsets[rgb_selector].rem_euclid(flags_count as i8);
-        rgb_flag_names[rgb_selector] = PhysicalPageFlags::all()
-            .iter_names()
-            .nth(rgb_offsets[rgb_selector] as usize)
-            .unwrap()
-            .0
-            .to_string();
-    }
+        egui::Window::new("replay").show(egui_ctx, |ui| {
+            ui.label(format!(
+                "Recording started at {} (unix time)",
+                control.start_time
+            ));
+            ui.label(format!(
+                "update {}/{}: {:.1}s / {:.1}s",
+                position,
+                last,
+                control.timestamps[position].as_secs_f64(),
+                control.timestamps[last].as_secs_f64()
+            ));
 
-    fn gen_image(
-        default_img: &Image,
-        memory_segments: &[(Pfn, Pfn, Vec<PhysicalPageFlags>)],
-        iomem: &[PhysicalMemoryMap],
-        order: u8,
-        r_flag: PhysicalPageFlags,
-        g_flag: PhysicalPageFlags,
-        b_flag: PhysicalPageFlags,
-    ) -> Image {
-        let mut img = default_img.clone();
-        let page_size = 4096; // TODO
+            // only seek when the slider is released, seeking can be slow
+            let slider = ui.add(egui::Slider::new(&mut position, 0..=last).show_value(false));
+            if slider.drag_stopped() || (slider.changed() && !slider.dragged()) {
+                let _ = control.commands.send(ReplayCommand::Seek(position));
+            }
 
-        for (start_pfn, end_pfn, flags) in memory_segments.iter() {
-            assert_eq!(end_pfn.0 - start_pfn.0, flags.len() as u64);
+            ui.horizontal(|ui| {
+                if ui.button("<").clicked() {
+                    let _ = control.commands.send(ReplayCommand::Step(-1));
+                }
+                if ui
+                    .button(if control.paused { "Play" } else { "Pause" })
+                    .clicked()
+                {
+                    control.paused ^= true;
+                    let _ = control.commands.send(ReplayCommand::Pause(control.paused));
+                }
+                if ui.button(">").clicked() {
+                    let _ = control.commands.send(ReplayCommand::Step(1));
+                }
 
-            for (pfn, &flag) in (start_pfn.0..end_pfn.0).zip(flags.iter()) {
-                let index = snap::pfn_to_index(iomem, page_size, Pfn(pfn)).unwrap();
+                egui::ComboBox::from_label("speed")
+                    .selected_text(format!("x{}", control.speed))
+                    .show_ui(ui, |ui| {
+                        for speed in [0.5, 1., 2., 5., 10., 50.] {
+                            if ui
+                                .selectable_value(&mut control.speed, speed, format!("x{}", speed))
+                                .changed()
+                            {
+                                let _ = control.commands.send(ReplayCommand::Speed(speed));
+                            }
+                        }
+                    });
+            });
+            ui.label("Left/Right arrows: previous/next update");
+        });
+    }
 
-                let pfn2 = snap::index_to_pfn(iomem, page_size, index).unwrap();
-                assert_eq!(Pfn(pfn), pfn2);
+    pub fn client(remote: Endpoint, update_interval: Duration) {
+        macroquad::Window::new("kpageflags-viewer", async_client(remote, update_interval));
+    }
 
-                let (x, y) = fast_hilbert::h2xy::<u64>(index.into(), order);
+    async fn async_client(remote: Endpoint, update_interval: Duration) {
+        let mut socket =
+            connect(&remote).unwrap_or_else(|e| panic!("Can't connect to {:?}: {}", remote, e));
+        let mut reader = MessageReader::default();
+        let server_hello =
+            handshake(&mut socket, &mut reader, update_interval).expect("Handshake failed");
+        eprintln!(
+            "Connected to {:?}, update interval {:?}",
+            remote, server_hello.update_interval
+        );
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let (requests_tx, requests_rx) = std::sync::mpsc::channel::<Message>();
 
-                let mut c = [0u8, 0, 0];
-                if flag.contains(r_flag) {
-                    c[0] = 255;
-                } else {
-                    c[0] = 0;
+        // deltas must not be dropped, block until the UI takes the message
+        let socket_thread = thread::spawn(move || loop {
+            for request in requests_rx.try_iter() {
+                if let Err(e) = request.send(&mut socket, Compression::None) {
+                    eprintln!("Connection to server lost: {}", e);
+                    return;
                 }
-                if flag.contains(g_flag) {
-                    c[1] = 255;
-                } else {
-                    c[1] = 0;
+            }
+            match reader.poll(&mut socket) {
+                Ok(Some(message)) => {
+                    let finish = matches!(message, Message::Finish | Message::Error(_));
+                    if tx.send(message).is_err() || finish {
+                        break;
+                    }
                 }
-                if flag.contains(b_flag) {
-                    c[2] = 255;
-                } else {
-                    c[2] = 0;
+                Ok(None) => (),
+                Err(e) => {
+                    eprintln!("Connection to server lost: {}", e);
+                    break;
                 }
-
-                let color = Color::from_rgba(c[0], c[1], c[2], 255);
-                img.set_pixel(x as u32, y as u32, color);
             }
-        }
+        });
 
-        img
-    }
+        run(rx, Some(requests_tx), None).await;
 
-    pub fn client(remote: SocketAddr) {
-        macroquad::Window::new("kpageflags-viewer", async_client(remote));
+        socket_thread.join().unwrap();
     }
 
-    async fn async_client(remote: SocketAddr) {
-        let page_size = 4096;
+    pub fn replay(path: PathBuf, speed: f64) {
+        macroquad::Window::new("kpageflags-viewer replay", async_replay(path, speed));
+    }
 
-        let mut socket =
-            TcpStream::connect_timeout(&remote, std::time::Duration::from_secs(5)).unwrap();
+    async fn async_replay(path: PathBuf, speed: f64) {
+        let recording =
+            Recording::open(&path).unwrap_or_else(|e| panic!("Can't open {:?}: {}", path, e));
         let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let (commands_tx, commands_rx) = std::sync::mpsc::channel();
+        let position = Arc::new(AtomicUsize::new(0));
 
-        let socket_thread = thread::spawn(move || loop {
-            let message = Message::recv(&mut socket).unwrap();
-            tx.try_send(message).unwrap();
+        let control = ReplayControl {
+            start_time: recording.start_time,
+            timestamps: recording
+                .index
+                .iter()
+                .map(|record| record.timestamp)
+                .collect(),
+            position: position.clone(),
+            commands: commands_tx,
+            paused: false,
+            speed,
+        };
+        let replay_thread = thread::spawn(move || {
+            if let Err(e) = recording::replay(recording, tx, commands_rx, position, speed) {
+                eprintln!("Replay failed: {}", e);
+            }
         });
 
-        let mut default_img: Option<Image> = None;
-        let mut img: Option<Image> = None;
+        run(rx, None, Some(control)).await;
+
+        // dropping the controls stops the replay thread
+        replay_thread.join().unwrap();
+    }
+
+    /// Display messages from a server or a recording
+    ///
+    /// Tiles of an overview are requested with `requests`, recordings have the flags of all pages
+    async fn run(
+        rx: Receiver<Message>,
+        requests: Option<Sender<Message>>,
+        mut replay_control: Option<ReplayControl>,
+    ) {
+        // from the first update
+        let mut page_size = 0;
+
+        // whole map, coarser than the pages on large hosts, see detail_level
         let mut texture: Option<Texture2D> = None;
+        let mut level = 0;
+        // full resolution tiles of the viewport, when zoomed in past the overview
+        let mut tiles: HashMap<(u32, u32), Texture2D> = HashMap::new();
+        // layers of the tiles sent by the server, by first page index, and pending requests
+        let mut tile_data: HashMap<u64, TileMessage> = HashMap::new();
+        let mut requested_tiles: HashSet<u64> = HashSet::new();
+        // selected processes when the tiles were requested
+        let mut tile_pids: Vec<i32> = Vec::new();
 
         let mut zoom = 1.;
         let mut canvas_offset = Vec2::new(0., 0.);
         let canvas_size = Vec2::new(600., 600.);
         let mut _autorefresh = true;
 
-        // TODO: move flags to server
-        let flags_count = PhysicalPageFlags::all().iter().count();
-        let mut rgb_offsets = [26i8, 12, 10]; // default view
-        let mut rgb_flag_names = [String::new(), String::new(), String::new()];
+        let mut layers_editor = LayersEditor {
+            layers: default_layers(),
+            presets: load_presets().unwrap_or_else(|e| {
+                eprintln!("Can't load presets from {:?}: {}", presets_path(), e);
+                BTreeMap::new()
+            }),
+            preset_name: String::new(),
+            status: String::new(),
+        };
+        let mut compiled_layers = compile_layers(&layers_editor.layers);
 
         let mut order: Option<u8> = None;
 
@@ -372,12 +4129,33 @@ mod client {
         #[derive(Copy, Clone, PartialEq, Eq)]
         enum DisplayTab {
             Info,
+            Layers,
+            Map,
+            Processes,
             Help,
             Stats,
         }
         let mut tab = DisplayTab::Info;
 
+        let mut search = String::new();
+        // in selection order, to keep the same colours
+        let mut selected_pids: Vec<i32> = Vec::new();
+        // set by the UI, after the image is generated
+        let mut redraw = false;
+
+        let mut memory_layout = MemoryLayout::default();
+        let mut outline_ranges: [Vec<(Pfn, Pfn)>; 3] = Default::default();
+        let mut outlines: [Vec<(u32, u32)>; 3] = Default::default();
+        let mut show_outlines = [true, true, false];
+        let mut hovered_pfn: Option<Pfn> = None;
+
+        let mut color_mode = ColorMode::Flags;
+        let mut mode_view = ModeView::Flags;
+        let mut highlighted = Highlighted::default();
+
         let mut changed = false;
+        // a delta couldn't be applied, deltas are ignored until the next complete update
+        let mut sync_error: Option<String> = None;
 
         'mainloop: loop {
             let chrono = Instant::now();
@@ -386,13 +4164,10 @@ mod client {
             let (_mouse_wheel_x, mouse_wheel_y) = mouse_wheel();
 
             let mouse_screen = Vec2::new(mouse_x, mouse_y);
-            //mouse_world = (mouse_screen - canvas_offset) / zoom / canvas_size
-            //    * Vec2::new(img.width() as f32, img.height() as f32);
-            mouse_world = default_img
-                .as_ref()
-                .map(|img| {
-                    (mouse_screen - canvas_offset) / zoom / canvas_size
-                        * Vec2::new(img.width() as f32, img.height() as f32)
+            // in pages, on the full resolution curve
+            mouse_world = order
+                .map(|order| {
+                    (mouse_screen - canvas_offset) / zoom / canvas_size * 2f32.powi(order as i32)
                 })
                 .unwrap_or_default();
 
@@ -413,93 +4188,168 @@ mod client {
                 _autorefresh ^= true;
             }
 
-            if let Ok(message) = rx.try_recv() {
+            while let Ok(message) = rx.try_recv() {
                 match message {
                     Message::FirstUpdate(message) => {
-                        update = Some(message.update_message);
-
-                        let pfns = snap::get_pfn_count(&update.as_ref().unwrap().iomem);
-                        let order_f64 = (pfns as f64).log2() / 2.;
-                        order = Some(order_f64.ceil() as u8);
+                        update = Some(Arc::unwrap_or_clone(message.update_message));
 
-                        default_img = Some(Image::gen_image_color(
-                            2u16.pow(order.unwrap() as u32),
-                            2u16.pow(order.unwrap() as u32),
-                            Color::from_rgba(79, 79, 79, 255),
-                        ));
+                        page_size = message.page_size;
+                        order = Some(map_order(&update.as_ref().unwrap().iomem, page_size));
 
-                        for (start, end, _) in &update.as_ref().unwrap().memory_segments {
-                            for pfn in start.0..end.0 {
-                                let index = snap::pfn_to_index(
-                                    &update.as_ref().unwrap().iomem,
-                                    page_size,
-                                    Pfn(pfn),
-                                )
-                                .unwrap();
-                                let (x, y) =
-                                    fast_hilbert::h2xy::<u64>(index.into(), order.unwrap());
-
-                                default_img.as_mut().unwrap().set_pixel(
-                                    x as u32,
-                                    y as u32,
-                                    Color::from_rgba(64, 64, 64, 255),
-                                );
-                            }
-                        }
+                        level = detail_level(order.unwrap());
 
-                        // first loop
-                        for i in 0..3 {
-                            recompute_rgb_data(
-                                &mut rgb_offsets,
-                                &mut rgb_flag_names,
-                                i,
-                                flags_count,
-                            );
-                        }
+                        sync_error = None;
+                        memory_layout = message.memory_layout;
+                        outline_ranges = layout_ranges(&memory_layout);
+                        outlines = outline_ranges.each_ref().map(|ranges| {
+                            outline_pixels(
+                                &update.as_ref().unwrap().iomem,
+                                page_size,
+                                order.unwrap(),
+                                level,
+                                ranges,
+                            )
+                        });
 
-                        img = default_img.clone();
-                        texture = Some(Texture2D::from_image(img.as_ref().unwrap()));
+                        // the first update has full data
+                        changed = true;
                     }
                     Message::Update(message) => {
-                        update = Some(message);
+                        update = Some(Arc::unwrap_or_clone(message));
+                        sync_error = None;
                         // TODO: update image in egui
                         changed = true;
                     }
+                    Message::Delta(_) if sync_error.is_some() => (),
+                    Message::Delta(delta) => {
+                        let result = match update.as_mut() {
+                            Some(update) => update.apply_delta(*delta),
+                            None => Err("Delta before first update".into()),
+                        };
+                        match result {
+                            Ok(()) => changed = true,
+                            Err(e) => {
+                                eprintln!("Can't apply update: {}", e);
+                                sync_error = Some(e.to_string());
+                                // a recording has regular keyframes
+                                if let Some(requests) = &requests {
+                                    let _ = requests.send(Message::KeyframeRequest);
+                                }
+                            }
+                        }
+                    }
                     Message::Finish => {
+                        eprintln!("Server is shutting down");
+                        break 'mainloop;
+                    }
+                    Message::Error(e) => {
+                        eprintln!("Server error: {}", e);
                         break 'mainloop;
                     }
+                    Message::Tile(tile) => {
+                        // requested before the selection changed
+                        if tile
+                            .processes
+                            .iter()
+                            .map(|(pid, _)| *pid)
+                            .ne(tile_pids.iter().copied())
+                        {
+                            continue;
+                        }
+                        requested_tiles.remove(&tile.index);
+                        tile_data.insert(tile.index, tile);
+                        // generated on the next frame
+                    }
+                    Message::ClientHello(_)
+                    | Message::ServerHello(_)
+                    | Message::TileRequest(_)
+                    | Message::KeyframeRequest => {
+                        eprintln!("Unexpected message");
+                    }
+                }
+                // one update per frame
+                if changed {
+                    break;
                 }
             }
 
             clear_background(DARKGRAY);
 
-            let r_flag = PhysicalPageFlags::all()
-                .iter()
-                .nth(rgb_offsets[0] as usize)
-                .unwrap();
-            let g_flag = PhysicalPageFlags::all()
-                .iter()
-                .nth(rgb_offsets[1] as usize)
-                .unwrap();
-            let b_flag = PhysicalPageFlags::all()
-                .iter()
-                .nth(rgb_offsets[2] as usize)
-                .unwrap();
+            // the update may be partially applied, keep the previous image
+            if (changed || redraw) && sync_error.is_none() {
+                let update = update.as_ref().unwrap();
+                selected_pids.retain(|pid| update.processes_info.iter().any(|p| p.pid == *pid));
+
+                // the server may not send extra layers
+                color_mode = match color_mode {
+                    ColorMode::MapCount if !update.has_map_counts() => ColorMode::Flags,
+                    ColorMode::Cgroups if !update.has_memcgs() => ColorMode::Flags,
+                    color_mode => color_mode,
+                };
+
+                mode_view = match color_mode {
+                    ColorMode::Flags => ModeView::Flags,
+                    ColorMode::Groups => ModeView::Groups(GroupsView::new(update)),
+                    ColorMode::MapCount => ModeView::MapCount(match &update.map_counts_overview {
+                        Some(overview) => {
+                            MapCountView::new(overview.values().map(|(c, p)| (c, p as u64)))
+                        }
+                        None => MapCountView::new(
+                            update
+                                .map_counts
+                                .iter()
+                                .flatten()
+                                .flatten()
+                                .map(|&c| (c, 1)),
+                        ),
+                    }),
+                    ColorMode::Cgroups => ModeView::Cgroups(match &update.memcgs_overview {
+                        Some(overview) => CgroupsView::new(
+                            overview.values().map(|(m, p)| (m, p as u64)),
+                            &update.cgroup_paths,
+                        ),
+                        None => CgroupsView::new(
+                            update.memcgs.iter().flatten().flatten().map(|&m| (m, 1)),
+                            &update.cgroup_paths,
+                        ),
+                    }),
+                };
+                highlighted = Highlighted::new(&update.processes_info, &selected_pids);
 
-            if changed {
-                img = Some(gen_image(
-                    default_img.as_ref().unwrap(),
-                    &update.as_ref().unwrap().memory_segments,
-                    &update.as_ref().unwrap().iomem,
+                let colors = PageColors {
+                    mode_view: &mode_view,
+                    layers: &compiled_layers,
+                };
+                let mut new_img = gen_overview(
+                    &colors,
+                    update,
+                    &highlighted,
+                    page_size,
                     order.unwrap(),
-                    r_flag,
-                    g_flag,
-                    b_flag,
-                ));
-                texture = Some(Texture2D::from_image(img.as_ref().unwrap()));
+                    level,
+                );
+                for ((_, color), (pixels, &show)) in
+                    OUTLINES.iter().zip(outlines.iter().zip(&show_outlines))
+                {
+                    if !show {
+                        continue;
+                    }
+                    for &(x, y) in pixels {
+                        new_img.set_pixel(x, y, to_color(*color));
+                    }
+                }
+                texture = Some(Texture2D::from_image(&new_img));
+                tiles.clear();
+                // tiles have the pages of the selected processes
+                if changed || tile_pids != selected_pids {
+                    tile_data.clear();
+                    requested_tiles.clear();
+                    tile_pids = selected_pids.clone();
+                }
+                redraw = false;
             }
 
-            if img.is_none() {
+            if texture.is_none() {
                 // wait for first image
                 next_frame().await;
                 continue;
@@ -521,60 +4371,131 @@ mod client {
                 WHITE,
                 params,
             );
+
+            // screen pixels per page
+            let page_px = canvas_size.x * zoom / 2f32.powi(order.unwrap() as i32);
+            if level > 0 && page_px >= 1. {
+                let order = order.unwrap();
+                let tile_px = page_px * 2f32.powi(TILE_ORDER as i32);
+                let tiles_per_side = 2u32.pow((order - TILE_ORDER) as u32);
+                let visible = |offset: f32, screen: f32| {
+                    let first = (-offset / tile_px).floor().max(0.) as u32;
+                    let last = ((screen - offset) / tile_px)
+                        .ceil()
+                        .clamp(0., tiles_per_side as f32) as u32;
+                    first..last
+                };
+                let visible_tiles: Vec<(u32, u32)> = visible(canvas_offset.y, screen_height())
+                    .cartesian_product(visible(canvas_offset.x, screen_width()))
+                    .map(|(ty, tx)| (tx, ty))
+                    .collect();
+                tiles.retain(|tile, _| visible_tiles.contains(tile));
+                let visible_indices: HashSet<u64> = visible_tiles
+                    .iter()
+                    .map(|&tile| tile_index(order, tile))
+                    .collect();
+                tile_data.retain(|index, _| visible_indices.contains(index));
+                // only overviews were sent, layers of tiles are requested
+                let has_pages = update.as_ref().unwrap().flags_overview.is_none();
+
+                let colors = PageColors {
+                    mode_view: &mode_view,
+                    layers: &compiled_layers,
+                };
+                let shown_outlines: Vec<(&[(Pfn, Pfn)], Color32)> = outline_ranges
+                    .iter()
+                    .zip(OUTLINES)
+                    .zip(show_outlines)
+                    .filter(|(_, show)| *show)
+                    .map(|((ranges, (_, color)), _)| (ranges.as_slice(), color))
+                    .collect();
+                let mut generated = 0;
+                for tile in visible_tiles {
+                    let texture = match tiles.entry(tile) {
+                        Entry::Occupied(entry) => entry.into_mut(),
+                        // the overview stays visible until the tile is ready
+                        Entry::Vacant(_) if generated == TILES_PER_FRAME => continue,
+                        Entry::Vacant(entry) => {
+                            let index = tile_index(order, tile);
+                            let data = tile_data.get(&index);
+                            if !has_pages && data.is_none() {
+                                if let Some(requests) = &requests {
+                                    if requested_tiles.insert(index) {
+                                        let _ = requests.send(Message::TileRequest(
+                                            TileRequestMessage {
+                                                index,
+                                                pages: 1 << (2 * TILE_ORDER),
+                                                pids: tile_pids.clone(),
+                                            },
+                                        ));
+                                    }
+                                }
+                                continue;
+                            }
+                            generated += 1;
+                            let texture = Texture2D::from_image(&gen_tile(
+                                &colors,
+                                update.as_ref().unwrap(),
+                                &highlighted,
+                                page_size,
+                                order,
+                                tile,
+                                data,
+                                &shown_outlines,
+                            ));
+                            texture.set_filter(FilterMode::Nearest);
+                            entry.insert(texture)
+                        }
+                    };
+                    draw_texture_ex(
+                        texture,
+                        canvas_offset.x + tile.0 as f32 * tile_px,
+                        canvas_offset.y + tile.1 as f32 * tile_px,
+                        WHITE,
+                        DrawTextureParams {
+                            dest_size: Some(Vec2::splat(tile_px)),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
             let _elapsed = chrono.elapsed();
 
             egui_macroquad::ui(|egui_ctx| {
+                if let Some(replay_control) = replay_control.as_mut() {
+                    show_replay_control(egui_ctx, replay_control);
+                }
+
                 egui_macroquad::egui::Window::new("kpageflags").show(egui_ctx, |ui| {
+                    if let Some(e) = &sync_error {
+                        ui.colored_label(
+                            Color32::RED,
+                            format!("Out of sync, waiting for a complete update: {}", e),
+                        );
+                    }
                     ui.horizontal(|ui| {
                         ui.selectable_value(&mut tab, DisplayTab::Info, "Info");
+                        ui.selectable_value(&mut tab, DisplayTab::Layers, "Layers");
+                        ui.selectable_value(&mut tab, DisplayTab::Map, "Map");
+                        ui.selectable_value(&mut tab, DisplayTab::Processes, "Processes");
                         ui.selectable_value(&mut tab, DisplayTab::Stats, "Stats");
                         ui.selectable_value(&mut tab, DisplayTab::Help, "Help");
                     });
 
                     match tab {
                         DisplayTab::Info => {
-                            for ((i, &color), &color_name) in (0..3)
-                                .zip(&[Color32::RED, Color32::GREEN, Color32::BLUE])
-                                .zip(&["Red", "Green", "Blue"])
-                            {
-                                egui::ComboBox::from_label(color_name)
-                                    .selected_text(
-                                        RichText::new(
-                                            PhysicalPageFlags::all()
-                                                .iter_names()
-                                                .nth(rgb_offsets[i] as usize)
-                                                .map(|(name, _)| name)
-                                                .unwrap(),
-                                        )
-                                        .color(color),
-                                    )
-                                    .show_ui(ui, |ui| {
-                                        ui.style_mut().wrap_mode = Some(TextWrapMode::Extend);
-                                        ui.set_min_width(60.0);
-
-                                        for (idx, flag_name) in PhysicalPageFlags::all()
-                                            .iter_names()
-                                            .map(|(flag_name, _)| flag_name)
-                                            .enumerate()
-                                        {
-                                            if ui
-                                                .selectable_value(
-                                                    &mut rgb_offsets[i],
-                                                    idx as i8,
-                                                    flag_name,
-                                                )
-                                                .changed()
-                                            {
-                                                // update image
-                                            }
-                                        }
-                                    });
-                                //ui.label("");
-                            }
-
-                            ui.separator();
-
+                            ui.label(format!(
+                                "hovered pfn: {}",
+                                match hovered_pfn {
+                                    Some(pfn) =>
+                                        format!("{} ({})", pfn.0, layout_text(&memory_layout, pfn)),
+                                    None => "None".to_string(),
+                                }
+                            ));
                             ui.label(format!("pfn: {:?}", pfn.map(|pfn| pfn.0)));
+                            if let Some(pfn) = pfn {
+                                ui.label(layout_text(&memory_layout, pfn));
+                            }
 
                             if let Some(pfn) = pfn {
                                 // mouse is over canvas AND RAM
@@ -588,24 +4509,91 @@ mod client {
                                             .copied();
                                     }
                                 }
+                                // with overviews, only the layers of loaded tiles are known
+                                let current = update.as_ref().unwrap();
+                                let overview = current.flags_overview.is_some();
+                                let index = snap::pfn_to_index(&current.iomem, page_size, pfn);
+                                let tile_page = index.filter(|_| overview).and_then(|index| {
+                                    let tile = index >> (2 * TILE_ORDER) << (2 * TILE_ORDER);
+                                    PageValues::from_tile(
+                                        tile_data.get(&tile)?,
+                                        (index - tile) as usize,
+                                    )
+                                });
+                                if overview {
+                                    flags = tile_page.map(|page| page.flags);
+                                }
 
                                 let flags_text: String = if let Some(flags) = flags {
                                     flags.iter_names().map(|(flag_name, _)| flag_name).join(" ")
+                                } else if overview {
+                                    "unknown, zoom in to load the flags of each page".into()
                                 } else {
                                     // TODO: should be unreachable somehow
                                     "NOT IN RAM?".into()
                                 };
                                 ui.label(format!("flags: {}", flags_text));
 
-                                let processes: Vec<&ProcessInfo> = update
-                                    .as_ref()
-                                    .unwrap()
+                                let page = tile_page.or_else(|| {
+                                    let (segment_idx, offset) =
+                                        page_position(&current.memory_segments, pfn)?;
+                                    Some(PageValues::new(current, None, segment_idx, offset, pfn))
+                                });
+                                if let Some(page) = page {
+                                    if current.has_map_counts() {
+                                        ui.label(format!("map count: {}", page.map_count));
+                                    }
+                                    if current.has_memcgs() {
+                                        ui.label(format!(
+                                            "cgroup: {} (inode {})",
+                                            current
+                                                .cgroup_paths
+                                                .get(&page.memcg)
+                                                .map(|path| path.as_str())
+                                                .unwrap_or("?"),
+                                            page.memcg
+                                        ));
+                                    }
+                                }
+
+                                let layer = flags.and_then(|flags| {
+                                    layers_editor.layers.iter().find(|layer| {
+                                        layer.enabled
+                                            && layer
+                                                .expr
+                                                .parse::<FlagExpr>()
+                                                .is_ok_and(|expr| expr.eval(flags))
+                                    })
+                                });
+                                if let Some(layer) = layer {
+                                    let [r, g, b] = layer.color;
+                                    ui.label(
+                                        RichText::new(format!("layer: {}", layer.expr))
+                                            .color(Color32::from_rgb(r, g, b)),
+                                    );
+                                }
+
+                                // with overviews, processes mapping pages of the pixel
+                                let pixel = index.map(|index| (index >> (2 * level)) as u32);
+                                let processes: Vec<&ProcessInfo> = current
                                     .processes_info
                                     .iter()
-                                    .filter(|proc_info| proc_info.pfns.contains(&pfn))
+                                    .filter(|proc_info| match (overview, pixel) {
+                                        (true, Some(pixel)) => proc_info
+                                            .pixels
+                                            .binary_search_by_key(&pixel, |&(pixel, _)| pixel)
+                                            .is_ok(),
+                                        _ => proc_info.pfns.contains(&pfn),
+                                    })
                                     .collect();
 
                                 ui.separator();
+                                if overview {
+                                    ui.label(format!(
+                                        "Processes with pages in the {} pages around",
+                                        4u64.pow(level as u32)
+                                    ));
+                                }
 
                                 use egui_extras::{Column, TableBuilder};
                                 let table = TableBuilder::new(ui)
@@ -640,39 +4628,200 @@ mod client {
                             }
 
                             // see https://github.com/optozorax/egui-macroquad/issues/26
+                            let side = 2f32.powi(order.unwrap() as i32);
                             if mouse_world.x >= Vec2::ZERO.x
                                 && mouse_world.y >= Vec2::ZERO.y
-                                && mouse_world.x < img.as_ref().unwrap().width() as f32
-                                && mouse_world.y < img.as_ref().unwrap().height() as f32
+                                && mouse_world.x < side
+                                && mouse_world.y < side
                                 && !egui_ctx.wants_pointer_input()
                                 && !egui_ctx.is_pointer_over_area()
                             {
                                 // mouse is over a canvas
+                                let index = fast_hilbert::xy2h::<u64>(
+                                    mouse_world.x as u64,
+                                    mouse_world.y as u64,
+                                    order.unwrap(),
+                                ) as u64;
+                                hovered_pfn = snap::index_to_pfn(
+                                    &update.as_ref().unwrap().iomem,
+                                    page_size,
+                                    index,
+                                );
 
                                 if macroquad::input::is_mouse_button_down(
                                     macroquad::miniquad::MouseButton::Left,
                                 ) {
-                                    let index = fast_hilbert::xy2h::<u64>(
-                                        mouse_world.x as u64,
-                                        mouse_world.y as u64,
-                                        order.unwrap(),
-                                    ) as u64;
-                                    pfn = snap::index_to_pfn(
-                                        &update.as_ref().unwrap().iomem,
-                                        page_size,
-                                        index,
-                                    );
+                                    pfn = hovered_pfn;
+                                }
+                            } else {
+                                hovered_pfn = None;
+                            }
+                        }
+                        DisplayTab::Layers => {
+                            if show_layers_editor(ui, &mut layers_editor) {
+                                compiled_layers = compile_layers(&layers_editor.layers);
+                                redraw = true;
+                            }
+                        }
+                        DisplayTab::Map => {
+                            let update = update.as_ref().unwrap();
+                            ui.horizontal(|ui| {
+                                ui.label("Colour by:");
+                                for (mode, name, available) in [
+                                    (ColorMode::Flags, "flags", true),
+                                    (ColorMode::Groups, "groups", true),
+                                    (ColorMode::MapCount, "sharing", update.has_map_counts()),
+                                    (ColorMode::Cgroups, "cgroups", update.has_memcgs()),
+                                ] {
+                                    if ui
+                                        .add_enabled(
+                                            available,
+                                            egui::SelectableLabel::new(color_mode == mode, name),
+                                        )
+                                        .on_disabled_hover_text("Not sent by the server, see EXTRA_LAYERS")
+                                        .clicked()
+                                    {
+                                        color_mode = mode;
+                                        redraw = true;
+                                    }
+                                }
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Outlines:");
+                                for ((name, color), show) in
+                                    OUTLINES.iter().zip(show_outlines.iter_mut())
+                                {
+                                    if ui
+                                        .checkbox(show, RichText::new(*name).color(*color))
+                                        .changed()
+                                    {
+                                        redraw = true;
+                                    }
                                 }
+                            });
+                            if level > 0 {
+                                ui.label(format!(
+                                    "Overview: 1 pixel = {} pages, full resolution when zoomed in",
+                                    4u64.pow(level as u32)
+                                ));
+                            }
+                            ui.separator();
+                            show_mode_legend(ui, &mode_view, update, page_size);
+                        }
+                        DisplayTab::Processes => {
+                            let processes_info = &update.as_ref().unwrap().processes_info;
+
+                            // legend
+                            for (selection_idx, pid) in selected_pids.iter().enumerate() {
+                                let Some(process_info) =
+                                    processes_info.iter().find(|p| p.pid == *pid)
+                                else {
+                                    continue;
+                                };
+                                ui.label(
+                                    RichText::new(format!(
+                                        "{} {}: {} pages ({} MiB)",
+                                        process_info.pid,
+                                        process_info.exe.to_string_lossy(),
+                                        process_info.pages(),
+                                        process_info.pages() * page_size / 1024 / 1024
+                                    ))
+                                    .color(highlight_color(selection_idx)),
+                                );
+                            }
+                            if !selected_pids.is_empty() && ui.button("Clear selection").clicked()
+                            {
+                                selected_pids.clear();
+                                redraw = true;
                             }
+
+                            ui.separator();
+
+                            ui.horizontal(|ui| {
+                                ui.label("Search:");
+                                ui.text_edit_singleline(&mut search);
+                            });
+
+                            let mut processes: Vec<&ProcessInfo> = processes_info
+                                .iter()
+                                .filter(|p| process_matches(p, &search))
+                                .collect();
+                            processes.sort_by_key(|p| std::cmp::Reverse(p.pages()));
+
+                            use egui_extras::{Column, TableBuilder};
+                            let table = TableBuilder::new(ui)
+                                .striped(true)
+                                .resizable(false)
+                                .cell_layout(egui::Layout::left_to_right(egui::Align::Center))
+                                .column(Column::auto())
+                                .column(Column::auto())
+                                .column(Column::auto())
+                                .column(Column::remainder().clip(true))
+                                .min_scrolled_height(0.0)
+                                .max_scroll_height(400.0);
+
+                            table
+                                .header(20.0, |mut header| {
+                                    header.col(|ui| {
+                                        ui.strong("PID");
+                                    });
+                                    header.col(|ui| {
+                                        ui.strong("Pages");
+                                    });
+                                    header.col(|ui| {
+                                        ui.strong("exe");
+                                    });
+                                    header.col(|ui| {
+                                        ui.strong("cmdline");
+                                    });
+                                })
+                                .body(|body| {
+                                    body.rows(20.0, processes.len(), |mut row| {
+                                        let proc = processes[row.index()];
+                                        let mut selected = selected_pids.contains(&proc.pid);
+                                        row.col(|ui| {
+                                            if ui
+                                                .checkbox(&mut selected, format!("{}", proc.pid))
+                                                .changed()
+                                            {
+                                                if selected {
+                                                    selected_pids.push(proc.pid);
+                                                } else {
+                                                    selected_pids.retain(|pid| *pid != proc.pid);
+                                                }
+                                                redraw = true;
+                                            }
+                                        });
+                                        row.col(|ui| {
+                                            ui.label(format!("{}", proc.pages()));
+                                        });
+                                        row.col(|ui| {
+                                            ui.label(proc.exe.to_string_lossy());
+                                        });
+                                        row.col(|ui| {
+                                            ui.label(&proc.cmdline);
+                                        });
+                                    });
+                                });
                         }
                         DisplayTab::Help => {
-                            ui.label("Left click to select page");
+                            ui.label("Left click to select page, the Info tab shows the node, zone and memory block of the hovered and selected pages");
+                            ui.label("Layers tab: pages are coloured by the first matching flag expression, e.g. ANON & !SWAPBACKED. Operators, by precedence: ! & | and ()");
+                            ui.label("Map tab: colour pages by flags, process group (GROUP_BY on the server), sharing count or memory cgroup (EXTRA_LAYERS on the server). Zones, NUMA nodes and memory blocks boundaries can be outlined");
+                            ui.label("Processes tab: search and select processes to highlight their pages");
+                            ui.label("On large hosts, a pixel of the map is a square of pages, with their average colour. Zooming in shows each page, the Info tab shows the layers of the pages of loaded tiles and the processes mapping pages of the hovered pixel");
                         }
                         DisplayTab::Stats => {
-                            // memory_segments[2] == normal zone
-                            let stats = compute_compound_pages(
-                                &update.as_ref().unwrap().memory_segments[2].2,
-                            );
+                            let stats = match &update.as_ref().unwrap().flags_overview {
+                                Some(overview) => {
+                                    ui.label("Whole RAM, from the overview: tail pages of compound pages are not merged with their head");
+                                    overview_flag_counts(overview)
+                                }
+                                // memory_segments[2] == normal zone
+                                None => compute_compound_pages(
+                                    &update.as_ref().unwrap().memory_segments[2].2,
+                                ),
+                            };
 
                             use egui_extras::{Column, TableBuilder};
                             let table = TableBuilder::new(ui)
@@ -710,7 +4859,7 @@ mod client {
                                             row.col(|ui| {
                                                 ui.label(format!(
                                                     "{} MiB",
-                                                    stat * 4096 / 1024 / 1024
+                                                    stat * page_size / 1024 / 1024
                                                 ));
                                             });
                                         });
@@ -733,8 +4882,6 @@ mod client {
 
             changed = false;
         }
-
-        socket_thread.join().unwrap();
     }
 }
 
@@ -746,8 +4893,20 @@ fn main() {
     let usage = format!(
         "Usage:
 local mode (Linux only): {exe}
-server     (Linux only): {exe} server <listen socket>
-client  (Windows/Linux): {exe} client <remote client>"
+server     (Linux only): {exe} server <endpoint>
+client  (Windows/Linux): {exe} client <endpoint> [update interval in seconds]
+record     (Linux only): {exe} record <file> [update interval in seconds]
+replay  (Windows/Linux): {exe} replay <file> [speed]
+
+endpoint: host:port (TLS), tcp://127.0.0.1:port (plain TCP, loopback only), unix:/path/to/socket
+
+env variables:
+  AUTH_TOKEN: shared secret. Generated and printed by the server if not set (optional for Unix sockets)
+  TLS_CERT, TLS_KEY: server certificate and key (PEM). Generated if not set
+  TLS_FINGERPRINT or TLS_CERT: client side, expected server certificate
+  EXTRA_LAYERS: server side, also send kpagecount and/or kpagecgroup (comma separated)
+  GROUP_BY: server side, processes groups: uid (default), env:<VAR>, custom:<memstats filters>
+  PRESETS_FILE: client side, colour layers presets (default: kpageflags-viewer-presets.json)"
     );
 
     // TODO: proper parsing
@@ -758,40 +4917,68 @@ client  (Windows/Linux): {exe} client <remote client>"
     match args[..] {
         [_, "client", remote] => {
             // TODO: resolve names
-            let remote: SocketAddr = remote.parse().expect("Remote address");
-            client::client(remote);
+            let remote: Endpoint = remote.parse().expect("Remote address");
+            client::client(remote, Duration::from_secs(2));
+        }
+        [_, "client", remote, update_interval] => {
+            let remote: Endpoint = remote.parse().expect("Remote address");
+            let update_interval: f64 = update_interval.parse().expect("Update interval");
+            client::client(remote, Duration::from_secs_f64(update_interval));
+        }
+        [_, "record", path] => {
+            #[cfg(unix)]
+            server::record(Path::new(path), Duration::from_secs(2));
+        }
+        [_, "record", path, update_interval] => {
+            let update_interval: f64 = update_interval.parse().expect("Update interval");
+            #[cfg(unix)]
+            server::record(Path::new(path), Duration::from_secs_f64(update_interval));
+        }
+        [_, "replay", path] => {
+            client::replay(path.into(), 1.);
+        }
+        [_, "replay", path, speed] => {
+            let speed: f64 = speed.parse().expect("Speed");
+            // Duration::div_f64 panics on 0, negative or NaN
+            if speed.is_nan() || speed <= 0. {
+                panic!("Speed must be greater than 0\n{}", usage);
+            }
+            client::replay(path.into(), speed);
         }
-        [_, "server", socket] => {
-            let port: SocketAddr = socket.parse().expect("Local socket");
+        [_, "server", endpoint] => {
+            let endpoint: Endpoint = endpoint.parse().expect("Local endpoint");
             #[cfg(unix)]
-            server::server(port);
+            server::server(endpoint);
         }
         [me] => {
+            // local mode doesn't need TLS
+            let socket_path =
+                std::env::temp_dir().join(format!("kpageflags-viewer-{}.sock", std::process::id()));
+            let endpoint = format!("unix:{}", socket_path.display());
+
             let mut server = Command::new(me)
-                .args(vec!["server", "127.0.0.1:10000"])
+                .args(vec!["server", &endpoint])
                 .spawn()
                 .expect("Can't spawn server");
-            thread::sleep(Duration::from_millis(10));
+            for _ in 0..50 {
+                if socket_path.exists() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
 
             let mut client = Command::new(me)
-                .args(vec!["client", "127.0.0.1:10000"])
+                .args(vec!["client", &endpoint])
                 .spawn()
                 .expect("Can't spawn client");
 
-            let mut client_exited = false;
-            let mut server_exited = false;
-            loop {
-                if let Ok(Some(_)) = server.try_wait() {
-                    server_exited = true;
-                }
-                if let Ok(Some(_)) = client.try_wait() {
-                    client_exited = true;
-                }
-                if client_exited && server_exited {
-                    break;
-                }
-                thread::sleep(Duration::from_millis(100));
+            // the server doesn't stop when the client disconnects
+            client.wait().expect("Client failed");
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(server.id() as i32, libc::SIGTERM);
             }
+            server.wait().expect("Server failed");
         }
         _ => panic!("{}", usage),
     }
@@ -18,7 +18,7 @@ fn main() {
     for segment in iomem.iter() {
         eprintln!(
             "{} {:x}-{:x}: {} MiB",
-            &segment.name,
+            segment.name,
             segment.address.0,
             segment.address.1,
             (segment.address.1 - segment.address.0) / 1024 / 1024
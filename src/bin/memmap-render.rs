@@ -0,0 +1,607 @@
+// Render physical memory maps to PNG, without a GUI
+// Pages are placed on a Hilbert curve, and coloured by layers: iomem segments, flags expressions, processes or groups of processes
+// Works on the live system, or offline on a snap.py snapshot
+// Timelapse mode samples the live system and writes an animated GIF or APNG
+//
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use image::{Rgb, RgbImage};
+use log::{debug, info};
+use procfs::prelude::*;
+use procfs::process::{Pfn, Process};
+use procfs::{KPageFlags, PhysicalMemoryMap, PhysicalPageFlags};
+
+use snap::flag_expr::FlagExpr;
+use snap::groups::GroupBy;
+use snap::process_tree::ProcessTree;
+use snap::render::{self, HilbertMap, LegendEntry};
+
+const AFTER_HELP: &str = "Layers, the first matching layer gives the page colour:
+    iomem                        System RAM segments
+    flags:<EXPR>[=RRGGBB]        Pages matching a flags expression, e.g. flags:ANON&!SWAPBACKED=ff0000
+                                 Precedence: ! then & then |, use parenthesis for (A|B)&C
+    all-flags                    One flags layer per flag, use with an output path containing {}
+    processes[:FILTER][=RRGGBB]  Pages mapped by processes, optionally matching a memstats filter, e.g. processes:uid(1000)
+    groups[:GROUP_BY]            Pages coloured by group of processes: uid (default), env:<VAR>, custom:<filters>. Pages mapped by several groups are white
+
+Examples:
+    memmap-render -l iomem -o iomem.png
+    memmap-render -l all-flags -o kpageflags_{}.png
+    memmap-render -l processes -l iomem --size 1024
+    memmap-render --snapshot /tmp/snap123.tar.gz -l groups:env:ORACLE_SID -o sids.png
+    memmap-render -l processes -l 'flags:COMPOUND_HEAD | COMPOUND_TAIL' --interval 2 --duration 120 --size 512 -o compaction.gif
+";
+
+#[derive(Parser, Debug)]
+#[command(author, version = option_env!("VERSION").unwrap_or("0.1"), about, long_about = None, after_help = AFTER_HELP)]
+struct Cli {
+    #[arg(
+        short,
+        long = "layer",
+        required = true,
+        help = "Layer to draw, can be repeated. See below for syntax"
+    )]
+    layers: Vec<String>,
+
+    #[arg(
+        short,
+        long,
+        default_value = "img.png",
+        help = "Output PNG. If the path contains {}, one image is written per layer, {} is replaced by the layer name"
+    )]
+    output: String,
+
+    #[arg(short, long, help = "Width and height of the map in pixels")]
+    size: Option<u32>,
+
+    #[arg(long, help = "Don't draw the legend")]
+    no_legend: bool,
+
+    #[arg(long, default_value_t = 40., help = "Legend font size in pixels")]
+    font_size: f32,
+
+    #[arg(short, long, help = "/proc")]
+    procfs_root: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "procfs_root",
+        help = "snap.py snapshot, .tar.gz archive or extracted directory"
+    )]
+    snapshot: Option<PathBuf>,
+
+    #[arg(
+        long,
+        requires = "snapshot",
+        help = "Page size of the snapshot host in bytes, read from the snapshot metadata by default"
+    )]
+    page_size: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        conflicts_with = "snapshot",
+        help = "Timelapse: sample every SECONDS, and write an animated GIF (.gif output) or APNG (.png output)"
+    )]
+    interval: Option<f64>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        requires = "interval",
+        default_value_t = 60.,
+        help = "Timelapse duration"
+    )]
+    duration: f64,
+
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 500,
+        help = "Timelapse delay between frames when playing the animation"
+    )]
+    frame_delay: u16,
+}
+
+enum Layer {
+    Iomem,
+    Flags {
+        name: String,
+        /// As given on the command line
+        label: String,
+        expr: FlagExpr,
+        color: Option<Rgb<u8>>,
+    },
+    Processes {
+        filter: Option<String>,
+        color: Option<Rgb<u8>>,
+    },
+    Groups(GroupBy),
+}
+
+impl Layer {
+    /// A spec can expand to multiple layers
+    fn parse(spec: &str) -> Result<Vec<Layer>> {
+        // optional trailing =RRGGBB
+        let split_color = |spec: &str| -> (String, Option<Rgb<u8>>) {
+            match spec.rsplit_once('=') {
+                Some((rest, color)) => match render::parse_color(color) {
+                    Ok(color) => (rest.to_string(), Some(color)),
+                    Err(_) => (spec.to_string(), None),
+                },
+                None => (spec.to_string(), None),
+            }
+        };
+
+        if spec == "iomem" {
+            Ok(vec![Layer::Iomem])
+        } else if spec == "all-flags" {
+            Ok(PhysicalPageFlags::all()
+                .iter_names()
+                .map(|(name, flag)| Layer::Flags {
+                    name: name.to_lowercase(),
+                    label: name.to_string(),
+                    expr: FlagExpr::Flag(flag),
+                    color: None,
+                })
+                .collect())
+        } else if let Some(rest) = spec.strip_prefix("flags:") {
+            let (expr, color) = split_color(rest);
+            let name = expr
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+                .to_lowercase();
+            let label = expr.clone();
+            let expr = expr
+                .parse()
+                .with_context(|| format!("Invalid flags expression {expr:?}"))?;
+            Ok(vec![Layer::Flags {
+                name,
+                label,
+                expr,
+                color,
+            }])
+        } else if spec == "processes"
+            || spec.starts_with("processes:")
+            || spec.starts_with("processes=")
+        {
+            let (rest, color) = split_color(&spec["processes".len()..]);
+            let filter = rest.strip_prefix(':').map(|filter| filter.to_string());
+            if let Some(filter) = &filter {
+                snap::filters::parse(filter)
+                    .with_context(|| format!("Invalid filter {filter:?}"))?;
+            }
+            Ok(vec![Layer::Processes { filter, color }])
+        } else if spec == "groups" {
+            Ok(vec![Layer::Groups(GroupBy::Uid)])
+        } else if let Some(group_by) = spec.strip_prefix("groups:") {
+            Ok(vec![Layer::Groups(group_by.parse()?)])
+        } else {
+            bail!("Unknown layer {spec:?}, see --help")
+        }
+    }
+
+    /// Used in output file names
+    fn name(&self) -> String {
+        match self {
+            Layer::Iomem => "iomem".to_string(),
+            Layer::Flags { name, .. } => name.clone(),
+            Layer::Processes { .. } => "processes".to_string(),
+            Layer::Groups(_) => "groups".to_string(),
+        }
+    }
+}
+
+/// Data read from /proc, only what layers need
+struct Source {
+    root: PathBuf,
+    page_size: u64,
+    iomem: Vec<PhysicalMemoryMap>,
+    flags: Option<Vec<(Pfn, Pfn, Vec<PhysicalPageFlags>)>>,
+    /// Processes and their pages
+    processes: Option<(Vec<Process>, Vec<HashSet<Pfn>>)>,
+}
+
+impl Source {
+    fn new(root: PathBuf, page_size: u64) -> Result<Self> {
+        let iomem = procfs::Iomem::from_file(root.join("iomem"))
+            .with_context(|| format!("Can't read {:?}", root.join("iomem")))?;
+        let iomem = render::system_ram(&iomem.0);
+        for map in &iomem {
+            info!(
+                "{} {:x}-{:x}: {} MiB",
+                map.name,
+                map.address.0,
+                map.address.1,
+                (map.address.1 - map.address.0) / 1024 / 1024
+            );
+        }
+
+        Ok(Self {
+            root,
+            page_size,
+            iomem,
+            flags: None,
+            processes: None,
+        })
+    }
+
+    /// Read pages again on next use, the memory layout is kept
+    fn refresh(&mut self) {
+        self.flags = None;
+        self.processes = None;
+    }
+
+    fn flags(&mut self) -> Result<&[(Pfn, Pfn, Vec<PhysicalPageFlags>)]> {
+        if self.flags.is_none() {
+            let mut kpageflags = KPageFlags::from_custom_root(&self.root)
+                .with_context(|| "Can't open kpageflags")?;
+            let mut segments = Vec::new();
+            for map in &self.iomem {
+                let (start, end) = snap::pfn_range(map, self.page_size);
+                let flags = kpageflags
+                    .get_range_info(start, end)
+                    .with_context(|| format!("Can't read kpageflags {start:?}-{end:?}"))?;
+                segments.push((start, end, flags));
+            }
+            self.flags = Some(segments);
+        }
+        Ok(self.flags.as_deref().unwrap())
+    }
+
+    fn processes(&mut self) -> Result<(&[Process], &[HashSet<Pfn>])> {
+        if self.processes.is_none() {
+            use rayon::prelude::*;
+
+            let page_size = self.page_size;
+            let processes: Vec<Process> = procfs::process::all_processes_with_root(&self.root)
+                .with_context(|| format!("Can't list processes in {:?}", self.root))?
+                .filter_map(|p| p.ok())
+                .collect();
+            let processes: (Vec<Process>, Vec<HashSet<Pfn>>) = processes
+                .into_par_iter()
+                .filter_map(
+                    |process| match snap::get_process_pfns(&process, page_size) {
+                        Ok(pfns) => Some((process, pfns)),
+                        Err(e) => {
+                            debug!("Can't read pages of process {}: {e}", process.pid);
+                            None
+                        }
+                    },
+                )
+                .unzip();
+            self.processes = Some(processes);
+        }
+        let (processes, pfns) = self.processes.as_ref().unwrap();
+        Ok((processes, pfns))
+    }
+}
+
+/// snap.py archives are extracted next to the archive, once. Returns the snapshot directory
+fn snapshot_dir(path: &Path) -> Result<PathBuf> {
+    let name = path.to_string_lossy().to_string();
+    let snap_dir = match name.strip_suffix(".tar.gz") {
+        Some(dir) => {
+            let dir = PathBuf::from(dir);
+            if !dir.exists() {
+                info!("Extracting {path:?}");
+                let file =
+                    std::fs::File::open(path).with_context(|| format!("Can't open {path:?}"))?;
+                let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+                archive
+                    .unpack(dir.parent().unwrap_or(Path::new(".")))
+                    .with_context(|| format!("Can't extract {path:?}"))?;
+            }
+            dir
+        }
+        None => path.to_path_buf(),
+    };
+
+    if !snap_dir.join("proc").is_dir() {
+        bail!("{snap_dir:?} is not a snapshot, missing proc directory");
+    }
+    Ok(snap_dir)
+}
+
+/// Page size of the snapshot host, from the getconf output saved by snap.py in metadata.json
+fn snapshot_page_size(snap_dir: &Path) -> Result<u64> {
+    let path = snap_dir.join("metadata.json");
+    let metadata: serde_json::Value = serde_json::from_reader(
+        std::fs::File::open(&path)
+            .with_context(|| format!("Can't open {path:?}, use --page-size"))?,
+    )
+    .with_context(|| format!("Can't parse {path:?}"))?;
+    let Some(page_size) = metadata["getconf"]["PAGESIZE"].as_str() else {
+        bail!("No getconf PAGESIZE in {path:?}, use --page-size");
+    };
+    page_size
+        .parse()
+        .with_context(|| format!("Invalid PAGESIZE {page_size:?} in {path:?}"))
+}
+
+fn size_label(label: &str, pages: u64, page_size: u64) -> String {
+    format!(
+        "{} - {}",
+        label,
+        humansize::format_size(pages * page_size, humansize::BINARY)
+    )
+}
+
+/// Paint pages matched by the layer, and return its legend
+fn draw_layer(
+    layer: &Layer,
+    layer_index: usize,
+    layer_count: usize,
+    source: &mut Source,
+    map: &HilbertMap,
+    img: &mut RgbImage,
+) -> Result<Vec<LegendEntry>> {
+    let page_size = source.page_size;
+    let default_color = render::rainbow(layer_index, layer_count);
+
+    let legend = match layer {
+        Layer::Iomem => {
+            let count = map.iomem.len();
+            map.segments()
+                .map(|(idx, start, end)| {
+                    let color = render::rainbow(idx, count);
+                    for pfn in start.0..end.0 {
+                        if let Some((x, y)) = map.xy(Pfn(pfn)) {
+                            img.put_pixel(x, y, color);
+                        }
+                    }
+                    let segment = &map.iomem[idx];
+                    LegendEntry {
+                        color,
+                        label: format!(
+                            "{} {:x}-{:x} - {}",
+                            segment.name,
+                            segment.address.0,
+                            segment.address.1,
+                            humansize::format_size(snap::get_size(segment), humansize::BINARY)
+                        ),
+                    }
+                })
+                .collect()
+        }
+        Layer::Flags {
+            label, expr, color, ..
+        } => {
+            let color = color.unwrap_or(default_color);
+            let mut pages = 0;
+            for (start, _end, flags) in source.flags()? {
+                for (pfn, &flags) in (start.0..).zip(flags.iter()) {
+                    if expr.eval(flags) {
+                        if let Some((x, y)) = map.xy(Pfn(pfn)) {
+                            img.put_pixel(x, y, color);
+                            pages += 1;
+                        }
+                    }
+                }
+            }
+            vec![LegendEntry {
+                color,
+                label: size_label(label, pages, page_size),
+            }]
+        }
+        Layer::Processes { filter, color } => {
+            let color = color.unwrap_or(default_color);
+            let (processes, processes_pfns) = source.processes()?;
+
+            let selected: Vec<&HashSet<Pfn>> = match filter {
+                Some(filter) => {
+                    let (filter, _) = snap::filters::parse(filter)?;
+                    let tree = ProcessTree::new(processes);
+                    processes
+                        .iter()
+                        .zip(processes_pfns)
+                        .filter(|(process, _)| filter.eval(process, &tree))
+                        .map(|(_, pfns)| pfns)
+                        .collect()
+                }
+                None => processes_pfns.iter().collect(),
+            };
+
+            let pfns: HashSet<Pfn> = selected
+                .iter()
+                .flat_map(|pfns| pfns.iter().copied())
+                .collect();
+            let mut pages = 0;
+            for &pfn in &pfns {
+                if let Some((x, y)) = map.xy(pfn) {
+                    img.put_pixel(x, y, color);
+                    pages += 1;
+                }
+            }
+
+            let label = match filter {
+                Some(filter) => format!("{} processes {}", selected.len(), filter),
+                None => format!("{} processes", selected.len()),
+            };
+            vec![LegendEntry {
+                color,
+                label: size_label(&label, pages, page_size),
+            }]
+        }
+        Layer::Groups(group_by) => {
+            let (processes, processes_pfns) = source.processes()?;
+            let groups = group_by.groups(processes);
+
+            let mut names: Vec<String> = groups.clone();
+            names.sort();
+            names.dedup();
+            let group_index: HashMap<&str, usize> = names
+                .iter()
+                .enumerate()
+                .map(|(idx, name)| (name.as_str(), idx))
+                .collect();
+
+            // None: mapped by several groups
+            let mut owners: HashMap<Pfn, Option<usize>> = HashMap::new();
+            for (pfns, group) in processes_pfns.iter().zip(&groups) {
+                let idx = group_index[group.as_str()];
+                for &pfn in pfns {
+                    owners
+                        .entry(pfn)
+                        .and_modify(|owner| {
+                            if *owner != Some(idx) {
+                                *owner = None
+                            }
+                        })
+                        .or_insert(Some(idx));
+                }
+            }
+
+            let shared_color = Rgb([255, 255, 255]);
+            let colors: Vec<Rgb<u8>> = (0..names.len())
+                .map(|idx| render::rainbow(idx, names.len()))
+                .collect();
+            let mut pages = vec![0; names.len()];
+            let mut shared_pages = 0;
+            for (&pfn, owner) in &owners {
+                let Some((x, y)) = map.xy(pfn) else {
+                    continue;
+                };
+                match owner {
+                    Some(idx) => {
+                        img.put_pixel(x, y, colors[*idx]);
+                        pages[*idx] += 1;
+                    }
+                    None => {
+                        img.put_pixel(x, y, shared_color);
+                        shared_pages += 1;
+                    }
+                }
+            }
+
+            names
+                .iter()
+                .zip(colors)
+                .zip(pages)
+                .map(|((name, color), pages)| LegendEntry {
+                    color,
+                    label: size_label(name, pages, page_size),
+                })
+                .chain(std::iter::once(LegendEntry {
+                    color: shared_color,
+                    label: size_label("shared", shared_pages, page_size),
+                }))
+                .collect()
+        }
+    };
+
+    Ok(legend)
+}
+
+/// Layers are painted last to first, so that the first matching layer is visible
+///
+/// Layers are given with their index in the command line, for default colours
+fn render_image(
+    layers: &[(usize, &Layer)],
+    layer_count: usize,
+    source: &mut Source,
+    cli: &Cli,
+) -> Result<RgbImage> {
+    let iomem = source.iomem.clone();
+    let map = HilbertMap::new(&iomem, source.page_size);
+    let mut img = map.background(Rgb([128, 128, 128]));
+
+    let mut legends = Vec::new();
+    for &(layer_index, layer) in layers.iter().rev() {
+        let legend = draw_layer(layer, layer_index, layer_count, source, &map, &mut img)?;
+        legends.push(legend);
+    }
+    let legend: Vec<LegendEntry> = legends.into_iter().rev().flatten().collect();
+
+    if let Some(size) = cli.size {
+        img = render::scale(&img, size);
+    }
+    if !cli.no_legend {
+        img = render::draw_legend(&img, &legend, cli.font_size);
+    }
+    Ok(img)
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+
+    let cli = Cli::parse();
+
+    let mut layers = Vec::new();
+    for spec in &cli.layers {
+        layers.extend(Layer::parse(spec)?);
+    }
+
+    let (root, page_size) = match (&cli.snapshot, &cli.procfs_root) {
+        (Some(snapshot), _) => {
+            let snap_dir = snapshot_dir(snapshot)?;
+            let page_size = match cli.page_size {
+                Some(page_size) => page_size,
+                None => snapshot_page_size(&snap_dir)?,
+            };
+            (snap_dir.join("proc"), page_size)
+        }
+        (None, Some(root)) => (root.clone(), procfs::page_size()),
+        (None, None) => (PathBuf::from("/proc"), procfs::page_size()),
+    };
+    if !page_size.is_power_of_two() {
+        bail!("Invalid page size {page_size}");
+    }
+    let mut source = Source::new(root, page_size)?;
+
+    if let Some(interval) = cli.interval {
+        if cli.output.contains("{}") {
+            bail!("Timelapse output can't contain {{}}");
+        }
+        if interval <= 0. {
+            bail!("Interval must be positive");
+        }
+        let animation = render::AnimationFile::create(Path::new(&cli.output))?;
+        let layers: Vec<(usize, &Layer)> = layers.iter().enumerate().collect();
+        let frames_count = (cli.duration / interval).floor() as u32 + 1;
+
+        let start = std::time::Instant::now();
+        let mut frames = Vec::new();
+        for frame_index in 0..frames_count {
+            let due = start + std::time::Duration::from_secs_f64(interval * frame_index as f64);
+            std::thread::sleep(due.saturating_duration_since(std::time::Instant::now()));
+
+            let timestamp = chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+            source.refresh();
+            let mut img = render_image(&layers, layers.len(), &mut source, &cli)?;
+            render::draw_caption(&mut img, &timestamp, cli.font_size);
+            frames.push(img);
+            info!(
+                "Frame {}/{} at {}",
+                frame_index + 1,
+                frames_count,
+                timestamp
+            );
+        }
+
+        animation.save(&frames, cli.frame_delay)?;
+        println!("{}", cli.output);
+    } else if cli.output.contains("{}") {
+        for (layer_index, layer) in layers.iter().enumerate() {
+            let img = render_image(&[(layer_index, layer)], layers.len(), &mut source, &cli)?;
+            let path = cli.output.replace("{}", &layer.name());
+            img.save(&path)
+                .with_context(|| format!("Can't write {path:?}"))?;
+            println!("{path}");
+        }
+    } else {
+        let layers: Vec<(usize, &Layer)> = layers.iter().enumerate().collect();
+        let img = render_image(&layers, layers.len(), &mut source, &cli)?;
+        img.save(&cli.output)
+            .with_context(|| format!("Can't write {:?}", cli.output))?;
+        println!("{}", cli.output);
+    }
+
+    Ok(())
+}
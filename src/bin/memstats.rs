@@ -30,8 +30,8 @@ use rayon::prelude::*;
 use rustc_hash::FxHasher;
 use snap::tmpfs::format_units_MiB;
 use snap::{
-    filters, get_process_info, get_smon_info, groups, LargePages, ProcessInfo, ShmsMetadata,
-    SmonInfo,
+    filters, get_process_info, get_smon_info, groups, swap, InstanceKind, LargePages,
+    OracleProcess, ProcessInfo, ShmsMetadata, SmonInfo,
 };
 use tabled::Tabled;
 
@@ -43,9 +43,14 @@ use std::{
 };
 
 use groups::{
-    ProcessSplitter, ProcessSplitterCustomFilter, ProcessSplitterEnvVariable, ProcessSplitterUid,
+    ProcessSplitter, ProcessSplitterCgroup, ProcessSplitterCustomFilter,
+    ProcessSplitterEnvVariable, ProcessSplitterPdb, ProcessSplitterUid,
 };
 
+use snap::db_info::{DbInfoError, DbInfoResponse};
+use snap::instances::{
+    AppInstance, InstanceDiscovery, JvmDiscovery, OracleDiscovery, PostgresDiscovery,
+};
 use snap::process_tree::ProcessTree;
 
 fn main() {
@@ -119,36 +124,97 @@ Examples:
         #[arg(short, long, action = clap::ArgAction::Set, default_value_t = false, help = "Force read PFN for shm, even if shm is in swap")]
         force_read_shm: bool,
 
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            help = "Sample memory pressure (PSI, /proc/vmstat) during SECONDS before scanning"
+        )]
+        pressure: Option<f64>,
+
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            default_value_t = 30.,
+            help = "Time allowed to get the info of each Oracle instance"
+        )]
+        db_timeout: f64,
+
         #[command(subcommand)]
         commands: Commands,
     }
 
+    /// Reports of the groups command
+    #[derive(Debug, clap::Args)]
+    struct GroupsOptions {
+        #[arg(short = 'e', long)]
+        split_env: Option<String>,
+
+        #[arg(short = 'u', long)]
+        split_uid: bool,
+
+        #[arg(short = 'p', long, action = clap::ArgAction::Append)]
+        split_pids: Vec<i32>,
+
+        #[arg(
+            short = 'g',
+            long,
+            help = "Split by cgroup v2, also display memory.stat and memory.pressure of each cgroup"
+        )]
+        split_cgroup: bool,
+
+        #[arg(
+            long,
+            help = "Split Oracle processes by the container (PDB) of their current session"
+        )]
+        split_pdb: bool,
+
+        #[arg(
+            short = 'c',
+            long,
+            help = "Comma separated list of filters, evaluated in order. Can be repeated to create multiple reports"
+        )]
+        split_custom: Vec<String>,
+
+        #[arg(
+            short = 's',
+            long,
+            help = "Display swap usage per swap device for each group, and the most swapped processes"
+        )]
+        swap: bool,
+
+        #[arg(
+            long,
+            help = "Display anonymous memory shared between forked processes (copy-on-write)"
+        )]
+        cow: bool,
+
+        #[arg(
+            long,
+            help = "Display KSM (kernel samepage merging) counters, and KSM pages for each group"
+        )]
+        ksm: bool,
+    }
+
     #[derive(Debug, Subcommand)]
     enum Commands {
+        /// Read a request on stdin, see snap::db_info
         #[command(hide = true)]
-        GetDbInfo {
-            #[arg(long, required = true)]
-            pid: i32,
-        },
+        GetDbInfo,
         /// Single threaded process scan, can't do multiple groups, but memory efficient
         Single,
         /// Multi threaded process scan, multiple groups, memory hungry
-        Groups {
-            #[arg(short = 'e', long)]
-            split_env: Option<String>,
-
-            #[arg(short = 'u', long)]
-            split_uid: bool,
-
-            #[arg(short = 'p', long, action = clap::ArgAction::Append)]
-            split_pids: Vec<i32>,
-
+        Groups(GroupsOptions),
+        /// Free memory fragmentation per zone: buddyinfo, pagetypeinfo and contiguous free runs from kpageflags
+        Fragmentation,
+        /// Memory that would be released by killing or restarting a group of processes
+        Whatif {
             #[arg(
-                short = 'c',
+                short = 'r',
                 long,
-                help = "Comma separated list of filters, evaluated in order. Can be repeated to create multiple reports"
+                required = true,
+                help = "Filter selecting the processes to remove. See below for syntax"
             )]
-            split_custom: Vec<String>,
+            remove: String,
         },
     }
 
@@ -159,31 +225,23 @@ Examples:
 
     let cli = Cli::parse();
 
-    if let Commands::GetDbInfo { pid } = cli.commands {
+    if let Commands::GetDbInfo = cli.commands {
         // oracle shouldn't run as root
         assert_ne!(uzers::get_effective_uid(), 0);
 
-        // subprogram to connect to instance and print sga size
+        // subprogram to connect to instance and print its info
         // We must have the correct context (user, env vars) to connect to database
-        let (sga_size, processes, pga_size, large_pages) = snap::get_db_info().unwrap();
-
-        let sid = std::env::var_os("ORACLE_SID").expect("Missing ORACLE_SID");
-
-        let smon_info: SmonInfo = SmonInfo {
-            pid,
-            sid: sid.clone(),
-            sga_size,
-            large_pages,
-            processes,
-            pga_size,
-        };
-        let out = serde_json::to_string(&smon_info)
-            .unwrap_or_else(|_| panic!("Can't serialize SmonInfo for {sid:?}"));
-        println!("{out}");
+        let result = snap::db_info::read_request(std::io::stdin().lock()).and_then(|request| {
+            // keep some time to send the response before the parent kills us
+            let deadline = std::time::Instant::now() + request.timeout.mul_f64(0.9);
+            snap::get_db_info(request.pid, deadline)
+        });
+        let response = DbInfoResponse::new(result);
+        let out = serde_json::to_string(&response).expect("Can't serialize DbInfoResponse");
 
-        // print value, can't use logger here
+        // print response, can't use logger here
         // parent will grab that value in `get_smon_info`
-        //println!("{sga_size} {processes} {pga_size} {large_pages}");
+        println!("{out}");
         std::process::exit(0);
     }
     // can't print anything before that line
@@ -231,8 +289,22 @@ Examples:
         std::process::exit(1);
     }
 
+    if let Commands::Fragmentation = cli.commands {
+        // no need to scan processes
+        let report = snap::fragmentation::FragmentationReport::new()
+            .expect("Can't compute fragmentation report");
+        report.display();
+        return;
+    }
+
     snap::tmpfs::display_tmpfs();
 
+    if let Some(window) = cli.pressure {
+        // sample before scanning, memstats itself puts pressure on memory
+        let sample = snap::pressure::sample_pressure(std::time::Duration::from_secs_f64(window));
+        snap::pressure::display_pressure(&sample);
+    }
+
     println!("Scanning /proc/kpageflags...");
     let mut kpageflags = procfs::KPageFlags::new().expect("Can't open /proc/kpageflags");
     let all_physical_pages: HashMap<Pfn, PhysicalPageFlags> = procfs::iomem()
@@ -245,7 +317,7 @@ Examples:
                 None
             }
         })
-        .map(|map| {
+        .flat_map(|map| {
             let (start, end) = map.get_range().get();
 
             //let counts = kpagecount
@@ -261,33 +333,38 @@ Examples:
 
             v
         })
-        .flatten()
         .collect();
     println!();
 
     // find smons processes, and for each spawn a new process in the correct context to get database info
     println!("Scanning Oracle instances...");
-    let mut instances: Vec<SmonInfo> = snap::find_smons()
-        .iter()
-        .filter_map(|(pid, uid, sid, home)| {
-            debug!("Getting DB info for pid={pid}, uid={uid}, sid={sid:?}, home={home:?}");
-            let smon_info = get_smon_info(*pid, *uid, sid.as_os_str(), home.as_os_str());
+    let db_timeout = std::time::Duration::from_secs_f64(cli.db_timeout);
+    let mut instances: Vec<SmonInfo> = Vec::new();
+    // instances without info, and why
+    let mut missing_instances: Vec<(i32, String, DbInfoError)> = Vec::new();
+    for (pid, uid, sid, home) in snap::find_smons() {
+        debug!("Getting DB info for pid={pid}, uid={uid}, sid={sid:?}, home={home:?}");
+        let smon_info = match &home {
+            Some(home) => get_smon_info(pid, uid, &sid, home, db_timeout),
+            None => Err(DbInfoError::MissingEnv("ORACLE_HOME".to_string())),
+        };
 
-            match smon_info {
-                Ok(x) => Some(x),
-                Err(e) => {
-                    warn!("Can't get DB info for {sid:?}: {e:?}");
-                    None
-                }
+        match smon_info {
+            Ok(x) => instances.push(x),
+            Err(e) => {
+                debug!("Can't get DB info for {sid:?}: {e:?}");
+                missing_instances.push((pid, sid.to_string_lossy().to_string(), e));
             }
-        })
-        .collect();
+        }
+    }
 
     instances.sort_by(|a, b| a.sga_size.cmp(&b.sga_size).reverse());
 
     #[derive(Tabled)]
     struct InstanceDisplayRow {
         sid: String,
+        #[tabled(rename = "type")]
+        kind: InstanceKind,
         #[tabled(display = "format_units_MiB")]
         sga: u64,
         #[tabled(display = "format_units_MiB")]
@@ -303,6 +380,7 @@ Examples:
             .iter()
             .map(|instance| InstanceDisplayRow {
                 sid: instance.sid.to_string_lossy().to_string(),
+                kind: instance.kind,
                 sga: instance.sga_size,
                 pga: instance.pga_size,
                 processes: instance.processes,
@@ -314,8 +392,41 @@ Examples:
         table.with(tabled::settings::Style::sharp());
         println!("{}", table);
 
+        for instance in &instances {
+            for e in &instance.errors {
+                println!(
+                    "Incomplete DB info for {}: {e}",
+                    instance.sid.to_string_lossy()
+                );
+            }
+        }
         println!();
-    } else {
+    }
+
+    if !missing_instances.is_empty() {
+        #[derive(Tabled)]
+        struct MissingInstanceDisplayRow {
+            sid: String,
+            pid: i32,
+            reason: String,
+        }
+
+        println!("Oracle instances without DB info:");
+        let display_info: Vec<MissingInstanceDisplayRow> = missing_instances
+            .iter()
+            .map(|(pid, sid, e)| MissingInstanceDisplayRow {
+                sid: sid.clone(),
+                pid: *pid,
+                reason: e.to_string(),
+            })
+            .collect();
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+        println!("{table}");
+        println!();
+    }
+
+    if instances.is_empty() && missing_instances.is_empty() {
         println!("Can't locate any Oracle instance");
         println!();
     }
@@ -364,12 +475,15 @@ Examples:
             #[tabled(rename = "used %")]
             used: f32,
             sid: String,
+            #[tabled(rename = "type")]
+            kind: String,
         }
 
         println!("Sysvipc shm:");
         let mut shm_display = Vec::new();
         for shm in &shms {
             let mut sid_list = Vec::new();
+            let mut kind_list = Vec::new();
             for instance in &instances {
                 // we associate each shm with an sid by looking for smon processes
                 let Ok(process) = Process::new(instance.pid) else {
@@ -381,6 +495,9 @@ Examples:
 
                 if process_info.referenced_shms.contains(shm) {
                     sid_list.push(instance.sid.to_string_lossy().to_string());
+                    if !kind_list.contains(&instance.kind) {
+                        kind_list.push(instance.kind);
+                    }
                 }
             }
 
@@ -403,6 +520,7 @@ Examples:
                 // USED% can be >100% if size is not aligned with the underling pages: in that case, size < rss+swap
                 used: (shm.rss + shm.swap) as f32 / shm.size as f32 * 100.,
                 sid: sid_list.join(" "),
+                kind: kind_list.iter().join(" "),
             };
             shm_display.push(shm_display_row);
         }
@@ -418,6 +536,39 @@ Examples:
         println!();
     }
 
+    for instance in &instances {
+        // shm segments attached by smon
+        let Ok(process) = Process::new(instance.pid) else {
+            continue;
+        };
+        let Ok(process_info) = get_process_info(process, &shms_metadata) else {
+            continue;
+        };
+        let shms: Vec<Shm> = process_info.referenced_shms.into_iter().collect();
+        snap::sga::display(
+            &instance.sid.to_string_lossy(),
+            &instance.sga_layout,
+            &shms,
+            &shms_metadata,
+        );
+    }
+
+    for instance in &instances {
+        if !instance.containers.is_empty() {
+            snap::pdb::display(
+                &instance.sid.to_string_lossy(),
+                &instance.containers,
+                &instance.pga_processes,
+            );
+        }
+    }
+
+    for instance in &instances {
+        if instance.asm.is_some() {
+            snap::asm::display(instance, &instances);
+        }
+    }
+
     // probably incorrect?
     // size of kernel structures
     //let current_kernel = procfs::sys::kernel::Version::current().unwrap();
@@ -449,6 +600,14 @@ Examples:
     info!("Total processes {all_processes_count}");
     let tree = ProcessTree::new(&all_processes);
 
+    // databases and JVMs, measured once processes are scanned
+    let oracle_discovery = OracleDiscovery {
+        instances: &instances,
+    };
+    let discoveries: [&dyn InstanceDiscovery; 3] =
+        [&oracle_discovery, &PostgresDiscovery, &JvmDiscovery];
+    let app_instances = snap::instances::discover(&discoveries, &all_processes, &tree);
+
     // exclude kernel procs
     let processes: Vec<Process> = all_processes
         .into_iter()
@@ -510,49 +669,53 @@ Examples:
     let my_pid = std::process::id();
     let my_process = procfs::process::Process::new(my_pid as i32).unwrap();
 
+    let context = ScanContext {
+        my_process,
+        global_chrono,
+        mem_limit,
+        tree: &tree,
+        shms_metadata: &shms_metadata,
+        all_physical_pages: &all_physical_pages,
+        instances: &instances,
+        app_instances: &app_instances,
+    };
+
     match cli.commands {
-        Commands::GetDbInfo { .. } => unreachable!(),
+        Commands::GetDbInfo | Commands::Fragmentation => unreachable!(),
+        Commands::Whatif { remove } => {
+            scan_whatif(&context, processes, &remove);
+        }
         Commands::Single => {
-            scan_single(
-                my_process,
-                global_chrono,
-                mem_limit,
-                processes,
-                &tree,
-                &shms_metadata,
-            );
+            scan_single(&context, processes);
         }
-        Commands::Groups {
-            split_env,
-            split_uid,
-            split_pids,
-            mut split_custom,
-        } => {
-            split_custom.reverse();
-
-            scan_groups(
-                my_process,
-                global_chrono,
-                mem_limit,
-                processes,
-                &tree,
-                &shms_metadata,
-                split_env,
-                split_uid,
-                split_pids,
-                split_custom,
-            );
+        Commands::Groups(mut options) => {
+            options.split_custom.reverse();
+
+            scan_groups(&context, processes, options);
         }
     }
 
-    fn scan_single(
+    /// What the scan of each command needs, besides the processes
+    struct ScanContext<'a> {
         my_process: Process,
         global_chrono: std::time::Instant,
         mem_limit: u64,
-        processes: Vec<Process>,
-        _tree: &ProcessTree,
-        shms_metadata: &ShmsMetadata,
-    ) {
+        tree: &'a ProcessTree,
+        shms_metadata: &'a ShmsMetadata,
+        all_physical_pages: &'a HashMap<Pfn, PhysicalPageFlags>,
+        instances: &'a [SmonInfo],
+        /// Configured vs measured memory is displayed after the scan
+        app_instances: &'a [(&'a dyn InstanceDiscovery, AppInstance)],
+    }
+
+    fn scan_single(context: &ScanContext, processes: Vec<Process>) {
+        let ScanContext {
+            ref my_process,
+            global_chrono,
+            mem_limit,
+            shms_metadata,
+            ..
+        } = *context;
         let processes_count = processes.len();
         let single_chrono = std::time::Instant::now();
         let hit_memory_limit = Arc::new(Mutex::new(false));
@@ -615,23 +778,17 @@ Examples:
         info!("shm mem: {shm_mem}");
         info!("shm swap: {shm_swap}");
 
-        finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+        finalize(hit_memory_limit, mem_limit, my_process, global_chrono);
     }
 
-    fn scan_groups(
-        my_process: Process,
-        global_chrono: std::time::Instant,
+    fn scan_processes(
+        my_process: &Process,
         mem_limit: u64,
         processes: Vec<Process>,
-        tree: &ProcessTree,
         shms_metadata: &ShmsMetadata,
-        split_env: Option<String>,
-        split_uid: bool,
-        split_pids: Vec<i32>,
-        mut split_custom: Vec<String>,
-    ) {
+        hit_memory_limit: &Arc<Mutex<bool>>,
+    ) -> Vec<ProcessInfo> {
         let processes_count = processes.len();
-        let hit_memory_limit = Arc::new(Mutex::new(false));
         let chrono = std::time::Instant::now();
         println!("\nScanning {processes_count} processes");
         let pb = ProgressBar::new(processes_count as u64);
@@ -674,6 +831,92 @@ Examples:
         info!("{} processe(s) vanished", vanished_processes_count);
         info!("");
 
+        processes_info
+    }
+
+    fn scan_whatif(context: &ScanContext, processes: Vec<Process>, remove: &str) {
+        let ScanContext {
+            ref my_process,
+            global_chrono,
+            mem_limit,
+            tree,
+            shms_metadata,
+            all_physical_pages,
+            instances,
+            app_instances,
+        } = *context;
+        let hit_memory_limit = Arc::new(Mutex::new(false));
+        let processes_info = scan_processes(
+            my_process,
+            mem_limit,
+            processes,
+            shms_metadata,
+            &hit_memory_limit,
+        );
+        println!();
+        let processes_info = snap::instances::display(app_instances, processes_info, shms_metadata);
+        display_oracle_pga(instances, &processes_info);
+
+        let mut splitter = match ProcessSplitterCustomFilter::new(remove) {
+            Ok(splitter) => splitter,
+            Err(e) => {
+                error!("Invalid filter {remove:?}: {e:?}");
+                std::process::exit(1);
+            }
+        };
+        splitter.split(tree, shms_metadata, processes_info);
+        splitter.display(shms_metadata);
+
+        // all filters are removed, "Other" remains
+        let mut removed_processes: Vec<ProcessInfo> = Vec::new();
+        let mut remaining_processes: Vec<ProcessInfo> = Vec::new();
+        for (name, group) in splitter.groups.drain() {
+            if name == "Other" {
+                remaining_processes.extend(group.processes_info);
+            } else {
+                removed_processes.extend(group.processes_info);
+            }
+        }
+        let removed = snap::get_processes_group_info(removed_processes, remove, shms_metadata);
+        let remaining = snap::get_processes_group_info(remaining_processes, "Other", shms_metadata);
+
+        let what_if = snap::whatif::what_if_remove(&removed, &remaining, all_physical_pages);
+        snap::whatif::display_what_if(remove, &what_if);
+
+        finalize(hit_memory_limit, mem_limit, my_process, global_chrono);
+    }
+
+    fn scan_groups(context: &ScanContext, processes: Vec<Process>, options: GroupsOptions) {
+        let ScanContext {
+            ref my_process,
+            global_chrono,
+            mem_limit,
+            tree,
+            shms_metadata,
+            all_physical_pages,
+            instances,
+            app_instances,
+        } = *context;
+        let GroupsOptions {
+            split_env,
+            split_uid,
+            split_pids,
+            split_cgroup,
+            split_pdb,
+            mut split_custom,
+            swap,
+            cow,
+            ksm,
+        } = options;
+        let hit_memory_limit = Arc::new(Mutex::new(false));
+        let processes_info = scan_processes(
+            my_process,
+            mem_limit,
+            processes,
+            shms_metadata,
+            &hit_memory_limit,
+        );
+
         {
             // scan missing SHM
             let missing_shms: Vec<_> = processes_info
@@ -695,7 +938,7 @@ Examples:
 
             //dbg!(&more_pids_and_shm);
 
-            for (_shm, pids) in more_pids_and_shm.iter_mut() {
+            for pids in more_pids_and_shm.values_mut() {
                 for _p in pids {
                     // TODO
                     //let if Ok(shm_metadata) = scan_pid_shm(p, shm) {
@@ -716,10 +959,72 @@ Examples:
         }
 
         println!();
+        let processes_info = snap::instances::display(app_instances, processes_info, shms_metadata);
+        display_oracle_pga(instances, &processes_info);
+
+        let (swap_devices, zswap) = if swap {
+            let swap_devices = swap::get_swap_devices().unwrap_or_else(|e| {
+                warn!("Can't read swap devices: {e:?}");
+                Vec::new()
+            });
+            let zswap = swap::get_zswap_stats();
+            swap::display_swap_devices(&swap_devices, zswap);
+            swap::display_top_swapped_processes(&processes_info, 10);
+            (swap_devices, zswap)
+        } else {
+            (Vec::new(), None)
+        };
+
+        if cow {
+            snap::cow::display_shared_anon(&processes_info, all_physical_pages, tree);
+        }
+
+        let ksm_stats = if ksm {
+            match snap::ksm::get_ksm_stats() {
+                Ok(ksm_stats) => {
+                    snap::ksm::display_ksm_stats(&ksm_stats);
+                    Some(ksm_stats)
+                }
+                Err(e) => {
+                    warn!("Can't read KSM stats: {e:?}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let reports = GroupReports {
+            shms_metadata,
+            swap: swap.then_some((swap_devices.as_slice(), zswap)),
+            ksm: ksm_stats
+                .as_ref()
+                .map(|ksm_stats| (all_physical_pages, ksm_stats)),
+        };
+
         let processes_info: Vec<ProcessInfo> = if split_uid {
             let mut splitter = ProcessSplitterUid::new();
             splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
+            display_groups(&splitter, &reports);
+            splitter.collect_processes()
+        } else {
+            processes_info
+        };
+
+        let processes_info: Vec<ProcessInfo> = if split_cgroup {
+            let mut splitter = ProcessSplitterCgroup::new();
+            splitter.split(tree, shms_metadata, processes_info);
+            display_groups(&splitter, &reports);
+            splitter.display_cgroup_memory();
+            splitter.collect_processes()
+        } else {
+            processes_info
+        };
+
+        let processes_info: Vec<ProcessInfo> = if split_pdb {
+            let mut splitter = ProcessSplitterPdb::new(instances);
+            splitter.split(tree, shms_metadata, processes_info);
+            display_groups(&splitter, &reports);
             splitter.collect_processes()
         } else {
             processes_info
@@ -728,7 +1033,7 @@ Examples:
         let processes_info: Vec<ProcessInfo> = if let Some(var) = split_env {
             let mut splitter = ProcessSplitterEnvVariable::new(var);
             splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
+            display_groups(&splitter, &reports);
             splitter.collect_processes()
         } else {
             processes_info
@@ -752,7 +1057,7 @@ Examples:
 
             let mut splitter = ProcessSplitterCustomFilter::new(&expr).unwrap();
             splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
+            display_groups(&splitter, &reports);
             splitter.collect_processes()
         } else {
             processes_info
@@ -762,11 +1067,163 @@ Examples:
         while let Some(filter) = split_custom.pop() {
             let mut splitter = ProcessSplitterCustomFilter::new(&filter).unwrap();
             splitter.split(tree, shms_metadata, processes_info);
-            splitter.display(shms_metadata);
+            display_groups(&splitter, &reports);
             processes_info = splitter.collect_processes();
         }
 
-        finalize(hit_memory_limit, mem_limit, &my_process, global_chrono);
+        finalize(hit_memory_limit, mem_limit, my_process, global_chrono);
+    }
+
+    /// Reports displayed for each split of the groups command
+    struct GroupReports<'a> {
+        shms_metadata: &'a ShmsMetadata,
+        swap: Option<(&'a [swap::SwapDevice], Option<swap::ZswapStats>)>,
+        ksm: Option<(&'a HashMap<Pfn, PhysicalPageFlags>, &'a snap::ksm::KsmStats)>,
+    }
+
+    /// Groups of a splitter, then their swap and KSM usage if requested
+    fn display_groups<'a>(splitter: &'a impl ProcessSplitter<'a>, reports: &GroupReports) {
+        splitter.display(reports.shms_metadata);
+        if let Some((swap_devices, zswap)) = reports.swap {
+            splitter.display_swap(swap_devices, zswap);
+        }
+        if let Some((all_physical_pages, ksm_stats)) = reports.ksm {
+            splitter.display_ksm(all_physical_pages, ksm_stats);
+        }
+    }
+
+    /// PGA reported by v$process next to the anon memory measured for the same pids
+    ///
+    /// Oracle processes don't fork, their anon memory is mostly PGA plus a few MiB of libraries.
+    /// Processes missing from `processes_info` (filtered, vanished, memory limit) are not listed
+    fn display_oracle_pga(instances: &[SmonInfo], processes_info: &[ProcessInfo]) {
+        // largest PGA first, processes with a discrepancy are always displayed
+        const DISPLAYED_PROCESSES: usize = 20;
+        // discrepancy if anon + swap differs from PGA allocated by more than both
+        const DISCREPANCY_BYTES: i64 = 64 * 1024 * 1024;
+        const DISCREPANCY_RATIO: f64 = 0.5;
+
+        #[derive(Tabled)]
+        struct PgaDisplayRow {
+            pid: i32,
+            name: String,
+            #[tabled(rename = "type")]
+            kind: &'static str,
+            #[tabled(display = "format_units_MiB")]
+            pga_used: u64,
+            #[tabled(display = "format_units_MiB")]
+            pga_alloc: u64,
+            #[tabled(display = "format_units_MiB")]
+            pga_max: u64,
+            #[tabled(display = "format_units_MiB")]
+            anon: u64,
+            #[tabled(display = "format_units_MiB")]
+            anon_swap: u64,
+            #[tabled(display = "format_units_MiB")]
+            uss: u64,
+            discrepancy: String,
+        }
+
+        let page_size = procfs::page_size();
+        let by_pid: HashMap<i32, &ProcessInfo> = processes_info
+            .iter()
+            .map(|process_info| (process_info.process.pid, process_info))
+            .collect();
+
+        for instance in instances {
+            let sid = instance.sid.to_string_lossy();
+
+            let measured: Vec<(&OracleProcess, &ProcessInfo)> = instance
+                .pga_processes
+                .iter()
+                .filter_map(|oracle_process| {
+                    let process_info = by_pid.get(&oracle_process.spid)?;
+                    Some((oracle_process, *process_info))
+                })
+                .collect();
+
+            // USS: anon pages not referenced by another process of the instance
+            let mut references: HashMap<Pfn, u32, BuildHasherDefault<FxHasher>> =
+                Default::default();
+            for (_, process_info) in &measured {
+                for pfn in &process_info.anon_pfns {
+                    *references.entry(*pfn).or_default() += 1;
+                }
+            }
+
+            let mut rows: Vec<(bool, PgaDisplayRow)> = measured
+                .iter()
+                .map(|(oracle_process, process_info)| {
+                    let anon = process_info.anon_pfns.len() as u64 * page_size;
+                    let anon_swap = process_info.anon_swap_pages.len() as u64 * page_size;
+                    let uss = process_info
+                        .anon_pfns
+                        .iter()
+                        .filter(|pfn| references[pfn] == 1)
+                        .count() as u64
+                        * page_size;
+
+                    let pga_alloc = oracle_process.pga_alloc_mem;
+                    let difference = (anon + anon_swap) as i64 - pga_alloc as i64;
+                    let discrepancy = difference.abs() > DISCREPANCY_BYTES
+                        && difference.abs() as f64 > pga_alloc as f64 * DISCREPANCY_RATIO;
+
+                    let row = PgaDisplayRow {
+                        pid: oracle_process.spid,
+                        name: oracle_process
+                            .pname
+                            .clone()
+                            .or_else(|| oracle_process.program.clone())
+                            .unwrap_or_default(),
+                        kind: if oracle_process.background {
+                            "background"
+                        } else {
+                            "foreground"
+                        },
+                        pga_used: oracle_process.pga_used_mem,
+                        pga_alloc,
+                        pga_max: oracle_process.pga_max_mem,
+                        anon,
+                        anon_swap,
+                        uss,
+                        discrepancy: if discrepancy {
+                            format!(
+                                "{}{}",
+                                if difference < 0 { "-" } else { "+" },
+                                format_units_MiB(&difference.unsigned_abs())
+                            )
+                        } else {
+                            String::new()
+                        },
+                    };
+                    (discrepancy, row)
+                })
+                .collect();
+            rows.sort_by(|(_, a), (_, b)| a.pga_alloc.cmp(&b.pga_alloc).reverse());
+
+            let discrepancies = rows.iter().filter(|(discrepancy, _)| *discrepancy).count();
+            let measured_rows = rows.len();
+            let rows: Vec<PgaDisplayRow> = rows
+                .into_iter()
+                .enumerate()
+                .filter(|(idx, (discrepancy, _))| *idx < DISPLAYED_PROCESSES || *discrepancy)
+                .map(|(_, (_, row))| row)
+                .collect();
+            let hidden = measured_rows - rows.len();
+
+            println!(
+                "Oracle PGA vs measured anon memory for {sid}: {} of {} processes scanned, {discrepancies} discrepancies",
+                measured.len(),
+                instance.pga_processes.len(),
+            );
+            let mut table = tabled::Table::new(&rows);
+            table.with(tabled::settings::Style::sharp());
+            println!("{table}");
+            if hidden > 0 {
+                println!("{hidden} processes with a smaller PGA not displayed");
+            }
+            println!();
+        }
     }
 
     fn finalize(
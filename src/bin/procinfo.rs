@@ -81,8 +81,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = std::env::args().collect();
 
     let pid: i32 = args
-        .iter()
-        .nth(1)
+        .get(1)
         .map(|s| s.parse::<i32>().expect("PID arg must be a number"))
         .expect("Insert PID");
 
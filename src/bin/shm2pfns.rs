@@ -20,7 +20,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 None
             }
         })
-        .map(|map| {
+        .flat_map(|map| {
             let (start, end) = map.get_range().get();
 
             //let counts = kpagecount
@@ -36,7 +36,6 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
             v
         })
-        .flatten()
         .collect();
 
     // (key, id) -> PFNs
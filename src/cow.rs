@@ -0,0 +1,185 @@
+use std::{
+    collections::{HashMap, HashSet},
+    hash::BuildHasherDefault,
+};
+
+use itertools::Itertools;
+use procfs::{process::Pfn, PhysicalPageFlags};
+use rustc_hash::FxHasher;
+use tabled::Tabled;
+
+use crate::process_tree::ProcessTree;
+use crate::tmpfs::format_units_MiB;
+use crate::ProcessInfo;
+
+/// Anonymous pages shared by processes of the same fork family
+#[derive(Debug)]
+pub struct ForkFamily {
+    /// Closest common ancestor of the processes sharing the pages
+    pub root: i32,
+    pub pids: HashSet<i32>,
+    /// Distinct shared PFNs
+    pub shared_pfns: u64,
+    /// Sum of (references - 1) for each PFN: pages that would be duplicated without CoW
+    pub saved_pfns: u64,
+}
+
+/// Find anon PFNs referenced by more than 1 process
+///
+/// After `fork`, parent and child map the same anon pages until one of them writes to it (CoW).
+/// memstats counts these pages in the `anon_pfns` of each process.
+/// KSM pages are also shared between processes, but have nothing to do with fork, they are excluded
+pub fn find_shared_anon(
+    processes_info: &[ProcessInfo],
+    all_physical_pages: &HashMap<Pfn, PhysicalPageFlags>,
+) -> HashMap<Pfn, Vec<i32>, BuildHasherDefault<FxHasher>> {
+    // first pass: only count references, most pages are not shared
+    let mut counts: HashMap<Pfn, u32, BuildHasherDefault<FxHasher>> = Default::default();
+    for process_info in processes_info {
+        for pfn in &process_info.anon_pfns {
+            if pfn.0 == 0 {
+                continue;
+            }
+            *counts.entry(*pfn).or_default() += 1;
+        }
+    }
+    // vdso and other special mappings are counted in `anon_pfns`, but are not anonymous pages
+    counts.retain(|pfn, count| {
+        *count > 1
+            && all_physical_pages.get(pfn).is_some_and(|flags| {
+                flags.contains(PhysicalPageFlags::ANON) && !flags.contains(PhysicalPageFlags::KSM)
+            })
+    });
+
+    // second pass: get pids for shared pages
+    let mut shared: HashMap<Pfn, Vec<i32>, BuildHasherDefault<FxHasher>> = Default::default();
+    for process_info in processes_info {
+        for pfn in &process_info.anon_pfns {
+            if counts.contains_key(pfn) {
+                shared
+                    .entry(*pfn)
+                    .or_default()
+                    .push(process_info.process.pid);
+            }
+        }
+    }
+
+    shared
+}
+
+/// Group shared PFNs by the closest common ancestor of the processes referencing them
+pub fn get_fork_families(
+    shared: &HashMap<Pfn, Vec<i32>, BuildHasherDefault<FxHasher>>,
+    tree: &ProcessTree,
+) -> Vec<ForkFamily> {
+    let mut ancestors_cache: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut families: HashMap<i32, ForkFamily> = HashMap::new();
+
+    for pids in shared.values() {
+        for pid in pids {
+            ancestors_cache
+                .entry(*pid)
+                .or_insert_with(|| tree.ancestors(*pid, true));
+        }
+
+        // first ancestor of pids[0] that is also an ancestor of every other pid
+        let first = &ancestors_cache[&pids[0]];
+        let root = first
+            .iter()
+            .find(|ancestor| {
+                pids[1..]
+                    .iter()
+                    .all(|pid| ancestors_cache[pid].contains(ancestor))
+            })
+            .copied()
+            .unwrap_or(1);
+
+        let family = families.entry(root).or_insert_with(|| ForkFamily {
+            root,
+            pids: HashSet::new(),
+            shared_pfns: 0,
+            saved_pfns: 0,
+        });
+        family.pids.extend(pids);
+        family.shared_pfns += 1;
+        family.saved_pfns += pids.len() as u64 - 1;
+    }
+
+    families
+        .into_values()
+        .sorted_by_key(|family| std::cmp::Reverse(family.saved_pfns))
+        .collect()
+}
+
+pub fn display_shared_anon(
+    processes_info: &[ProcessInfo],
+    all_physical_pages: &HashMap<Pfn, PhysicalPageFlags>,
+    tree: &ProcessTree,
+) {
+    #[derive(Tabled)]
+    struct ForkFamilyDisplayRow {
+        root_pid: i32,
+        root_comm: String,
+        procs: usize,
+        #[tabled(display = "format_units_MiB")]
+        family_anon: u64,
+        #[tabled(display = "format_units_MiB")]
+        shared_anon: u64,
+        #[tabled(display = "format_units_MiB")]
+        saved: u64,
+    }
+
+    let chrono = std::time::Instant::now();
+    let page_size = procfs::page_size();
+
+    let shared = find_shared_anon(processes_info, all_physical_pages);
+    let families = get_fork_families(&shared, tree);
+
+    let comm = |pid: i32| {
+        procfs::process::Process::new(pid)
+            .and_then(|p| p.stat())
+            .map(|stat| stat.comm)
+            .unwrap_or_else(|_| "?".to_string())
+    };
+
+    let display_info: Vec<ForkFamilyDisplayRow> = families
+        .iter()
+        .map(|family| {
+            let mut family_anon: HashSet<Pfn, BuildHasherDefault<FxHasher>> = Default::default();
+            for process_info in processes_info
+                .iter()
+                .filter(|p| family.pids.contains(&p.process.pid))
+            {
+                family_anon.extend(&process_info.anon_pfns);
+            }
+
+            ForkFamilyDisplayRow {
+                root_pid: family.root,
+                root_comm: comm(family.root),
+                procs: family.pids.len(),
+                family_anon: family_anon.len() as u64 * page_size,
+                shared_anon: family.shared_pfns * page_size,
+                saved: family.saved_pfns * page_size,
+            }
+        })
+        .collect();
+
+    if display_info.is_empty() {
+        println!("No anonymous memory shared between processes");
+        println!();
+        return;
+    }
+
+    let total_saved: u64 = display_info.iter().map(|row| row.saved).sum();
+
+    println!("Anonymous memory shared after fork (CoW), by closest common ancestor:");
+    let mut table = tabled::Table::new(&display_info);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!(
+        "Total memory saved by CoW sharing: {}",
+        format_units_MiB(&total_saved)
+    );
+    log::debug!("Shared anon report: {:?}", chrono.elapsed());
+    println!();
+}
@@ -0,0 +1,249 @@
+//! Protocol of the `get-db-info` subprocess
+//!
+//! memstats runs as root, but connects to each Oracle instance as its owner. The parent re-executes
+//! itself with the user, groups and environment of the instance, writes a [`DbInfoRequest`] on the
+//! stdin of the child, and reads a single [`DbInfoResponse`] line from its stdout
+
+use std::{fmt::Display, io::BufRead, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::SmonInfo;
+
+/// Bumped on any change of the request, the response or `SmonInfo`
+pub const PROTOCOL_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbInfoRequest {
+    pub version: u32,
+    /// pmon of the instance
+    pub pid: i32,
+    /// The parent kills the child after this delay, queries must stop before
+    pub timeout: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DbInfoResponse {
+    pub version: u32,
+    pub result: Result<SmonInfo, DbInfoError>,
+}
+
+impl DbInfoResponse {
+    pub fn new(result: Result<SmonInfo, DbInfoError>) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            result,
+        }
+    }
+}
+
+/// Why the info of an instance is missing or incomplete
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum DbInfoError {
+    /// Environment variable of the instance not set, e.g. ORACLE_HOME
+    MissingEnv(String),
+    /// Connection refused, e.g. ORA-01034 when the instance is starting
+    Connect { code: Option<i32>, message: String },
+    /// Query failed, `context` is the queried view
+    Oracle {
+        context: String,
+        code: Option<i32>,
+        message: String,
+    },
+    /// Out of time before or while querying `context`
+    Timeout(String),
+    /// Can't run the subprocess, or can't understand its response
+    Subprocess(String),
+}
+
+impl DbInfoError {
+    /// ODPI-C error raised when the call timeout expires
+    const DPI_CALL_TIMEOUT: i32 = 1067;
+
+    pub fn connect(e: oracle::Error) -> Self {
+        Self::Connect {
+            code: e.oci_code(),
+            message: e.to_string(),
+        }
+    }
+
+    pub fn query(context: &str, e: oracle::Error) -> Self {
+        if e.dpi_code() == Some(Self::DPI_CALL_TIMEOUT) {
+            return Self::Timeout(context.to_string());
+        }
+        Self::Oracle {
+            context: context.to_string(),
+            code: e.oci_code(),
+            message: e.to_string(),
+        }
+    }
+}
+
+impl Display for DbInfoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingEnv(variable) => write!(f, "{variable} is not set"),
+            Self::Connect { message, .. } => write!(f, "can't connect: {message}"),
+            Self::Oracle {
+                context, message, ..
+            } => write!(f, "{context}: {message}"),
+            Self::Timeout(context) => write!(f, "timeout: {context}"),
+            Self::Subprocess(message) => write!(f, "subprocess: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for DbInfoError {}
+
+/// Request sent by the parent, on the first line of `input`
+pub fn read_request(input: impl BufRead) -> Result<DbInfoRequest, DbInfoError> {
+    let line = input
+        .lines()
+        .next()
+        .ok_or_else(|| DbInfoError::Subprocess("no request".to_string()))?
+        .map_err(|e| DbInfoError::Subprocess(format!("can't read request: {e}")))?;
+    let request: serde_json::Value = serde_json::from_str(&line)
+        .map_err(|e| DbInfoError::Subprocess(format!("invalid request: {e}")))?;
+    check_version(&request)?;
+    serde_json::from_value(request)
+        .map_err(|e| DbInfoError::Subprocess(format!("invalid request: {e}")))
+}
+
+/// Response of the child, on the last line of `output`
+pub fn parse_response(output: &str) -> Result<SmonInfo, DbInfoError> {
+    let line = output
+        .lines()
+        .rfind(|line| !line.trim().is_empty())
+        .ok_or_else(|| DbInfoError::Subprocess("no response".to_string()))?;
+    let response: serde_json::Value = serde_json::from_str(line)
+        .map_err(|e| DbInfoError::Subprocess(format!("invalid response: {e}")))?;
+    check_version(&response)?;
+    let response: DbInfoResponse = serde_json::from_value(response)
+        .map_err(|e| DbInfoError::Subprocess(format!("invalid response: {e}")))?;
+    response.result
+}
+
+/// Parent and child are the same binary, unless it was replaced while memstats runs
+fn check_version(message: &serde_json::Value) -> Result<(), DbInfoError> {
+    match message.get("version").and_then(|version| version.as_u64()) {
+        Some(version) if version == PROTOCOL_VERSION as u64 => Ok(()),
+        Some(version) => Err(DbInfoError::Subprocess(format!(
+            "protocol version {version}, expected {PROTOCOL_VERSION}"
+        ))),
+        None => Err(DbInfoError::Subprocess("no protocol version".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smon_info(pid: i32) -> SmonInfo {
+        SmonInfo {
+            pid,
+            sid: "ORCL1".into(),
+            kind: crate::InstanceKind::Database,
+            sga_size: 0,
+            large_pages: crate::LargePages::Only,
+            processes: 0,
+            pga_size: 0,
+            pga_processes: Vec::new(),
+            sga_layout: Default::default(),
+            containers: Vec::new(),
+            asm: None,
+            errors: Vec::new(),
+        }
+    }
+
+    fn subprocess_error<T: std::fmt::Debug>(result: Result<T, DbInfoError>) -> String {
+        match result {
+            Err(DbInfoError::Subprocess(message)) => message,
+            other => panic!("expected a subprocess error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn request() {
+        let request = DbInfoRequest {
+            version: PROTOCOL_VERSION,
+            pid: 1234,
+            timeout: Duration::from_secs(10),
+        };
+        let input = format!("{}\nignored\n", serde_json::to_string(&request).unwrap());
+        let request = read_request(input.as_bytes()).unwrap();
+        assert_eq!(request.pid, 1234);
+        assert_eq!(request.timeout, Duration::from_secs(10));
+    }
+
+    #[test]
+    fn invalid_request() {
+        assert_eq!(subprocess_error(read_request("".as_bytes())), "no request");
+        assert!(
+            subprocess_error(read_request("pid 1234\n".as_bytes())).starts_with("invalid request")
+        );
+        assert!(
+            subprocess_error(read_request(r#"{"version": 2, "pid": 1234}"#.as_bytes()))
+                .starts_with("invalid request")
+        );
+        let message = subprocess_error(read_request(
+            r#"{"version": 1, "pid": 1234, "timeout": {"secs": 10, "nanos": 0}}"#.as_bytes(),
+        ));
+        assert_eq!(
+            message,
+            format!("protocol version 1, expected {PROTOCOL_VERSION}")
+        );
+    }
+
+    #[test]
+    fn response() {
+        let response = serde_json::to_string(&DbInfoResponse::new(Ok(smon_info(1234)))).unwrap();
+        // the Oracle client may print on stdout before the response
+        let output = format!("Message from the client library\n{response}\n\n");
+        let info = parse_response(&output).unwrap();
+        assert_eq!(info.pid, 1234);
+        assert_eq!(info.sid, "ORCL1");
+    }
+
+    #[test]
+    fn error_response() {
+        let response = DbInfoResponse::new(Err(DbInfoError::Timeout("v$sga".to_string())));
+        let output = serde_json::to_string(&response).unwrap();
+        match parse_response(&output) {
+            Err(DbInfoError::Timeout(context)) => assert_eq!(context, "v$sga"),
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_response() {
+        assert_eq!(subprocess_error(parse_response("")), "no response");
+        assert_eq!(subprocess_error(parse_response("\n  \n")), "no response");
+        assert!(subprocess_error(parse_response("ORA-12345\n")).starts_with("invalid response"));
+        assert!(subprocess_error(parse_response(&format!(
+            r#"{{"version": {PROTOCOL_VERSION}}}"#
+        )))
+        .starts_with("invalid response"));
+    }
+
+    #[test]
+    fn version() {
+        assert!(check_version(&serde_json::json!({ "version": PROTOCOL_VERSION })).is_ok());
+        assert_eq!(
+            subprocess_error(check_version(
+                &serde_json::json!({ "version": PROTOCOL_VERSION + 1 })
+            )),
+            format!(
+                "protocol version {}, expected {PROTOCOL_VERSION}",
+                PROTOCOL_VERSION + 1
+            )
+        );
+        assert_eq!(
+            subprocess_error(check_version(&serde_json::json!({ "version": "2" }))),
+            "no protocol version"
+        );
+        assert_eq!(
+            subprocess_error(check_version(&serde_json::json!({ "pid": 1234 }))),
+            "no protocol version"
+        );
+    }
+}
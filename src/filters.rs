@@ -6,7 +6,7 @@ use procfs::process::Process;
 
 use crate::process_tree::ProcessTree;
 
-pub trait Filter: std::fmt::Debug {
+pub trait Filter: std::fmt::Debug + Send + Sync {
     fn eval(&self, p: &Process, tree: &ProcessTree) -> bool;
 }
 
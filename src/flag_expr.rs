@@ -0,0 +1,256 @@
+use anyhow::{bail, Context, Result};
+use procfs_core::PhysicalPageFlags;
+use std::str::FromStr;
+
+/// Boolean expression of physical page flags
+///
+/// `ANON & !SWAPBACKED`
+/// `LRU & ACTIVE & !REFERENCED`
+/// `(COMPOUND_HEAD | COMPOUND_TAIL) & HUGE`
+///
+/// `!` binds tighter than `&`, which binds tighter than `|`. Flag names are case insensitive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlagExpr {
+    Flag(PhysicalPageFlags),
+    Not(Box<FlagExpr>),
+    And(Vec<FlagExpr>),
+    Or(Vec<FlagExpr>),
+}
+
+impl FlagExpr {
+    pub fn eval(&self, flags: PhysicalPageFlags) -> bool {
+        match self {
+            FlagExpr::Flag(flag) => flags.contains(*flag),
+            FlagExpr::Not(inner) => !inner.eval(flags),
+            FlagExpr::And(children) => children.iter().all(|child| child.eval(flags)),
+            FlagExpr::Or(children) => children.iter().any(|child| child.eval(flags)),
+        }
+    }
+}
+
+impl FromStr for FlagExpr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            bail!("Unexpected {:?} in {:?}", token, s);
+        }
+        Ok(expr)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Flag(PhysicalPageFlags),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut iter = input.char_indices().peekable();
+    while let Some((idx, c)) = iter.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '!' => Token::Not,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = idx + c.len_utf8();
+                while let Some(&(next_idx, next)) = iter.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    end = next_idx + next.len_utf8();
+                    iter.next();
+                }
+                let name = input[idx..end].to_uppercase();
+                let flag = PhysicalPageFlags::from_name(&name)
+                    .with_context(|| format!("Unknown flag {:?}", &input[idx..end]))?;
+                Token::Flag(flag)
+            }
+            c => bail!("Unexpected character {:?} at {}", c, idx),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> bool {
+        if self.tokens.get(self.pos) == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// or := and ('|' and)*
+    fn parse_or(&mut self) -> Result<FlagExpr> {
+        let mut children = vec![self.parse_and()?];
+        while self.next_if(&Token::Or) {
+            children.push(self.parse_and()?);
+        }
+        Ok(if children.len() == 1 {
+            children.pop().unwrap()
+        } else {
+            FlagExpr::Or(children)
+        })
+    }
+
+    /// and := not ('&' not)*
+    fn parse_and(&mut self) -> Result<FlagExpr> {
+        let mut children = vec![self.parse_not()?];
+        while self.next_if(&Token::And) {
+            children.push(self.parse_not()?);
+        }
+        Ok(if children.len() == 1 {
+            children.pop().unwrap()
+        } else {
+            FlagExpr::And(children)
+        })
+    }
+
+    /// not := '!' not | flag | '(' or ')'
+    fn parse_not(&mut self) -> Result<FlagExpr> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .with_context(|| "Unexpected end of expression")?;
+        self.pos += 1;
+
+        match token {
+            Token::Not => Ok(FlagExpr::Not(Box::new(self.parse_not()?))),
+            Token::Flag(flag) => Ok(FlagExpr::Flag(flag)),
+            Token::Open => {
+                let inner = self.parse_or()?;
+                if !self.next_if(&Token::Close) {
+                    bail!("Missing closing parenthesis");
+                }
+                Ok(inner)
+            }
+            token => bail!("Expected a flag, '!' or '(', got {:?}", token),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag(flag: PhysicalPageFlags) -> FlagExpr {
+        FlagExpr::Flag(flag)
+    }
+
+    fn parse(s: &str) -> FlagExpr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        // ! > & > |
+        assert_eq!(
+            parse("!ANON & LRU | SLAB"),
+            FlagExpr::Or(vec![
+                FlagExpr::And(vec![
+                    FlagExpr::Not(Box::new(flag(PhysicalPageFlags::ANON))),
+                    flag(PhysicalPageFlags::LRU),
+                ]),
+                flag(PhysicalPageFlags::SLAB),
+            ])
+        );
+        assert_eq!(
+            parse("SLAB | LRU & !ANON"),
+            FlagExpr::Or(vec![
+                flag(PhysicalPageFlags::SLAB),
+                FlagExpr::And(vec![
+                    flag(PhysicalPageFlags::LRU),
+                    FlagExpr::Not(Box::new(flag(PhysicalPageFlags::ANON))),
+                ]),
+            ])
+        );
+    }
+
+    #[test]
+    fn or_without_parenthesis() {
+        // HEAD | (TAIL & HUGE), not (HEAD | TAIL) & HUGE
+        let expr = parse("COMPOUND_HEAD|COMPOUND_TAIL & HUGE");
+        assert_eq!(
+            expr,
+            FlagExpr::Or(vec![
+                flag(PhysicalPageFlags::COMPOUND_HEAD),
+                FlagExpr::And(vec![
+                    flag(PhysicalPageFlags::COMPOUND_TAIL),
+                    flag(PhysicalPageFlags::HUGE),
+                ]),
+            ])
+        );
+        assert!(expr.eval(PhysicalPageFlags::COMPOUND_HEAD));
+        assert!(!expr.eval(PhysicalPageFlags::COMPOUND_TAIL));
+    }
+
+    #[test]
+    fn parenthesis() {
+        let expr = parse("(COMPOUND_HEAD | COMPOUND_TAIL) & HUGE");
+        assert_eq!(
+            expr,
+            FlagExpr::And(vec![
+                FlagExpr::Or(vec![
+                    flag(PhysicalPageFlags::COMPOUND_HEAD),
+                    flag(PhysicalPageFlags::COMPOUND_TAIL),
+                ]),
+                flag(PhysicalPageFlags::HUGE),
+            ])
+        );
+        assert!(!expr.eval(PhysicalPageFlags::COMPOUND_HEAD));
+        assert!(expr.eval(PhysicalPageFlags::COMPOUND_TAIL | PhysicalPageFlags::HUGE));
+
+        assert_eq!(parse("((ANON))"), flag(PhysicalPageFlags::ANON));
+        assert_eq!(
+            parse("!(ANON | KSM)"),
+            FlagExpr::Not(Box::new(FlagExpr::Or(vec![
+                flag(PhysicalPageFlags::ANON),
+                flag(PhysicalPageFlags::KSM),
+            ])))
+        );
+        assert!("(ANON".parse::<FlagExpr>().is_err());
+        assert!("()".parse::<FlagExpr>().is_err());
+    }
+
+    #[test]
+    fn case_insensitive() {
+        assert_eq!(parse("anon"), flag(PhysicalPageFlags::ANON));
+        assert_eq!(parse("Compound_Head"), parse("COMPOUND_HEAD"));
+    }
+
+    #[test]
+    fn unknown_flag() {
+        let error = "ANON & NOT_A_FLAG".parse::<FlagExpr>().unwrap_err();
+        assert!(error.to_string().contains("NOT_A_FLAG"));
+        assert!("ANON + LRU".parse::<FlagExpr>().is_err());
+    }
+
+    #[test]
+    fn trailing_tokens() {
+        assert!("ANON LRU".parse::<FlagExpr>().is_err());
+        assert!("ANON)".parse::<FlagExpr>().is_err());
+        assert!("ANON &".parse::<FlagExpr>().is_err());
+        assert!("ANON |".parse::<FlagExpr>().is_err());
+        assert!("".parse::<FlagExpr>().is_err());
+    }
+}
@@ -0,0 +1,782 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Result};
+use procfs::{process::Pfn, KPageFlags, PhysicalMemoryMap, PhysicalPageFlags};
+use tabled::Tabled;
+
+use crate::memory_layout::{parse_zoneinfo, Zone};
+use crate::tmpfs::format_units_MiB;
+
+/// Free blocks of each order, for a zone, from /proc/buddyinfo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuddyInfo {
+    pub node: u32,
+    pub zone: String,
+    /// Number of free blocks of 2^order pages, indexed by order
+    pub free: Vec<u64>,
+}
+
+impl BuddyInfo {
+    pub fn free_pages(&self) -> u64 {
+        self.free
+            .iter()
+            .enumerate()
+            .map(|(order, count)| count << order)
+            .sum()
+    }
+
+    /// Fraction of free memory that can't be used for an allocation of 2^order pages
+    ///
+    /// Same definition as the kernel unusable index (debugfs extfrag/unusable_index).
+    /// None if there is no free memory
+    pub fn unusable_index(&self, order: usize) -> Option<f64> {
+        let total = self.free_pages();
+        if total == 0 {
+            return None;
+        }
+        let usable: u64 = self
+            .free
+            .iter()
+            .enumerate()
+            .skip(order)
+            .map(|(order, count)| count << order)
+            .sum();
+        Some((total - usable) as f64 / total as f64)
+    }
+}
+
+pub fn parse_buddyinfo(content: &str) -> Vec<BuddyInfo> {
+    content
+        .lines()
+        .filter_map(|line| {
+            // Node 0, zone   Normal   6077   2370    831 ...
+            let (node, rest) = line.strip_prefix("Node ")?.split_once(", zone")?;
+            let mut fields = rest.split_whitespace();
+            let zone = fields.next()?.to_string();
+            Some(BuddyInfo {
+                node: node.trim().parse().ok()?,
+                zone,
+                free: fields.filter_map(|count| count.parse().ok()).collect(),
+            })
+        })
+        .collect()
+}
+
+/// Free blocks and pageblocks per migrate type, from /proc/pagetypeinfo
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PageTypeInfo {
+    pub pageblock_order: u32,
+    /// (node, zone, migrate type) -> free blocks indexed by order
+    pub free: BTreeMap<(u32, String, String), Vec<u64>>,
+    /// (node, zone, migrate type) -> number of pageblocks
+    pub blocks: BTreeMap<(u32, String, String), u64>,
+}
+
+pub fn parse_pagetypeinfo(content: &str) -> PageTypeInfo {
+    let mut info = PageTypeInfo::default();
+    let mut block_types: Vec<String> = Vec::new();
+
+    for line in content.lines() {
+        if let Some(order) = line.strip_prefix("Page block order:") {
+            info.pageblock_order = order.trim().parse().unwrap_or(0);
+        } else if let Some(types) = line.strip_prefix("Number of blocks type") {
+            block_types = types.split_whitespace().map(|t| t.to_string()).collect();
+        } else if let Some(rest) = line.strip_prefix("Node") {
+            let Some((node, rest)) = rest.split_once(", zone") else {
+                continue;
+            };
+            let Ok(node) = node.trim().parse::<u32>() else {
+                continue;
+            };
+
+            if let Some((zone, rest)) = rest.split_once(", type") {
+                // Node    0, zone   Normal, type    Movable   6018   2305 ...
+                let mut fields = rest.split_whitespace();
+                let Some(migrate_type) = fields.next() else {
+                    continue;
+                };
+                let free = fields.filter_map(|count| count.parse().ok()).collect();
+                info.free.insert(
+                    (node, zone.trim().to_string(), migrate_type.to_string()),
+                    free,
+                );
+            } else {
+                // Node 0, zone   Normal     64   1423     49 ...
+                let mut fields = rest.split_whitespace();
+                let Some(zone) = fields.next() else {
+                    continue;
+                };
+                for (migrate_type, count) in block_types.iter().zip(fields) {
+                    if let Ok(count) = count.parse() {
+                        info.blocks
+                            .insert((node, zone.to_string(), migrate_type.clone()), count);
+                    }
+                }
+            }
+        }
+    }
+
+    info
+}
+
+/// What kind of allocated page prevents free runs from merging
+pub fn flag_class(flags: PhysicalPageFlags) -> &'static str {
+    if flags.is_empty() {
+        "kernel (no flags)"
+    } else if flags.intersects(PhysicalPageFlags::OFFLINE | PhysicalPageFlags::HWPOISON) {
+        "offline/hwpoison"
+    } else if flags.contains(PhysicalPageFlags::SLAB) {
+        "slab"
+    } else if flags.contains(PhysicalPageFlags::PGTABLE) {
+        "page table"
+    } else if flags.contains(PhysicalPageFlags::UNEVICTABLE) {
+        "unevictable (mlocked, pinned)"
+    } else if flags.contains(PhysicalPageFlags::HUGE) {
+        "hugetlb"
+    } else if flags.contains(PhysicalPageFlags::THP) {
+        "thp"
+    } else if flags.contains(PhysicalPageFlags::KSM) {
+        "ksm"
+    } else if flags.contains(PhysicalPageFlags::ANON) {
+        "anon"
+    } else if flags.contains(PhysicalPageFlags::LRU) {
+        "page cache"
+    } else {
+        "other kernel"
+    }
+}
+
+/// Contiguous free pages of a zone, from kpageflags
+#[derive(Debug)]
+pub struct ZoneFreeRuns {
+    pub zone: Zone,
+    /// (start, pages), sorted by start
+    pub runs: Vec<(Pfn, u64)>,
+    /// Allocated pages bounding the largest runs, by class
+    pub largest_runs_blockers: HashMap<&'static str, u64>,
+    /// Allocated pages inside aligned huge blocks that are at least 90% free, by class
+    pub almost_free_blockers: HashMap<&'static str, u64>,
+}
+
+impl ZoneFreeRuns {
+    pub fn free_pages(&self) -> u64 {
+        self.runs.iter().map(|(_, pages)| pages).sum()
+    }
+
+    /// Fraction of free memory that can't be used for an aligned allocation of `pages` pages
+    pub fn unusable_index(&self, pages: u64) -> Option<f64> {
+        let total = self.free_pages();
+        if total == 0 {
+            return None;
+        }
+        let usable: u64 = self
+            .runs
+            .iter()
+            .map(|(start, len)| {
+                let first = start.0.div_ceil(pages);
+                let last = (start.0 + len) / pages;
+                last.saturating_sub(first) * pages
+            })
+            .sum();
+        Some((total - usable) as f64 / total as f64)
+    }
+
+    /// Number of runs and free pages, by power of 2 of the run length
+    pub fn histogram(&self) -> BTreeMap<u32, (u64, u64)> {
+        let mut histogram = BTreeMap::new();
+        for (_, pages) in &self.runs {
+            let entry = histogram.entry(pages.ilog2()).or_insert((0, 0));
+            entry.0 += 1;
+            entry.1 += pages;
+        }
+        histogram
+    }
+}
+
+/// Free pages are found from BUDDY pages: kpageflags only flags the first page of a free block
+///
+/// The block order is not exported, so it is guessed as the largest aligned block whose other
+/// pages have no flags at all. Pages allocated by the kernel (vmalloc, drivers, percpu) have no
+/// flags either, so the guess is capped by `budget`: the number of free blocks of each order left
+/// in /proc/buddyinfo, indexed by order
+fn free_blocks(start: Pfn, flags: &[PhysicalPageFlags], budget: &mut [u64]) -> Vec<(Pfn, u64)> {
+    let max_order = budget.len().saturating_sub(1) as u32;
+    let mut blocks = Vec::new();
+    let mut idx = 0;
+    while idx < flags.len() {
+        if !flags[idx].contains(PhysicalPageFlags::BUDDY) {
+            idx += 1;
+            continue;
+        }
+        let pfn = start.0 + idx as u64;
+        let max_aligned = pfn.trailing_zeros().min(max_order);
+        let order = (0..=max_aligned)
+            .rev()
+            .find(|order| {
+                let end = idx + (1 << order);
+                budget.get(*order as usize).is_some_and(|count| *count > 0)
+                    && end <= flags.len()
+                    && flags[idx + 1..end].iter().all(|f| f.is_empty())
+            })
+            .unwrap_or(0);
+        if let Some(count) = budget.get_mut(order as usize) {
+            *count = count.saturating_sub(1);
+        }
+        blocks.push((Pfn(pfn), 1 << order));
+        idx += 1 << order;
+    }
+    blocks
+}
+
+/// Pages of a huge block: a pageblock, else a PMD huge page, else 2 MiB
+///
+/// 2 MiB with 4K pages on x86_64, but 512 MiB with 64K pages on arm64
+pub fn huge_block_pages(
+    pageblock_order: Option<u32>,
+    hpage_pmd_size: Option<u64>,
+    page_size: u64,
+) -> u64 {
+    pageblock_order
+        .filter(|order| *order > 0)
+        .map(|order| 1 << order)
+        .or_else(|| hpage_pmd_size.map(|size| size / page_size))
+        .filter(|pages| *pages > 0)
+        .unwrap_or((2 * 1024 * 1024 / page_size).max(1))
+}
+
+/// Scan kpageflags of each zone, and merge adjacent free blocks into runs
+///
+/// Free blocks of each zone are capped by `buddyinfo`, zones missing from it are capped at
+/// `max_order` only. Allocated pages are counted in aligned blocks of `block_pages` pages
+pub fn scan_free_runs(
+    iomem: &[PhysicalMemoryMap],
+    zones: &[Zone],
+    buddyinfo: &[BuddyInfo],
+    kpageflags: &mut KPageFlags,
+    max_order: u32,
+    block_pages: u64,
+    largest_runs: usize,
+) -> Result<Vec<ZoneFreeRuns>> {
+    let page_size = procfs::page_size();
+    let block_pages = block_pages as usize;
+
+    let mut result = Vec::new();
+    for zone in zones {
+        let mut zone_runs = ZoneFreeRuns {
+            zone: zone.clone(),
+            runs: Vec::new(),
+            largest_runs_blockers: HashMap::new(),
+            almost_free_blockers: HashMap::new(),
+        };
+        let mut budget = buddyinfo
+            .iter()
+            .find(|info| info.node == zone.node && info.zone == zone.name)
+            .map(|info| info.free.clone())
+            .unwrap_or_else(|| vec![u64::MAX; max_order as usize + 1]);
+        // flags of the pages before and after each run, to find blockers
+        let mut neighbours: Vec<[Option<PhysicalPageFlags>; 2]> = Vec::new();
+        // last page of the previous RAM segment
+        let mut previous: Option<(Pfn, PhysicalPageFlags)> = None;
+
+        for map in iomem {
+            let start = Pfn((map.address.0 / page_size).max(zone.start_pfn.0));
+            let end = Pfn(((map.address.1 + 1) / page_size).min(zone.end_pfn.0));
+            if start >= end {
+                continue;
+            }
+            let flags = kpageflags
+                .get_range_info(start, end)
+                .with_context(|| format!("Can't read kpageflags {start:?}-{end:?}"))?;
+            let flags_at = |pfn: u64| match previous {
+                Some((last, last_flags)) if last.0 == pfn => Some(last_flags),
+                _ => pfn
+                    .checked_sub(start.0)
+                    .and_then(|offset| flags.get(offset as usize))
+                    .copied(),
+            };
+
+            // the run ending at the previous segment is bounded by the first page of this one
+            if let (Some((run_start, run_pages)), Some(bounds)) =
+                (zone_runs.runs.last(), neighbours.last_mut())
+            {
+                if run_start.0 + run_pages == start.0 {
+                    bounds[1] = flags.first().copied();
+                }
+            }
+
+            let blocks = free_blocks(start, &flags, &mut budget);
+            let mut free_pfns = vec![false; flags.len()];
+            for &(block_start, pages) in &blocks {
+                let offset = (block_start.0 - start.0) as usize;
+                free_pfns[offset..offset + pages as usize].fill(true);
+
+                let after = flags_at(block_start.0 + pages);
+                match (zone_runs.runs.last_mut(), neighbours.last_mut()) {
+                    (Some((run_start, run_pages)), Some(bounds))
+                        if run_start.0 + *run_pages == block_start.0 =>
+                    {
+                        *run_pages += pages;
+                        bounds[1] = after;
+                    }
+                    _ => {
+                        zone_runs.runs.push((block_start, pages));
+                        let before = block_start.0.checked_sub(1).and_then(flags_at);
+                        neighbours.push([before, after]);
+                    }
+                }
+            }
+
+            // aligned huge blocks, mostly free
+            let first_block = (start.0 as usize).div_ceil(block_pages) * block_pages;
+            for block in (first_block..end.0 as usize).step_by(block_pages) {
+                let offset = block - start.0 as usize;
+                let (Some(block_flags), Some(block_free)) = (
+                    flags.get(offset..offset + block_pages),
+                    free_pfns.get(offset..offset + block_pages),
+                ) else {
+                    break;
+                };
+                let free = block_free.iter().filter(|free| **free).count();
+                if free >= block_pages * 9 / 10 && free < block_pages {
+                    for (flags, _) in block_flags
+                        .iter()
+                        .zip(block_free)
+                        .filter(|(_, free)| !**free)
+                    {
+                        *zone_runs
+                            .almost_free_blockers
+                            .entry(flag_class(*flags))
+                            .or_default() += 1;
+                    }
+                }
+            }
+
+            previous = flags.last().map(|last| (Pfn(end.0 - 1), *last));
+        }
+
+        // pages right before and after the largest runs
+        let mut sorted: Vec<_> = zone_runs.runs.iter().zip(&neighbours).collect();
+        sorted.sort_by_key(|((_, pages), _)| std::cmp::Reverse(*pages));
+        for (_, bounds) in sorted.into_iter().take(largest_runs) {
+            for flags in bounds.iter().flatten() {
+                *zone_runs
+                    .largest_runs_blockers
+                    .entry(flag_class(*flags))
+                    .or_default() += 1;
+            }
+        }
+
+        result.push(zone_runs);
+    }
+
+    Ok(result)
+}
+
+/// Fragmentation of each zone
+pub struct FragmentationReport {
+    pub page_size: u64,
+    /// Pages of a huge block, see [`huge_block_pages`]
+    pub block_pages: u64,
+    pub buddyinfo: Vec<BuddyInfo>,
+    pub pagetypeinfo: Option<PageTypeInfo>,
+    pub free_runs: Vec<ZoneFreeRuns>,
+}
+
+/// Number of largest runs inspected for blockers, per zone
+pub const LARGEST_RUNS: usize = 10;
+
+impl FragmentationReport {
+    pub fn new() -> Result<Self> {
+        let page_size = procfs::page_size();
+        let buddyinfo = parse_buddyinfo(
+            &std::fs::read_to_string("/proc/buddyinfo").context("Can't read /proc/buddyinfo")?,
+        );
+        // root only
+        let pagetypeinfo = std::fs::read_to_string("/proc/pagetypeinfo")
+            .ok()
+            .map(|content| parse_pagetypeinfo(&content));
+        let hpage_pmd_size =
+            std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/hpage_pmd_size")
+                .ok()
+                .and_then(|size| size.trim().parse().ok());
+        let block_pages = huge_block_pages(
+            pagetypeinfo.as_ref().map(|info| info.pageblock_order),
+            hpage_pmd_size,
+            page_size,
+        );
+        let zones = parse_zoneinfo(
+            &std::fs::read_to_string("/proc/zoneinfo").context("Can't read /proc/zoneinfo")?,
+        );
+
+        let iomem: Vec<PhysicalMemoryMap> = procfs::iomem()
+            .context("Can't read /proc/iomem")?
+            .into_iter()
+            .filter(|(ident, map)| *ident == 0 && map.name == "System RAM")
+            .map(|(_, map)| map)
+            .collect();
+        let max_order = buddyinfo
+            .iter()
+            .map(|info| info.free.len() as u32)
+            .max()
+            .unwrap_or(11)
+            .saturating_sub(1);
+        let mut kpageflags = KPageFlags::new().context("Can't open /proc/kpageflags")?;
+        let free_runs = scan_free_runs(
+            &iomem,
+            &zones,
+            &buddyinfo,
+            &mut kpageflags,
+            max_order,
+            block_pages,
+            LARGEST_RUNS,
+        )?;
+
+        Ok(Self {
+            page_size,
+            block_pages,
+            buddyinfo,
+            pagetypeinfo,
+            free_runs,
+        })
+    }
+
+    pub fn display(&self) {
+        #[derive(Tabled)]
+        struct ZoneDisplayRow {
+            node: u32,
+            zone: String,
+            #[tabled(display = "format_units_MiB")]
+            free: u64,
+            #[tabled(rename = "unusable huge (buddyinfo)")]
+            unusable_huge_buddyinfo: String,
+            #[tabled(rename = "unusable huge (scan)")]
+            unusable_huge: String,
+            #[tabled(rename = "unusable 1G (scan)")]
+            unusable_1g: String,
+            #[tabled(rename = "largest run")]
+            largest_run: String,
+        }
+
+        #[derive(Tabled)]
+        struct HistogramDisplayRow {
+            node: u32,
+            zone: String,
+            #[tabled(rename = "run size")]
+            size: String,
+            runs: u64,
+            #[tabled(display = "format_units_MiB")]
+            free: u64,
+        }
+
+        #[derive(Tabled)]
+        struct MigrateTypeDisplayRow {
+            node: u32,
+            zone: String,
+            #[tabled(rename = "migrate type")]
+            migrate_type: String,
+            #[tabled(display = "format_units_MiB")]
+            free: u64,
+            pageblocks: u64,
+        }
+
+        #[derive(Tabled)]
+        struct BlockerDisplayRow {
+            node: u32,
+            zone: String,
+            class: &'static str,
+            #[tabled(rename = "bounding largest runs")]
+            largest_runs: u64,
+            #[tabled(rename = "pages in 90% free huge blocks")]
+            almost_free: u64,
+        }
+
+        let index = |index: Option<f64>| match index {
+            Some(index) => format!("{:.3}", index),
+            None => "-".to_string(),
+        };
+        let size = |pages: u64| humansize::format_size(pages * self.page_size, humansize::BINARY);
+        let page_size = self.page_size;
+
+        let zone_rows: Vec<ZoneDisplayRow> =
+            self.free_runs
+                .iter()
+                .map(|runs| {
+                    let buddyinfo = self
+                        .buddyinfo
+                        .iter()
+                        .find(|info| info.node == runs.zone.node && info.zone == runs.zone.name);
+                    ZoneDisplayRow {
+                        node: runs.zone.node,
+                        zone: runs.zone.name.clone(),
+                        free: buddyinfo
+                            .map(|info| info.free_pages())
+                            .unwrap_or_else(|| runs.free_pages())
+                            * page_size,
+                        unusable_huge_buddyinfo: index(buddyinfo.and_then(|info| {
+                            info.unusable_index(self.block_pages.ilog2() as usize)
+                        })),
+                        unusable_huge: index(runs.unusable_index(self.block_pages)),
+                        unusable_1g: index(runs.unusable_index((1 << 30) / page_size)),
+                        largest_run: runs
+                            .runs
+                            .iter()
+                            .map(|(_, pages)| *pages)
+                            .max()
+                            .map(size)
+                            .unwrap_or_else(|| "-".to_string()),
+                    }
+                })
+                .collect();
+
+        println!(
+            "Fragmentation per zone (huge block: {}, unusable index: 0 = all free memory usable, 1 = none):",
+            size(self.block_pages)
+        );
+        let mut table = tabled::Table::new(&zone_rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("{table}");
+        println!();
+
+        let histogram_rows: Vec<HistogramDisplayRow> = self
+            .free_runs
+            .iter()
+            .flat_map(|runs| {
+                runs.histogram()
+                    .into_iter()
+                    .map(|(bucket, (count, pages))| HistogramDisplayRow {
+                        node: runs.zone.node,
+                        zone: runs.zone.name.clone(),
+                        size: format!("{} - {}", size(1 << bucket), size(2 << bucket)),
+                        runs: count,
+                        free: pages * page_size,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        println!("Contiguous free runs (from kpageflags BUDDY pages):");
+        let mut table = tabled::Table::new(&histogram_rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("{table}");
+        println!();
+
+        match &self.pagetypeinfo {
+            Some(pagetypeinfo) => {
+                let rows: Vec<MigrateTypeDisplayRow> = pagetypeinfo
+                    .free
+                    .iter()
+                    .map(|((node, zone, migrate_type), free)| MigrateTypeDisplayRow {
+                        node: *node,
+                        zone: zone.clone(),
+                        migrate_type: migrate_type.clone(),
+                        free: free
+                            .iter()
+                            .enumerate()
+                            .map(|(order, count)| count << order)
+                            .sum::<u64>()
+                            * page_size,
+                        pageblocks: pagetypeinfo
+                            .blocks
+                            .get(&(*node, zone.clone(), migrate_type.clone()))
+                            .copied()
+                            .unwrap_or(0),
+                    })
+                    .filter(|row| row.free > 0 || row.pageblocks > 0)
+                    .collect();
+
+                println!(
+                    "Free memory per migrate type (pageblock: {}):",
+                    size(1 << pagetypeinfo.pageblock_order)
+                );
+                let mut table = tabled::Table::new(&rows);
+                table.with(tabled::settings::Style::sharp());
+                println!("{table}");
+                println!();
+            }
+            None => {
+                println!("Can't read /proc/pagetypeinfo");
+                println!();
+            }
+        }
+
+        let mut blocker_rows: Vec<BlockerDisplayRow> = Vec::new();
+        for runs in &self.free_runs {
+            let mut classes: Vec<&'static str> = runs
+                .largest_runs_blockers
+                .keys()
+                .chain(runs.almost_free_blockers.keys())
+                .copied()
+                .collect();
+            classes.sort();
+            classes.dedup();
+            for class in classes {
+                blocker_rows.push(BlockerDisplayRow {
+                    node: runs.zone.node,
+                    zone: runs.zone.name.clone(),
+                    class,
+                    largest_runs: runs.largest_runs_blockers.get(class).copied().unwrap_or(0),
+                    almost_free: runs.almost_free_blockers.get(class).copied().unwrap_or(0),
+                });
+            }
+        }
+
+        println!(
+            "Allocated pages breaking up free runs ({} largest runs per zone, and {} blocks at least 90% free):",
+            LARGEST_RUNS,
+            size(self.block_pages)
+        );
+        let mut table = tabled::Table::new(&blocker_rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("{table}");
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDDYINFO: &str = "\
+Node 0, zone      DMA      0      0      0      0      0      0      0      0      1      1      3 
+Node 0, zone    DMA32      1   5735   5234    964     21      3      8      9      1     13      5 
+Node 1, zone   Normal  16288   2951    882    159     91     27     39     12      4      4      0 
+";
+
+    const PAGETYPEINFO: &str = "\
+Page block order: 9
+Pages per block:  512
+
+Free pages count per migrate type at order       0      1      2      3      4      5      6      7      8      9     10 
+Node    0, zone      DMA, type    Unmovable      0      0      0      0      0      0      0      0      1      0      0 
+Node    0, zone      DMA, type      Movable      0      0      0      0      0      0      0      0      0      1      3 
+Node    1, zone   Normal, type    Unmovable    289    101     71     51     44      5      0      0      0      0      0 
+Node    1, zone   Normal, type      Movable  15999   2664    634     41     31     17     39     12      4      4      0 
+
+Number of blocks type     Unmovable      Movable  Reclaimable   HighAtomic      Isolate 
+Node 0, zone      DMA            1            7            0            0            0 
+Node 1, zone   Normal           29         1458           49            0            0 
+";
+
+    fn key(node: u32, zone: &str, migrate_type: &str) -> (u32, String, String) {
+        (node, zone.to_string(), migrate_type.to_string())
+    }
+
+    /// A BUDDY page for each `b`, an allocated page for each `a`, no flags for `.`
+    fn flags(pages: &str) -> Vec<PhysicalPageFlags> {
+        pages
+            .chars()
+            .map(|page| match page {
+                'b' => PhysicalPageFlags::BUDDY,
+                'a' => PhysicalPageFlags::LRU,
+                _ => PhysicalPageFlags::empty(),
+            })
+            .collect()
+    }
+
+    fn blocks(start: u64, pages: &str, budget: &mut [u64]) -> Vec<(u64, u64)> {
+        free_blocks(Pfn(start), &flags(pages), budget)
+            .into_iter()
+            .map(|(pfn, pages)| (pfn.0, pages))
+            .collect()
+    }
+
+    #[test]
+    fn buddyinfo() {
+        let buddyinfo = parse_buddyinfo(BUDDYINFO);
+        assert_eq!(buddyinfo.len(), 3);
+        assert_eq!(buddyinfo[0].node, 0);
+        assert_eq!(buddyinfo[0].zone, "DMA");
+        assert_eq!(buddyinfo[0].free, [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 3]);
+        assert_eq!(buddyinfo[0].free_pages(), 256 + 512 + 3 * 1024);
+        assert_eq!(buddyinfo[2].node, 1);
+        assert_eq!(buddyinfo[2].zone, "Normal");
+        assert_eq!(buddyinfo[2].free[0], 16288);
+
+        // only order 10 blocks can hold 4 MiB
+        let unusable = buddyinfo[0].unusable_index(10).unwrap();
+        assert_eq!(unusable, (256 + 512) as f64 / (256 + 512 + 3 * 1024) as f64);
+        assert_eq!(buddyinfo[0].unusable_index(0), Some(0.0));
+
+        let empty = BuddyInfo {
+            node: 0,
+            zone: "Movable".to_string(),
+            free: vec![0; 11],
+        };
+        assert_eq!(empty.unusable_index(9), None);
+        assert!(parse_buddyinfo("").is_empty());
+    }
+
+    #[test]
+    fn pagetypeinfo() {
+        let info = parse_pagetypeinfo(PAGETYPEINFO);
+        assert_eq!(info.pageblock_order, 9);
+
+        assert_eq!(info.free.len(), 4);
+        assert_eq!(
+            info.free[&key(0, "DMA", "Movable")],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 3]
+        );
+        assert_eq!(info.free[&key(1, "Normal", "Unmovable")][0], 289);
+
+        assert_eq!(info.blocks.len(), 10);
+        assert_eq!(info.blocks[&key(0, "DMA", "Movable")], 7);
+        assert_eq!(info.blocks[&key(1, "Normal", "Reclaimable")], 49);
+        assert_eq!(info.blocks[&key(1, "Normal", "Isolate")], 0);
+
+        assert_eq!(parse_pagetypeinfo(""), PageTypeInfo::default());
+    }
+
+    #[test]
+    fn huge_block() {
+        const MIB: u64 = 1024 * 1024;
+        assert_eq!(huge_block_pages(Some(9), Some(2 * MIB), 4096), 512);
+        // arm64 with 64K pages
+        assert_eq!(huge_block_pages(Some(13), Some(512 * MIB), 65536), 8192);
+        assert_eq!(huge_block_pages(None, Some(512 * MIB), 65536), 8192);
+        // pagetypeinfo without its header
+        assert_eq!(huge_block_pages(Some(0), Some(2 * MIB), 4096), 512);
+        assert_eq!(huge_block_pages(None, None, 4096), 512);
+        assert_eq!(huge_block_pages(None, Some(0), 4096), 512);
+    }
+
+    #[test]
+    fn largest_aligned_block() {
+        let mut budget = [9, 9, 9, 9];
+        assert_eq!(blocks(0, "b.......", &mut budget), [(0, 8)]);
+        assert_eq!(budget, [9, 9, 9, 8]);
+
+        // pfn 6 is aligned on 2 pages only, the next pages are kernel pages
+        let mut budget = [9, 9, 9, 9];
+        assert_eq!(blocks(6, "b.......", &mut budget), [(6, 2)]);
+
+        // the end of the range
+        let mut budget = [9, 9, 9, 9];
+        assert_eq!(blocks(0, "b..", &mut budget), [(0, 2)]);
+
+        // order of the largest block in buddyinfo
+        let mut budget = [9, 9];
+        assert_eq!(blocks(0, "b...", &mut budget), [(0, 2)]);
+    }
+
+    #[test]
+    fn allocated_pages() {
+        let mut budget = [9, 9, 9, 9];
+        assert_eq!(
+            blocks(0, "b.a.b...b", &mut budget),
+            [(0, 2), (4, 4), (8, 1)]
+        );
+        assert_eq!(budget, [8, 8, 8, 9]);
+    }
+
+    #[test]
+    fn budget() {
+        // a single order 3 block is free: the others are kernel pages without flags
+        let mut budget = [0, 0, 0, 1];
+        assert_eq!(blocks(0, "b.......b.......", &mut budget), [(0, 8), (8, 1)]);
+        assert_eq!(budget, [0, 0, 0, 0]);
+
+        let mut budget = [4, 0, 1, 0];
+        assert_eq!(blocks(0, "b.......b.......", &mut budget), [(0, 4), (8, 1)]);
+        assert_eq!(budget, [3, 0, 0, 0]);
+    }
+}
@@ -7,14 +7,22 @@ use std::{
 use anyhow::{bail, Context};
 use indicatif::ProgressBar;
 use log::{debug, warn};
-use procfs::{process::Pfn, Shm};
+use procfs::{
+    process::{Pfn, Process},
+    PhysicalPageFlags, Shm,
+};
 use rayon::prelude::*;
 
 use crate::{
     filters::{self, Filter},
-    get_processes_group_info, FxHasher, ProcessGroupInfo, ProcessInfo,
+    get_processes_group_info, FxHasher, ProcessGroupInfo, ProcessInfo, SmonInfo,
+};
+use crate::{
+    ksm::KsmStats,
+    process_tree::ProcessTree,
+    swap::{SwapDevice, ZswapStats},
+    ShmsMetadata,
 };
-use crate::{process_tree::ProcessTree, ShmsMetadata};
 
 pub trait ProcessSplitter<'a> {
     fn name(&self) -> String;
@@ -85,27 +93,21 @@ pub trait ProcessSplitter<'a> {
                 }
             }
             for (shm, meta) in shm_metadata {
-                match meta {
-                    Some((_shm_pfns, _swap_pages, _pages_4k, _pages_2M)) => {
-                        if other_referenced_shm.contains(shm) {
-                            //other_pfns.par_extend(shm_pfns);
-                        }
+                if let Some((_shm_pfns, _swap_pages, _pages_4k, _pages_2M)) = meta {
+                    if other_referenced_shm.contains(shm) {
+                        //other_pfns.par_extend(shm_pfns);
                     }
-                    None => (),
                 }
             }
 
             let group_1_pfns = group_1.pfns.clone();
             for (shm, meta) in shm_metadata {
-                match meta {
-                    Some((_shm_pfns, _swap_pages, _pages_4k, _pages_2M)) => {
-                        if group_1.referenced_shm.contains(shm) {
-                            // TODO: we count shm as rss
-                            // do something else?
-                            //group_1_pfns.par_extend(shm_pfns);
-                        }
+                if let Some((_shm_pfns, _swap_pages, _pages_4k, _pages_2M)) = meta {
+                    if group_1.referenced_shm.contains(shm) {
+                        // TODO: we count shm as rss
+                        // do something else?
+                        //group_1_pfns.par_extend(shm_pfns);
                     }
-                    None => (),
                 }
             }
             let processes_count = group_1.processes_info.len();
@@ -147,7 +149,7 @@ pub trait ProcessSplitter<'a> {
         pb.finish_and_clear();
 
         // sort by mem RSS
-        display_info.sort_by(|a, b| b.mem_rss.cmp(&a.mem_rss));
+        display_info.sort_by_key(|info| std::cmp::Reverse(info.mem_rss));
 
         let mut table = tabled::Table::new(&display_info);
         table.with(tabled::settings::Style::sharp());
@@ -158,6 +160,163 @@ pub trait ProcessSplitter<'a> {
         debug!("Display split by {}: {:?}", self.name(), chrono.elapsed());
         println!();
     }
+
+    /// Swap usage of each group, for each swap device
+    fn display_swap(&'a self, swap_devices: &[SwapDevice], zswap: Option<ZswapStats>) {
+        use crate::tmpfs::format_units_MiB;
+        use tabled::Tabled;
+
+        #[derive(Tabled)]
+        struct ProcessGroupSwapDisplayRow {
+            group_name: String,
+            device: String,
+            #[tabled(display = "format_units_MiB")]
+            swap_anon: u64,
+            #[tabled(display = "format_units_MiB")]
+            swap_rss: u64,
+            /// Estimated part of swap_rss held compressed in RAM (zram or zswap)
+            #[tabled(display = "format_units_MiB")]
+            compressed: u64,
+            #[tabled(display = "format_units_MiB")]
+            on_disk: u64,
+        }
+
+        let page_size = procfs::page_size();
+        let zswap_ratio = crate::swap::zswap_ratio(swap_devices, zswap);
+
+        let all_swap_pages: HashSet<&(u64, u64)> = self
+            .iter_groups()
+            .flat_map(|group| group.swap_pages.iter())
+            .collect();
+        let verified = match crate::swap::verify_swap_types(swap_devices, all_swap_pages) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Swap devices may be attributed to the wrong swap type, a device was probably removed by swapoff: {e}");
+                false
+            }
+        };
+
+        let mut display_info: Vec<ProcessGroupSwapDisplayRow> = Vec::new();
+        for group in self.iter_groups() {
+            let mut per_type: BTreeMap<u64, (u64, u64)> = BTreeMap::new();
+            for (swap_type, _offset) in &group.swap_pages {
+                per_type.entry(*swap_type).or_default().1 += page_size;
+            }
+            for (swap_type, _offset) in &group.anon_swap_pages {
+                per_type.entry(*swap_type).or_default().0 += page_size;
+            }
+
+            for (swap_type, (swap_anon, swap_rss)) in per_type {
+                let device = swap_devices
+                    .iter()
+                    .find(|device| device.swap_type == swap_type);
+                let (device_name, compressed) = match device {
+                    Some(device) if device.is_compressed() => (device.filename.clone(), swap_rss),
+                    Some(device) => (
+                        device.filename.clone(),
+                        (swap_rss as f64 * zswap_ratio) as u64,
+                    ),
+                    None => (format!("unknown type {swap_type}"), 0),
+                };
+
+                display_info.push(ProcessGroupSwapDisplayRow {
+                    group_name: group.name.clone(),
+                    device: device_name,
+                    swap_anon,
+                    swap_rss,
+                    compressed,
+                    on_disk: swap_rss - compressed,
+                });
+            }
+        }
+
+        if display_info.is_empty() {
+            return;
+        }
+
+        display_info.sort_by(|a, b| {
+            b.swap_rss
+                .cmp(&a.swap_rss)
+                .then_with(|| a.group_name.cmp(&b.group_name))
+        });
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        if verified {
+            println!("{} (swap per device)", self.name());
+        } else {
+            println!(
+                "{} (swap per device, devices guessed from /proc/swaps order)",
+                self.name()
+            );
+        }
+        println!("{table}");
+        println!();
+    }
+
+    /// KSM pages referenced by each group
+    /// Savings are estimated from the global sharing ratio, as pagemap doesn't tell who else shares a KSM page
+    fn display_ksm(
+        &'a self,
+        all_physical_pages: &HashMap<Pfn, PhysicalPageFlags>,
+        ksm_stats: &KsmStats,
+    ) {
+        use crate::tmpfs::format_units_MiB;
+        use tabled::Tabled;
+
+        #[derive(Tabled)]
+        struct ProcessGroupKsmDisplayRow {
+            group_name: String,
+            ksm_pages: u64,
+            #[tabled(display = "format_units_MiB")]
+            ksm_mem: u64,
+            #[tabled(rename = "ksm %")]
+            ksm_percent: String,
+            #[tabled(display = "format_units_MiB")]
+            estimated_saved: u64,
+        }
+
+        let page_size = procfs::page_size();
+        let ratio = ksm_stats.sharing_ratio();
+
+        let mut display_info: Vec<ProcessGroupKsmDisplayRow> = self
+            .iter_groups()
+            .map(|group| {
+                let ksm_pages = group
+                    .anon_pfns
+                    .par_iter()
+                    .filter(|pfn| {
+                        all_physical_pages
+                            .get(pfn)
+                            .is_some_and(|flags| flags.contains(PhysicalPageFlags::KSM))
+                    })
+                    .count() as u64;
+                let ksm_percent = if group.anon_pfns.is_empty() {
+                    0.
+                } else {
+                    ksm_pages as f64 / group.anon_pfns.len() as f64 * 100.
+                };
+
+                ProcessGroupKsmDisplayRow {
+                    group_name: group.name.clone(),
+                    ksm_pages,
+                    ksm_mem: ksm_pages * page_size,
+                    ksm_percent: format!("{ksm_percent:.1}"),
+                    estimated_saved: (ksm_pages as f64 * ratio) as u64 * page_size,
+                }
+            })
+            .collect();
+
+        display_info.sort_by_key(|row| std::cmp::Reverse(row.ksm_pages));
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("{} (KSM)", self.name());
+        println!("{table}");
+        println!();
+    }
 }
 
 pub struct ProcessSplitterCustomFilter {
@@ -307,6 +466,12 @@ impl ProcessSplitterUid {
         }
     }
 }
+
+impl Default for ProcessSplitterUid {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl<'a> ProcessSplitter<'a> for ProcessSplitterUid {
     type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, u32, ProcessGroupInfo>;
 
@@ -343,3 +508,278 @@ impl<'a> ProcessSplitter<'a> for ProcessSplitterUid {
             .collect()
     }
 }
+
+pub struct ProcessSplitterCgroup {
+    groups: BTreeMap<String, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterCgroup {
+    pub fn new() -> Self {
+        Self {
+            groups: BTreeMap::new(),
+        }
+    }
+
+    /// memory.current, memory.stat and memory.pressure of each cgroup v2
+    pub fn display_cgroup_memory(&self) {
+        use crate::pressure::format_option_units_MiB;
+        use crate::tmpfs::format_units_MiB;
+        use tabled::Tabled;
+
+        #[derive(Tabled)]
+        struct CgroupMemoryDisplayRow {
+            cgroup: String,
+            #[tabled(display = "format_option_units_MiB")]
+            current: Option<u64>,
+            #[tabled(display = "format_option_units_MiB")]
+            max: Option<u64>,
+            #[tabled(display = "format_units_MiB")]
+            anon: u64,
+            #[tabled(display = "format_units_MiB")]
+            file: u64,
+            #[tabled(display = "format_units_MiB")]
+            shmem: u64,
+            pgscan: u64,
+            pgsteal: u64,
+            #[tabled(rename = "some avg10")]
+            some_avg10: String,
+            #[tabled(rename = "full avg10")]
+            full_avg10: String,
+        }
+
+        let Some(mount_point) = crate::pressure::cgroup2_mount_point() else {
+            warn!("Can't find cgroup2 mount point");
+            return;
+        };
+
+        let display_info: Vec<CgroupMemoryDisplayRow> = self
+            .groups
+            .keys()
+            .filter_map(|cgroup| {
+                let stats = crate::pressure::get_cgroup_memory_stats(&mount_point, cgroup)?;
+                let stat = |key: &str| stats.stat.get(key).copied().unwrap_or_default();
+                let (some_avg10, full_avg10) = match &stats.pressure {
+                    Some(pressure) => (
+                        format!("{:.2}", pressure.some.avg10),
+                        format!("{:.2}", pressure.full.avg10),
+                    ),
+                    None => ("-".to_string(), "-".to_string()),
+                };
+
+                Some(CgroupMemoryDisplayRow {
+                    cgroup: cgroup.clone(),
+                    current: stats.current,
+                    max: stats.max,
+                    anon: stat("anon"),
+                    file: stat("file"),
+                    shmem: stat("shmem"),
+                    pgscan: stat("pgscan"),
+                    pgsteal: stat("pgsteal"),
+                    some_avg10,
+                    full_avg10,
+                })
+            })
+            .collect();
+
+        if display_info.is_empty() {
+            warn!("No memory controller found in cgroup v2 hierarchy");
+            return;
+        }
+
+        let mut table = tabled::Table::new(&display_info);
+        table.with(tabled::settings::Style::sharp());
+
+        println!("{} (memory.stat, memory.pressure)", self.name());
+        println!("{table}");
+        println!();
+    }
+}
+
+impl Default for ProcessSplitterCgroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ProcessSplitter<'a> for ProcessSplitterCgroup {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, String, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "cgroup v2".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut by_cgroup: BTreeMap<String, Vec<ProcessInfo>> = BTreeMap::new();
+        for process_info in processes {
+            let cgroup = crate::pressure::get_cgroup2_path(&process_info.process)
+                .unwrap_or_else(|| "?".to_string());
+            by_cgroup.entry(cgroup).or_default().push(process_info);
+        }
+
+        for (cgroup, processes_info) in by_cgroup {
+            let group_info = get_processes_group_info(processes_info, &cgroup, shms_metadata);
+            self.groups.insert(cgroup, group_info);
+        }
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// Oracle processes by the container (PDB) of their current session
+///
+/// Processes of an instance without a session in a container are grouped by instance
+pub struct ProcessSplitterPdb {
+    /// Group name of each Oracle process
+    containers: HashMap<i32, String>,
+    groups: BTreeMap<String, ProcessGroupInfo>,
+}
+
+impl ProcessSplitterPdb {
+    pub fn new(instances: &[SmonInfo]) -> Self {
+        let mut containers = HashMap::new();
+        for instance in instances {
+            let sid = instance.sid.to_string_lossy();
+            for process in &instance.pga_processes {
+                let container = process.con_id.and_then(|con_id| {
+                    instance
+                        .containers
+                        .iter()
+                        .find(|container| container.con_id == con_id)
+                });
+                let name = match container {
+                    Some(container) => format!("{sid} {}", container.name),
+                    None => format!("{sid} (no container)"),
+                };
+                containers.insert(process.spid, name);
+            }
+        }
+
+        Self {
+            containers,
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+impl<'a> ProcessSplitter<'a> for ProcessSplitterPdb {
+    type GroupIter<'b: 'a> = std::collections::btree_map::Values<'a, String, ProcessGroupInfo>;
+
+    fn name(&self) -> String {
+        "Oracle container".to_string()
+    }
+    fn __split(
+        &mut self,
+        _tree: &ProcessTree,
+        shms_metadata: &ShmsMetadata,
+        processes: Vec<ProcessInfo>,
+    ) {
+        let mut by_container: BTreeMap<String, Vec<ProcessInfo>> = BTreeMap::new();
+        for process_info in processes {
+            let container = self
+                .containers
+                .get(&process_info.process.pid)
+                .cloned()
+                .unwrap_or_else(|| "Other".to_string());
+            by_container
+                .entry(container)
+                .or_default()
+                .push(process_info);
+        }
+
+        for (container, processes_info) in by_container {
+            let group_info = get_processes_group_info(processes_info, &container, shms_metadata);
+            self.groups.insert(container, group_info);
+        }
+    }
+    fn iter_groups<'x>(&'a self) -> Self::GroupIter<'a> {
+        self.groups.values()
+    }
+    fn collect_processes(self) -> Vec<ProcessInfo> {
+        self.groups
+            .into_values()
+            .flat_map(|group| group.processes_info)
+            .collect()
+    }
+}
+
+/// How processes are grouped, for tools that only need group names
+///
+/// Same groups as the memstats splitters: `uid`, `env:ORACLE_SID`, `custom:<filters>`
+pub enum GroupBy {
+    Uid,
+    EnvVariable(OsString),
+    CustomFilter(ProcessSplitterCustomFilter),
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        if value == "uid" {
+            Ok(GroupBy::Uid)
+        } else if let Some(var) = value.strip_prefix("env:") {
+            Ok(GroupBy::EnvVariable(var.into()))
+        } else if let Some(filters) = value.strip_prefix("custom:") {
+            Ok(GroupBy::CustomFilter(ProcessSplitterCustomFilter::new(
+                filters,
+            )?))
+        } else {
+            bail!("Unknown group {value:?}, expected uid, env:<VAR> or custom:<filters>")
+        }
+    }
+}
+
+impl GroupBy {
+    /// Group name of each process
+    pub fn groups(&self, processes: &[Process]) -> Vec<String> {
+        match self {
+            GroupBy::Uid => processes
+                .iter()
+                .map(|p| match p.uid() {
+                    Ok(uid) => match uzers::get_user_by_uid(uid) {
+                        Some(user) => user.name().to_string_lossy().to_string(),
+                        None => format!("{uid}"),
+                    },
+                    Err(_) => "?".to_string(),
+                })
+                .collect(),
+            GroupBy::EnvVariable(var) => processes
+                .iter()
+                .map(|p| {
+                    let value = p.environ().ok().and_then(|environ| {
+                        environ
+                            .get(var)
+                            .map(|value| value.to_string_lossy().to_string())
+                    });
+                    format!("{:?}", value)
+                })
+                .collect(),
+            GroupBy::CustomFilter(splitter) => {
+                let tree = ProcessTree::new(processes);
+                processes
+                    .iter()
+                    .map(|p| {
+                        splitter
+                            .names
+                            .iter()
+                            .zip(&splitter.filters)
+                            .find(|(_, filter)| filter.eval(p, &tree))
+                            .map(|(name, _)| name.clone())
+                            .unwrap_or_else(|| "Other".to_string())
+                    })
+                    .collect()
+            }
+        }
+    }
+}
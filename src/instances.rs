@@ -0,0 +1,954 @@
+//! Application instances (databases, JVMs) found in the process list, with their configured memory
+//!
+//! Each kind of application implements [`InstanceDiscovery`]: find the instances and their
+//! processes, read the memory they are configured to use, and pick what to compare it with
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use log::debug;
+use procfs::process::{MMPermissions, MMapPath, PageInfo, Process};
+
+use crate::{
+    get_processes_group_info, process_tree::ProcessTree, tmpfs::format_units_MiB, ProcessGroupInfo,
+    ProcessInfo, ShmsMetadata, SmonInfo,
+};
+
+/// An instance of an application, and the memory it is configured to use
+#[derive(Debug)]
+pub struct AppInstance {
+    pub kind: &'static str,
+    /// SID, data directory, main class...
+    pub name: String,
+    /// Main process: pmon, postmaster, java
+    pub pid: i32,
+    /// All processes of the instance, including `pid`
+    pub pids: Vec<i32>,
+    /// Configured sizes in bytes, e.g. ("shared_buffers", 134217728)
+    pub configured: Vec<(&'static str, u64)>,
+}
+
+pub trait InstanceDiscovery {
+    /// Kind of the discovered instances, e.g. PostgreSQL
+    fn kind(&self) -> &'static str;
+
+    /// Instances among `processes`, kernel processes excluded
+    fn discover(&self, processes: &[Process], tree: &ProcessTree) -> Vec<AppInstance>;
+
+    /// Measured sizes in bytes to compare with the configured sizes
+    fn measure(
+        &self,
+        instance: &AppInstance,
+        group: &ProcessGroupInfo,
+        shms_metadata: &ShmsMetadata,
+    ) -> Vec<(&'static str, u64)>;
+}
+
+fn cmdline(process: &Process) -> Vec<String> {
+    process.cmdline().unwrap_or_default()
+}
+
+/// Path as seen by a process, through its root for processes in containers
+fn process_path(pid: i32, path: &Path) -> std::path::PathBuf {
+    if path.is_absolute() {
+        Path::new(&format!("/proc/{pid}/root")).join(path.strip_prefix("/").unwrap())
+    } else {
+        Path::new(&format!("/proc/{pid}/cwd")).join(path)
+    }
+}
+
+/// Resident anon memory and anon swap of a group
+fn anon_size(group: &ProcessGroupInfo) -> u64 {
+    let page_size = procfs::page_size();
+    let resident = group.anon_pfns.iter().filter(|pfn| pfn.0 != 0).count() as u64;
+    (resident + group.anon_swap_pages.len() as u64) * page_size
+}
+
+/// Oracle instances, from pmon processes. Configured memory comes from the DB info of `instances`
+pub struct OracleDiscovery<'a> {
+    pub instances: &'a [SmonInfo],
+}
+
+/// SID of an Oracle process: `ora_pmon_SID`, `asm_pmon_+ASM`, `oracleSID (LOCAL=NO)`
+fn oracle_sid(cmdline: &[String]) -> Option<&str> {
+    let first = cmdline.first()?.split_whitespace().next()?;
+    if let Some(background) = ["ora_", "asm_", "apx_"]
+        .iter()
+        .find_map(|prefix| first.strip_prefix(prefix))
+    {
+        background.split_once('_').map(|(_, sid)| sid)
+    } else {
+        first.strip_prefix("oracle").filter(|sid| !sid.is_empty())
+    }
+}
+
+impl InstanceDiscovery for OracleDiscovery<'_> {
+    fn kind(&self) -> &'static str {
+        "Oracle"
+    }
+
+    fn discover(&self, processes: &[Process], _tree: &ProcessTree) -> Vec<AppInstance> {
+        processes
+            .iter()
+            .filter(|process| crate::is_pmon(&cmdline(process)))
+            .filter_map(|pmon| {
+                let info = self.instances.iter().find(|info| info.pid == pmon.pid);
+                let pmon_cmdline = cmdline(pmon);
+                let sid = match info {
+                    Some(info) => info.sid.to_string_lossy().to_string(),
+                    None => oracle_sid(&pmon_cmdline)?.to_string(),
+                };
+
+                // v$process is exact, otherwise processes are found by name
+                let mut pids: Vec<i32> = match info {
+                    Some(info) if !info.pga_processes.is_empty() => {
+                        info.pga_processes.iter().map(|p| p.spid).collect()
+                    }
+                    _ => processes
+                        .iter()
+                        .filter(|process| oracle_sid(&cmdline(process)) == Some(&sid))
+                        .map(|process| process.pid)
+                        .collect(),
+                };
+                if !pids.contains(&pmon.pid) {
+                    pids.push(pmon.pid);
+                }
+
+                let configured = match info {
+                    Some(info) => vec![("sga", info.sga_size), ("pga allocated", info.pga_size)],
+                    None => Vec::new(),
+                };
+
+                let kind = match crate::InstanceKind::from_sid(std::ffi::OsStr::new(&sid)) {
+                    crate::InstanceKind::Database => self.kind(),
+                    crate::InstanceKind::Asm => "Oracle ASM",
+                    crate::InstanceKind::AsmProxy => "Oracle APX",
+                };
+
+                Some(AppInstance {
+                    kind,
+                    name: sid,
+                    pid: pmon.pid,
+                    pids,
+                    configured,
+                })
+            })
+            .collect()
+    }
+
+    fn measure(
+        &self,
+        _instance: &AppInstance,
+        group: &ProcessGroupInfo,
+        _shms_metadata: &ShmsMetadata,
+    ) -> Vec<(&'static str, u64)> {
+        let shm = group
+            .referenced_shm
+            .iter()
+            .map(|shm| shm.rss + shm.swap)
+            .sum();
+        vec![("shm", shm), ("anon", anon_size(group))]
+    }
+}
+
+/// PostgreSQL clusters, from postmaster processes. `shared_buffers` is read from the config files
+pub struct PostgresDiscovery;
+
+/// PostgreSQL memory setting: blocks of 8 kB without unit, or kB, MB, GB, TB
+fn parse_pg_memory(value: &str) -> Option<u64> {
+    let value = value.trim().trim_matches('\'').trim();
+    let (digits, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => value.split_at(idx),
+        None => (value, ""),
+    };
+    let multiplier = match unit.trim() {
+        "" => 8 * 1024,
+        "B" => 1,
+        "kB" => 1024,
+        "MB" => 1024 * 1024,
+        "GB" => 1024 * 1024 * 1024,
+        "TB" => 1024 * 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some(digits.parse::<u64>().ok()? * multiplier)
+}
+
+/// `line` without its `#` comment, a `#` inside a quoted value is kept
+fn strip_pg_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '\'' => quoted = !quoted,
+            '#' if !quoted => return &line[..idx],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// `name = value` settings of a postgresql.conf content, names lowercased
+fn parse_pg_conf(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = strip_pg_comment(line).trim();
+            let (name, value) = line
+                .split_once('=')
+                .or_else(|| line.split_once(char::is_whitespace))?;
+            Some((
+                name.trim().to_lowercase(),
+                value.trim().trim_matches('\'').to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Settings of a postgresql.conf file. Includes are not followed
+fn read_pg_conf(path: &Path) -> HashMap<String, String> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        debug!("Can't read {path:?}");
+        return HashMap::new();
+    };
+    parse_pg_conf(&content)
+}
+
+/// `-c name=value`, `--name=value` and `-D datadir` arguments of postgres
+fn pg_cmdline_settings(cmdline: &[String]) -> HashMap<String, String> {
+    let mut settings = HashMap::new();
+    let mut args = cmdline.iter().skip(1);
+    while let Some(arg) = args.next() {
+        let setting = match arg.as_str() {
+            "-c" => args.next().cloned(),
+            "-D" => args.next().map(|dir| format!("data_directory={dir}")),
+            "-B" => args.next().map(|blocks| format!("shared_buffers={blocks}")),
+            _ => {
+                if let Some(setting) = arg.strip_prefix("--") {
+                    // dashes of the name only, values can contain dashes
+                    setting
+                        .split_once('=')
+                        .map(|(name, value)| format!("{}={value}", name.replace('-', "_")))
+                } else if let Some(setting) = arg.strip_prefix("-c") {
+                    Some(setting.to_string())
+                } else {
+                    arg.strip_prefix("-D")
+                        .map(|dir| format!("data_directory={dir}"))
+                }
+            }
+        };
+        if let Some((name, value)) = setting.as_deref().and_then(|s| s.split_once('=')) {
+            settings.insert(name.to_lowercase(), value.to_string());
+        }
+    }
+    settings
+}
+
+impl InstanceDiscovery for PostgresDiscovery {
+    fn kind(&self) -> &'static str {
+        "PostgreSQL"
+    }
+
+    fn discover(&self, processes: &[Process], tree: &ProcessTree) -> Vec<AppInstance> {
+        // backends and auxiliary processes rewrite their cmdline to `postgres: ...`
+        processes
+            .iter()
+            .filter(|process| {
+                cmdline(process)
+                    .first()
+                    .and_then(|exe| Path::new(exe).file_name())
+                    .is_some_and(|name| name == "postgres" || name == "postmaster")
+            })
+            .map(|postmaster| {
+                let pid = postmaster.pid;
+                let args = pg_cmdline_settings(&cmdline(postmaster));
+                let data_directory = args.get("data_directory").cloned().or_else(|| {
+                    let environ = postmaster.environ().ok()?;
+                    Some(
+                        environ
+                            .get(std::ffi::OsStr::new("PGDATA"))?
+                            .to_string_lossy()
+                            .to_string(),
+                    )
+                });
+
+                // cmdline > postgresql.auto.conf > postgresql.conf
+                let config_file = args
+                    .get("config_file")
+                    .cloned()
+                    .or_else(|| Some(format!("{}/postgresql.conf", data_directory.as_ref()?)));
+                let mut settings = config_file
+                    .map(|file| read_pg_conf(&process_path(pid, Path::new(&file))))
+                    .unwrap_or_default();
+                let data_directory =
+                    data_directory.or_else(|| settings.get("data_directory").cloned());
+                if let Some(data_directory) = &data_directory {
+                    let auto_conf = format!("{data_directory}/postgresql.auto.conf");
+                    settings.extend(read_pg_conf(&process_path(pid, Path::new(&auto_conf))));
+                }
+                settings.extend(args);
+
+                // 128MB is the default
+                let shared_buffers = settings
+                    .get("shared_buffers")
+                    .map_or(Some(128 * 1024 * 1024), |value| parse_pg_memory(value));
+
+                let mut pids = vec![pid];
+                pids.extend(tree.descendants(pid));
+
+                AppInstance {
+                    kind: self.kind(),
+                    name: data_directory.unwrap_or_else(|| format!("pid {pid}")),
+                    pid,
+                    pids,
+                    configured: shared_buffers
+                        .map(|size| vec![("shared_buffers", size)])
+                        .unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    fn measure(
+        &self,
+        instance: &AppInstance,
+        group: &ProcessGroupInfo,
+        _shms_metadata: &ShmsMetadata,
+    ) -> Vec<(&'static str, u64)> {
+        let page_size = procfs::page_size();
+
+        // shared_memory_type = sysv
+        let mut shared: u64 = group
+            .referenced_shm
+            .iter()
+            .map(|shm| shm.rss + shm.swap)
+            .sum();
+
+        // shared_memory_type = mmap: shared anonymous mapping of the postmaster
+        let maps = match Process::new(instance.pid) {
+            Ok(process) => crate::get_memory_maps_for_process(&process, true),
+            Err(e) => Err(e.into()),
+        };
+        match maps {
+            Ok(maps) => {
+                for (memory_map, pages) in maps {
+                    let anonymous = match &memory_map.pathname {
+                        MMapPath::Anonymous => true,
+                        MMapPath::Path(path) => {
+                            let path = path.to_string_lossy();
+                            path.starts_with("/dev/zero") || path.starts_with("/anon_hugepage")
+                        }
+                        _ => false,
+                    };
+                    if !anonymous || !memory_map.perms.contains(MMPermissions::SHARED) {
+                        continue;
+                    }
+                    shared += pages
+                        .iter()
+                        .filter(|page| match page {
+                            PageInfo::MemoryPage(memory_page) => {
+                                memory_page.get_page_frame_number().0 != 0
+                            }
+                            PageInfo::SwapPage(_) => true,
+                        })
+                        .count() as u64
+                        * page_size;
+                }
+            }
+            Err(e) => debug!("Can't read maps of postmaster {}: {e:?}", instance.pid),
+        }
+
+        vec![("shared memory", shared)]
+    }
+}
+
+/// JVMs, from java processes. The heap size comes from `-Xmx` and hsperfdata
+pub struct JvmDiscovery;
+
+/// `-Xmx` size: bytes, or k, m, g, t suffix
+fn parse_java_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()? {
+        'k' | 'K' => (&value[..value.len() - 1], 1024),
+        'm' | 'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'g' | 'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        't' | 'T' => (&value[..value.len() - 1], 1024 * 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    Some(digits.parse::<u64>().ok()? * multiplier)
+}
+
+/// Last `-Xmx` or `-XX:MaxHeapSize`, the cmdline wins over JAVA_TOOL_OPTIONS
+fn java_max_heap<'a>(args: impl Iterator<Item = &'a str>) -> Option<u64> {
+    args.filter_map(|arg| {
+        arg.strip_prefix("-Xmx")
+            .or_else(|| arg.strip_prefix("-XX:MaxHeapSize="))
+            .and_then(parse_java_size)
+    })
+    .last()
+}
+
+/// Main class, `-jar` or module of a java cmdline
+fn java_main(cmdline: &[String]) -> Option<String> {
+    const WITH_VALUE: [&str; 8] = [
+        "-cp",
+        "-classpath",
+        "--class-path",
+        "-p",
+        "--module-path",
+        "--add-modules",
+        "--add-opens",
+        "--add-exports",
+    ];
+    let mut args = cmdline.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-jar" | "-m" | "--module" => return args.next().cloned(),
+            arg if WITH_VALUE.contains(&arg) => {
+                args.next();
+            }
+            arg if arg.starts_with('-') => (),
+            main => return Some(main.to_string()),
+        }
+    }
+    None
+}
+
+/// Long counters of a hsperfdata file (format 2), e.g. `sun.gc.generation.0.capacity`
+fn parse_hsperfdata(data: &[u8]) -> Option<HashMap<String, i64>> {
+    if data.get(0..4)? != [0xca, 0xfe, 0xc0, 0xc0] || *data.get(5)? != 2 {
+        return None;
+    }
+    let little_endian = *data.get(4)? == 1;
+    let int = |offset: usize| -> Option<i64> {
+        let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            i32::from_le_bytes(bytes)
+        } else {
+            i32::from_be_bytes(bytes)
+        } as i64)
+    };
+    let long = |offset: usize| -> Option<i64> {
+        let bytes: [u8; 8] = data.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if little_endian {
+            i64::from_le_bytes(bytes)
+        } else {
+            i64::from_be_bytes(bytes)
+        })
+    };
+
+    let mut counters = HashMap::new();
+    let mut entry = int(24)? as usize;
+    for _ in 0..int(28)? {
+        let length = int(entry)? as usize;
+        let name_offset = int(entry + 4)? as usize;
+        let vector_length = int(entry + 8)?;
+        let data_type = *data.get(entry + 12)?;
+        let data_offset = int(entry + 16)? as usize;
+
+        if data_type == b'J' && vector_length == 0 {
+            let name = data.get(entry + name_offset..)?;
+            let name = &name[..name.iter().position(|&b| b == 0)?];
+            counters.insert(
+                String::from_utf8_lossy(name).to_string(),
+                long(entry + data_offset)?,
+            );
+        }
+
+        if length == 0 {
+            break;
+        }
+        entry += length;
+    }
+    Some(counters)
+}
+
+/// hsperfdata of a JVM, in /tmp/hsperfdata_<user>/<pid> as seen by the process
+fn read_hsperfdata(process: &Process) -> Option<HashMap<String, i64>> {
+    let status = process.status().ok()?;
+    // pid in the namespace of the process
+    let pid = status
+        .nspid
+        .as_ref()
+        .and_then(|nspid| nspid.last().copied())
+        .unwrap_or(process.pid);
+    let tmp = process_path(process.pid, Path::new("/tmp"));
+    std::fs::read_dir(tmp)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("hsperfdata_")
+        })
+        .find_map(|entry| std::fs::read(entry.path().join(pid.to_string())).ok())
+        .and_then(|data| parse_hsperfdata(&data))
+}
+
+/// Sum of `sun.gc.generation.<n>.<counter>`
+fn heap_counter(counters: &HashMap<String, i64>, counter: &str) -> Option<u64> {
+    let values: Vec<i64> = counters
+        .iter()
+        .filter(|(name, _)| {
+            name.strip_prefix("sun.gc.generation.")
+                .and_then(|name| name.split_once('.'))
+                .is_some_and(|(generation, name)| {
+                    generation.parse::<u32>().is_ok() && name == counter
+                })
+        })
+        .map(|(_, value)| *value)
+        .collect();
+    (!values.is_empty()).then(|| values.iter().sum::<i64>() as u64)
+}
+
+/// Sum of `sun.gc.generation.<n>.space.<m>.used`
+fn heap_used(counters: &HashMap<String, i64>) -> Option<u64> {
+    let values: Vec<i64> = counters
+        .iter()
+        .filter(|(name, _)| name.starts_with("sun.gc.generation.") && name.ends_with(".used"))
+        .map(|(_, value)| *value)
+        .collect();
+    (!values.is_empty()).then(|| values.iter().sum::<i64>() as u64)
+}
+
+impl InstanceDiscovery for JvmDiscovery {
+    fn kind(&self) -> &'static str {
+        "JVM"
+    }
+
+    fn discover(&self, processes: &[Process], _tree: &ProcessTree) -> Vec<AppInstance> {
+        processes
+            .iter()
+            .filter(|process| {
+                cmdline(process)
+                    .first()
+                    .and_then(|exe| Path::new(exe).file_name())
+                    .is_some_and(|name| name == "java")
+            })
+            .map(|process| {
+                let cmdline = cmdline(process);
+                let tool_options = process
+                    .environ()
+                    .ok()
+                    .and_then(|environ| {
+                        environ
+                            .get(std::ffi::OsStr::new("JAVA_TOOL_OPTIONS"))
+                            .map(|options| options.to_string_lossy().to_string())
+                    })
+                    .unwrap_or_default();
+
+                let mut configured = Vec::new();
+                if let Some(xmx) = java_max_heap(
+                    tool_options
+                        .split_whitespace()
+                        .chain(cmdline.iter().map(|arg| arg.as_str())),
+                ) {
+                    configured.push(("-Xmx", xmx));
+                }
+                // ergonomics when there is no -Xmx
+                if let Some(max_heap) = read_hsperfdata(process)
+                    .and_then(|counters| heap_counter(&counters, "maxCapacity"))
+                {
+                    configured.push(("max heap", max_heap));
+                }
+
+                AppInstance {
+                    kind: self.kind(),
+                    name: java_main(&cmdline).unwrap_or_else(|| format!("pid {}", process.pid)),
+                    pid: process.pid,
+                    pids: vec![process.pid],
+                    configured,
+                }
+            })
+            .collect()
+    }
+
+    fn measure(
+        &self,
+        instance: &AppInstance,
+        group: &ProcessGroupInfo,
+        _shms_metadata: &ShmsMetadata,
+    ) -> Vec<(&'static str, u64)> {
+        let mut measured = Vec::new();
+        if let Some(counters) = Process::new(instance.pid)
+            .ok()
+            .and_then(|process| read_hsperfdata(&process))
+        {
+            if let Some(committed) = heap_counter(&counters, "capacity") {
+                measured.push(("heap committed", committed));
+            }
+            if let Some(used) = heap_used(&counters) {
+                measured.push(("heap used", used));
+            }
+        }
+        measured.push(("anon", anon_size(group)));
+        measured
+    }
+}
+
+/// Instances of all kinds, with the discovery measuring them
+pub fn discover<'a>(
+    discoveries: &[&'a dyn InstanceDiscovery],
+    processes: &[Process],
+    tree: &ProcessTree,
+) -> Vec<(&'a dyn InstanceDiscovery, AppInstance)> {
+    discoveries
+        .iter()
+        .flat_map(|discovery| {
+            discovery
+                .discover(processes, tree)
+                .into_iter()
+                .map(|instance| (*discovery, instance))
+        })
+        .collect()
+}
+
+/// Configured memory next to memory measured from the scanned `processes_info`, which are given
+/// back. Processes not scanned (filtered, vanished, memory limit) are not measured, a process
+/// listed by several instances is measured with the first one
+pub fn display(
+    instances: &[(&dyn InstanceDiscovery, AppInstance)],
+    processes_info: Vec<ProcessInfo>,
+    shms_metadata: &ShmsMetadata,
+) -> Vec<ProcessInfo> {
+    use tabled::Tabled;
+
+    #[derive(Tabled)]
+    struct InstanceDisplayRow {
+        #[tabled(rename = "type")]
+        kind: &'static str,
+        name: String,
+        pid: i32,
+        processes: usize,
+        configured: String,
+        measured: String,
+        #[tabled(display = "format_units_MiB")]
+        resident: u64,
+        #[tabled(display = "format_units_MiB")]
+        swap: u64,
+    }
+
+    let format_sizes = |sizes: &[(&str, u64)]| {
+        sizes
+            .iter()
+            .map(|(name, size)| format!("{name} {}", format_units_MiB(size)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    let page_size = procfs::page_size();
+    let mut rows = Vec::new();
+    let mut remaining = processes_info;
+    let mut groups = Vec::new();
+    for (discovery, instance) in instances {
+        let pids: HashSet<i32> = instance.pids.iter().copied().collect();
+        let (processes_info, others): (Vec<ProcessInfo>, Vec<ProcessInfo>) = remaining
+            .into_iter()
+            .partition(|process_info| pids.contains(&process_info.process.pid));
+        remaining = others;
+        debug!(
+            "{} {}: {} of {} processes scanned",
+            instance.kind,
+            instance.name,
+            processes_info.len(),
+            pids.len()
+        );
+        if processes_info.is_empty() {
+            continue;
+        }
+        let group = get_processes_group_info(processes_info, &instance.name, shms_metadata);
+        let measured = discovery.measure(instance, &group, shms_metadata);
+
+        let shm_rss: u64 = group.referenced_shm.iter().map(|shm| shm.rss).sum();
+        let shm_swap: u64 = group.referenced_shm.iter().map(|shm| shm.swap).sum();
+        rows.push(InstanceDisplayRow {
+            kind: instance.kind,
+            name: instance.name.clone(),
+            pid: instance.pid,
+            processes: group.processes_info.len(),
+            configured: format_sizes(&instance.configured),
+            measured: format_sizes(&measured),
+            resident: group.pfns.iter().filter(|pfn| pfn.0 != 0).count() as u64 * page_size
+                + shm_rss,
+            swap: group.swap_pages.len() as u64 * page_size + shm_swap,
+        });
+        groups.push(group);
+    }
+    remaining.extend(groups.into_iter().flat_map(|group| group.processes_info));
+
+    if rows.is_empty() {
+        return remaining;
+    }
+
+    println!("Application instances (MiB):");
+    let mut table = tabled::Table::new(&rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!();
+    remaining
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    /// hsperfdata file with `(name, type, vector length, value)` entries
+    fn hsperfdata(little_endian: bool, entries: &[(&str, u8, i32, i64)]) -> Vec<u8> {
+        let int = |value: i32| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+        let long = |value: i64| {
+            if little_endian {
+                value.to_le_bytes()
+            } else {
+                value.to_be_bytes()
+            }
+        };
+
+        let mut data = vec![0xca, 0xfe, 0xc0, 0xc0, little_endian as u8, 2, 0, 1];
+        data.resize(24, 0);
+        data.extend(int(32));
+        data.extend(int(entries.len() as i32));
+        for (name, data_type, vector_length, value) in entries {
+            // header, name, data aligned on 8 bytes
+            let data_offset = (20 + name.len() + 1).next_multiple_of(8);
+            let length = data_offset + 8 * (*vector_length).max(1) as usize;
+            let entry = data.len();
+            data.extend(int(length as i32));
+            data.extend(int(20));
+            data.extend(int(*vector_length));
+            data.extend([*data_type, 0, 0, 0]);
+            data.extend(int(data_offset as i32));
+            data.extend(name.as_bytes());
+            data.resize(entry + data_offset, 0);
+            data.extend(long(*value));
+            data.resize(entry + length, 0);
+        }
+        data
+    }
+
+    #[test]
+    fn sid() {
+        assert_eq!(oracle_sid(&cmdline(&["ora_pmon_ORCL1"])), Some("ORCL1"));
+        assert_eq!(
+            oracle_sid(&cmdline(&["ora_dbw0_DB_UNIQUE"])),
+            Some("DB_UNIQUE")
+        );
+        assert_eq!(oracle_sid(&cmdline(&["asm_pmon_+ASM1"])), Some("+ASM1"));
+        assert_eq!(oracle_sid(&cmdline(&["apx_pmon_+APX1"])), Some("+APX1"));
+        assert_eq!(
+            oracle_sid(&cmdline(&[
+                "oracleORCL1 (DESCRIPTION=(LOCAL=YES)(ADDRESS=(PROTOCOL=beq)))"
+            ])),
+            Some("ORCL1")
+        );
+        assert_eq!(
+            oracle_sid(&cmdline(&["oracleORCL1", "(LOCAL=NO)"])),
+            Some("ORCL1")
+        );
+
+        assert_eq!(oracle_sid(&cmdline(&["oracle"])), None);
+        assert_eq!(oracle_sid(&cmdline(&["ora_pmon"])), None);
+        assert_eq!(oracle_sid(&cmdline(&["/usr/bin/bash"])), None);
+        assert_eq!(oracle_sid(&[]), None);
+    }
+
+    #[test]
+    fn pg_memory() {
+        assert_eq!(parse_pg_memory("16384"), Some(16384 * 8 * 1024));
+        assert_eq!(parse_pg_memory("128MB"), Some(128 * 1024 * 1024));
+        assert_eq!(parse_pg_memory("'4 GB'"), Some(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse_pg_memory(" 512kB "), Some(512 * 1024));
+        assert_eq!(parse_pg_memory("1TB"), Some(1024 * 1024 * 1024 * 1024));
+        assert_eq!(parse_pg_memory("8192B"), Some(8192));
+
+        assert_eq!(parse_pg_memory("128mb"), None);
+        assert_eq!(parse_pg_memory("1.5GB"), None);
+        assert_eq!(parse_pg_memory("GB"), None);
+    }
+
+    #[test]
+    fn pg_conf() {
+        let settings = parse_pg_conf(
+            "# shared memory
+shared_buffers = 4GB                    # min 128kB
+#shared_buffers = 1GB
+Huge_Pages = try
+data_directory = '/var/lib/pgsql/16/data'   # use data in another directory
+log_line_prefix = '%m [%p] #%l '        # special values
+search_path = 'it''s # here'
+effective_cache_size 8GB
+
+",
+        );
+        assert_eq!(settings.len(), 6);
+        assert_eq!(settings["shared_buffers"], "4GB");
+        assert_eq!(settings["huge_pages"], "try");
+        assert_eq!(settings["data_directory"], "/var/lib/pgsql/16/data");
+        assert_eq!(settings["log_line_prefix"], "%m [%p] #%l ");
+        assert_eq!(settings["search_path"], "it''s # here");
+        assert_eq!(settings["effective_cache_size"], "8GB");
+    }
+
+    #[test]
+    fn pg_comment() {
+        assert_eq!(strip_pg_comment("a = 1 # comment"), "a = 1 ");
+        assert_eq!(strip_pg_comment("a = '#1' # comment"), "a = '#1' ");
+        assert_eq!(
+            strip_pg_comment(r"a = 'it\'s #1' # comment"),
+            r"a = 'it\'s #1' "
+        );
+        assert_eq!(strip_pg_comment("# comment 'quoted'"), "");
+        assert_eq!(strip_pg_comment("a = 1"), "a = 1");
+    }
+
+    #[test]
+    fn pg_conf_missing() {
+        assert!(read_pg_conf(Path::new("/nonexistent/postgresql.conf")).is_empty());
+    }
+
+    #[test]
+    fn pg_cmdline() {
+        let settings = pg_cmdline_settings(&cmdline(&[
+            "/usr/lib/postgresql/16/bin/postgres",
+            "-D",
+            "/var/lib/postgresql/16/main",
+            "-c",
+            "config_file=/etc/postgresql/16/main/postgresql.conf",
+            "--Shared-Buffers=1GB",
+            "--log-line-prefix=%m-%p",
+            "-cwork_mem=64MB",
+            "-B",
+            "4096",
+            "-p",
+            "5433",
+        ]));
+        assert_eq!(settings["data_directory"], "/var/lib/postgresql/16/main");
+        assert_eq!(
+            settings["config_file"],
+            "/etc/postgresql/16/main/postgresql.conf"
+        );
+        assert_eq!(settings["work_mem"], "64MB");
+        // -B comes after --shared-buffers
+        assert_eq!(settings["shared_buffers"], "4096");
+        assert_eq!(settings["log_line_prefix"], "%m-%p");
+        assert_eq!(settings.len(), 5);
+
+        let settings = pg_cmdline_settings(&cmdline(&["postgres", "-D/srv/pg"]));
+        assert_eq!(settings["data_directory"], "/srv/pg");
+        assert!(pg_cmdline_settings(&cmdline(&["postgres", "-D"])).is_empty());
+    }
+
+    #[test]
+    fn java_size() {
+        assert_eq!(parse_java_size("1073741824"), Some(1024 * 1024 * 1024));
+        assert_eq!(parse_java_size("512k"), Some(512 * 1024));
+        assert_eq!(parse_java_size("512K"), Some(512 * 1024));
+        assert_eq!(parse_java_size("256m"), Some(256 * 1024 * 1024));
+        assert_eq!(parse_java_size("4G"), Some(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse_java_size("1t"), Some(1024 * 1024 * 1024 * 1024));
+
+        assert_eq!(parse_java_size(""), None);
+        assert_eq!(parse_java_size("g"), None);
+        assert_eq!(parse_java_size("1.5g"), None);
+        assert_eq!(parse_java_size("4gb"), None);
+    }
+
+    #[test]
+    fn max_heap() {
+        let args = ["-Xms1g", "-Xmx2g", "-XX:MaxHeapSize=3g", "-Xmx4g"];
+        assert_eq!(
+            java_max_heap(args.into_iter()),
+            Some(4 * 1024 * 1024 * 1024)
+        );
+        let args = ["-Xmx2g", "-XX:MaxHeapSize=3221225472"];
+        assert_eq!(
+            java_max_heap(args.into_iter()),
+            Some(3 * 1024 * 1024 * 1024)
+        );
+        assert_eq!(java_max_heap(["-Xms1g"].into_iter()), None);
+    }
+
+    #[test]
+    fn main_class() {
+        assert_eq!(
+            java_main(&cmdline(&[
+                "java",
+                "-Xmx1g",
+                "-cp",
+                "lib/*",
+                "com.example.Main",
+                "-x"
+            ])),
+            Some("com.example.Main".to_string())
+        );
+        assert_eq!(
+            java_main(&cmdline(&[
+                "java",
+                "-Dfoo=bar",
+                "-jar",
+                "app.jar",
+                "--port",
+                "80"
+            ])),
+            Some("app.jar".to_string())
+        );
+        assert_eq!(
+            java_main(&cmdline(&[
+                "java",
+                "--module-path",
+                "mods",
+                "-m",
+                "com.example/com.example.Main"
+            ])),
+            Some("com.example/com.example.Main".to_string())
+        );
+        assert_eq!(java_main(&cmdline(&["java", "-version"])), None);
+        assert_eq!(java_main(&cmdline(&["java", "-jar"])), None);
+    }
+
+    #[test]
+    fn hsperfdata_counters() {
+        let entries = [
+            ("sun.gc.generation.0.capacity", b'J', 0, 64 << 20),
+            ("sun.gc.generation.1.capacity", b'J', 0, 192 << 20),
+            ("sun.gc.generation.0.space.0.used", b'J', 0, 10 << 20),
+            ("sun.gc.generation.1.space.0.used", b'J', 0, 20 << 20),
+            ("java.property.java.version", b'B', 8, 0),
+            ("sun.gc.cause", b'B', 16, 0),
+        ];
+        for little_endian in [true, false] {
+            let counters = parse_hsperfdata(&hsperfdata(little_endian, &entries)).unwrap();
+            assert_eq!(counters.len(), 4);
+            assert_eq!(counters["sun.gc.generation.1.capacity"], 192 << 20);
+            assert_eq!(heap_counter(&counters, "capacity"), Some(256 << 20));
+            assert_eq!(heap_used(&counters), Some(30 << 20));
+            assert_eq!(heap_counter(&counters, "maxCapacity"), None);
+        }
+    }
+
+    #[test]
+    fn invalid_hsperfdata() {
+        let mut data = hsperfdata(true, &[("sun.gc.generation.0.capacity", b'J', 0, 1)]);
+        assert!(parse_hsperfdata(&data[..data.len() - 1]).is_none());
+        assert!(parse_hsperfdata(&data[..16]).is_none());
+        assert!(parse_hsperfdata(&[]).is_none());
+        // format 1
+        data[5] = 1;
+        assert!(parse_hsperfdata(&data).is_none());
+        data[5] = 2;
+        data[0] = 0;
+        assert!(parse_hsperfdata(&data).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tabled::Tabled;
+
+use crate::tmpfs::format_units_MiB;
+
+/// Global KSM counters from /sys/kernel/mm/ksm
+///
+/// See https://www.kernel.org/doc/html/latest/admin-guide/mm/ksm.html
+#[derive(Debug, Clone, Copy)]
+pub struct KsmStats {
+    /// 0: stopped, 1: running, 2: unmerge all pages
+    pub run: u64,
+    /// Number of KSM pages in use
+    pub pages_shared: u64,
+    /// How many more sites are sharing them, i.e. how much memory is saved
+    pub pages_sharing: u64,
+    /// Pages unique but repeatedly checked for merging
+    pub pages_unshared: u64,
+    /// Pages changing too fast to be placed in a tree
+    pub pages_volatile: u64,
+    pub full_scans: u64,
+    /// Empty pages merged with the kernel zero page, when `use_zero_pages` is enabled (6.10+)
+    pub ksm_zero_pages: Option<u64>,
+    /// Saved memory minus KSM metadata overhead, can be negative (6.7+)
+    pub general_profit: Option<i64>,
+}
+
+impl KsmStats {
+    /// Average number of additional mappings for each KSM page
+    pub fn sharing_ratio(&self) -> f64 {
+        if self.pages_shared == 0 {
+            0.
+        } else {
+            self.pages_sharing as f64 / self.pages_shared as f64
+        }
+    }
+}
+
+fn read_value<T: std::str::FromStr>(dir: &Path, name: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let path = dir.join(name);
+    let content = std::fs::read_to_string(&path).with_context(|| format!("Can't read {path:?}"))?;
+    content
+        .trim()
+        .parse()
+        .with_context(|| format!("Can't parse {path:?}"))
+}
+
+/// Return an error if KSM is not available (CONFIG_KSM not set)
+pub fn get_ksm_stats() -> Result<KsmStats> {
+    let dir = Path::new("/sys/kernel/mm/ksm");
+
+    Ok(KsmStats {
+        run: read_value(dir, "run")?,
+        pages_shared: read_value(dir, "pages_shared")?,
+        pages_sharing: read_value(dir, "pages_sharing")?,
+        pages_unshared: read_value(dir, "pages_unshared")?,
+        pages_volatile: read_value(dir, "pages_volatile")?,
+        full_scans: read_value(dir, "full_scans")?,
+        ksm_zero_pages: read_value(dir, "ksm_zero_pages").ok(),
+        general_profit: read_value(dir, "general_profit").ok(),
+    })
+}
+
+pub fn display_ksm_stats(stats: &KsmStats) {
+    #[derive(Tabled)]
+    struct KsmDisplayRow {
+        run: u64,
+        pages_shared: u64,
+        pages_sharing: u64,
+        pages_unshared: u64,
+        pages_volatile: u64,
+        full_scans: u64,
+        sharing_ratio: String,
+        #[tabled(display = "format_units_MiB")]
+        saved: u64,
+    }
+
+    let page_size = procfs::page_size();
+
+    let row = KsmDisplayRow {
+        run: stats.run,
+        pages_shared: stats.pages_shared,
+        pages_sharing: stats.pages_sharing,
+        pages_unshared: stats.pages_unshared,
+        pages_volatile: stats.pages_volatile,
+        full_scans: stats.full_scans,
+        sharing_ratio: format!("{:.2}", stats.sharing_ratio()),
+        saved: stats.pages_sharing * page_size,
+    };
+
+    println!("KSM:");
+    let mut table = tabled::Table::new([row]);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    if let Some(zero_pages) = stats.ksm_zero_pages {
+        println!(
+            "Merged with zero page: {}",
+            format_units_MiB(&(zero_pages * page_size))
+        );
+    }
+    if let Some(profit) = stats.general_profit {
+        // negative when the rmap items cost more than the merged pages
+        println!(
+            "General profit: {}{}",
+            if profit < 0 { "-" } else { "" },
+            format_units_MiB(&profit.unsigned_abs())
+        );
+    }
+    println!();
+}
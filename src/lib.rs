@@ -40,36 +40,64 @@ use log::{info, warn};
 
 use oracle::{Connector, Privilege};
 use std::ffi::OsString;
+use std::time::{Duration, Instant};
 
+use db_info::{DbInfoError, DbInfoRequest};
+
+pub mod asm;
+#[cfg(unix)]
+pub mod cow;
+pub mod db_info;
 #[cfg(unix)]
 pub mod filters;
+pub mod flag_expr;
+#[cfg(unix)]
+pub mod fragmentation;
 #[cfg(unix)]
 pub mod groups;
 #[cfg(unix)]
+pub mod instances;
+#[cfg(unix)]
+pub mod ksm;
+pub mod memory_layout;
+pub mod pdb;
+#[cfg(unix)]
+pub mod pressure;
+#[cfg(unix)]
 pub mod process_tree;
+pub mod render;
+pub mod sga;
+#[cfg(unix)]
+pub mod swap;
 #[cfg(unix)]
 pub mod tmpfs;
+#[cfg(unix)]
+pub mod whatif;
+
+/// PFNs of a memory mapping with pages of `page_size` bytes, end excluded
+pub fn pfn_range(map: &PhysicalMemoryMap, page_size: u64) -> (Pfn, Pfn) {
+    let system_info = ExplicitSystemInfo {
+        boot_time_secs: 0,
+        is_little_endian: false,
+        page_size,
+        ticks_per_second: 1000,
+    };
+    map.get_range().with_system_info(&system_info)
+}
 
 /// Convert pfn to index into non-contiguous memory mappings
 pub fn pfn_to_index(iomem: &[PhysicalMemoryMap], page_size: u64, pfn: Pfn) -> Option<u64> {
-    if pfn.0 == 0 {
-        return None;
-    }
-
     let mut previous_maps_size = 0;
     for map in iomem {
         assert_eq!(map.name, "System RAM");
-        let (pfn_start, pfn_end) = (
-            Pfn(map.address.0 / page_size),
-            Pfn(map.address.1 / page_size),
-        );
+        let (pfn_start, pfn_end) = pfn_range(map, page_size);
         if pfn < pfn_start {
             return None;
         }
-        if pfn <= pfn_end {
+        if pfn < pfn_end {
             return Some(previous_maps_size + pfn.0 - pfn_start.0);
         }
-        previous_maps_size += pfn_end.0 - pfn_start.0 + 1;
+        previous_maps_size += pfn_end.0 - pfn_start.0;
     }
     None
 }
@@ -78,14 +106,11 @@ pub fn pfn_to_index(iomem: &[PhysicalMemoryMap], page_size: u64, pfn: Pfn) -> Op
 pub fn index_to_pfn(iomem: &[PhysicalMemoryMap], page_size: u64, mut index: u64) -> Option<Pfn> {
     for map in iomem {
         assert_eq!(map.name, "System RAM");
-        let (pfn_start, pfn_end) = (
-            Pfn(map.address.0 / page_size),
-            Pfn(map.address.1 / page_size),
-        );
-        if index <= pfn_end.0 - pfn_start.0 {
+        let (pfn_start, pfn_end) = pfn_range(map, page_size);
+        if index < pfn_end.0 - pfn_start.0 {
             return Some(Pfn(index + pfn_start.0));
         }
-        index -= pfn_end.0 - pfn_start.0 + 1;
+        index -= pfn_end.0 - pfn_start.0;
     }
     None
 }
@@ -106,20 +131,12 @@ pub fn pfn_is_in_ram(iomem: &[PhysicalMemoryMap], page_size: u64, pfn: Pfn) -> O
     None
 }
 
-/// Count total number of 4 kiB frames in memory segments
-pub fn get_pfn_count(iomem: &[PhysicalMemoryMap]) -> u64 {
+/// Count total number of frames in memory segments
+pub fn get_pfn_count(iomem: &[PhysicalMemoryMap], page_size: u64) -> u64 {
     iomem
         .iter()
         .map(|map| {
-            //let (start, end) = map.get_range().get();
-            //(map.address.1 - map.address.0) / 4096
-            let system_info = ExplicitSystemInfo {
-                boot_time_secs: 0,
-                is_little_endian: false,
-                page_size: 4096,
-                ticks_per_second: 1000,
-            };
-            let (start, end) = map.get_range().with_system_info(&system_info);
+            let (start, end) = pfn_range(map, page_size);
             end.0 - start.0
         })
         .sum()
@@ -164,7 +181,6 @@ pub fn compute_compound_pages(data: &[PhysicalPageFlags]) -> [u64; FLAG_NAMES.le
     let mut counters = [0u64; FLAG_NAMES.len() + 1];
 
     #[allow(unused_variables)]
-    let mut merged_compound_pages = 0;
     let mut iter = data.iter().peekable();
     while let Some(&flags) = iter.next() {
         if flags.contains(PhysicalPageFlags::COMPOUND_HEAD) {
@@ -180,7 +196,6 @@ pub fn compute_compound_pages(data: &[PhysicalPageFlags]) -> [u64; FLAG_NAMES.le
             for &flags in
                 iter.take_while_ref(|flags| flags.contains(PhysicalPageFlags::COMPOUND_TAIL))
             {
-                merged_compound_pages += 1;
                 let mut tail_flags = flags;
                 tail_flags.insert(head_flags & !PhysicalPageFlags::COMPOUND_HEAD);
 
@@ -205,8 +220,6 @@ pub fn compute_compound_pages(data: &[PhysicalPageFlags]) -> [u64; FLAG_NAMES.le
         }
     }
 
-    //dbg!(merged_compound_pages);
-
     counters
 }
 
@@ -217,7 +230,7 @@ pub fn shm2pfns(
     all_physical_pages: &HashMap<Pfn, PhysicalPageFlags>,
     shm: &Shm,
     force_read: bool,
-) -> Result<Option<(HashSet<Pfn>, HashSet<(u64, u64)>, usize, usize)>, Box<dyn std::error::Error>> {
+) -> Result<Option<ShmPages>, Box<dyn std::error::Error>> {
     let ptr: *mut libc::c_void;
     let shmid: libc::c_int = shm.shmid as i32;
     let must_read = shm.swap == 0 || force_read;
@@ -356,7 +369,7 @@ pub fn get_kernel_datastructure_size(
 pub fn get_memory_maps_for_process(
     process: &Process,
     optimize_shm: bool,
-) -> Result<Vec<(MemoryMap, Vec<PageInfo>)>, Box<dyn std::error::Error>> {
+) -> Result<Vec<MemoryMapPages>, Box<dyn std::error::Error>> {
     let page_size = procfs::page_size();
 
     let mut pagemap = process.pagemap()?;
@@ -389,6 +402,40 @@ pub fn get_memory_maps_for_process(
     Ok(result)
 }
 
+/// Physical pages mapped by a process, swapped pages are ignored. `page_size` is the one of the
+/// host the process runs on, it differs from the local one for a snapshot
+#[cfg(unix)]
+pub fn get_process_pfns(
+    process: &Process,
+    page_size: u64,
+) -> Result<HashSet<Pfn>, Box<dyn std::error::Error>> {
+    let mut pfn_set = HashSet::new();
+
+    let mut pagemap = process.pagemap()?;
+    let memmap = process.maps()?;
+
+    for memory_map in memmap {
+        let page_start = (memory_map.address.0 / page_size) as usize;
+        let page_end = (memory_map.address.1 / page_size) as usize;
+
+        // can't scan Vsyscall, so skip it
+        if memory_map.pathname == MMapPath::Vsyscall {
+            continue;
+        }
+
+        for page_info in pagemap.get_range_info(page_start..page_end)? {
+            match page_info {
+                PageInfo::MemoryPage(memory_page) => {
+                    pfn_set.insert(memory_page.get_page_frame_number());
+                }
+                PageInfo::SwapPage(_) => (),
+            }
+        }
+    }
+
+    Ok(pfn_set)
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone, Debug)]
 pub enum LargePages {
     True,
@@ -417,41 +464,235 @@ impl FromStr for LargePages {
     }
 }
 
-/// Connect to DB using OS auth and env vars
-/// return size of SGA
-pub fn get_db_info() -> Result<(u64, u64, u64, LargePages), Box<dyn std::error::Error>> {
+/// Database, or ASM instance (+ASM), or ASM proxy instance (+APX) of Flex ASM
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum InstanceKind {
+    Database,
+    Asm,
+    AsmProxy,
+}
+
+impl InstanceKind {
+    pub fn from_sid(sid: &OsStr) -> Self {
+        let sid = sid.to_string_lossy();
+        if sid.starts_with("+ASM") {
+            InstanceKind::Asm
+        } else if sid.starts_with("+APX") {
+            InstanceKind::AsmProxy
+        } else {
+            InstanceKind::Database
+        }
+    }
+}
+
+impl Display for InstanceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InstanceKind::Database => write!(f, "DB"),
+            InstanceKind::Asm => write!(f, "ASM"),
+            InstanceKind::AsmProxy => write!(f, "APX"),
+        }
+    }
+}
+
+/// A row of v$process, with the PGA reported by Oracle
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OracleProcess {
+    /// OS pid
+    pub spid: i32,
+    /// Background process name, e.g. PMON, DBW0
+    pub pname: Option<String>,
+    pub program: Option<String>,
+    pub background: bool,
+    pub pga_used_mem: u64,
+    pub pga_alloc_mem: u64,
+    pub pga_max_mem: u64,
+    /// Container of the current session, for a CDB
+    pub con_id: Option<u32>,
+}
+
+/// Rows of v$process with an OS pid
+fn query_oracle_processes(conn: &oracle::Connection) -> Result<Vec<OracleProcess>, oracle::Error> {
+    // the PSEUDO process has no spid
+    let sql = "select spid, pname, program, background, pga_used_mem, pga_alloc_mem, pga_max_mem
+        from v$process where spid is not null";
+    let mut processes = Vec::new();
+    for row in conn.query_as::<(
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        u64,
+        u64,
+        u64,
+    )>(sql, &[])?
+    {
+        let (spid, pname, program, background, pga_used_mem, pga_alloc_mem, pga_max_mem) = row?;
+        // spid is a varchar2, skip anything that is not a pid
+        let Ok(spid) = spid.parse() else {
+            continue;
+        };
+        processes.push(OracleProcess {
+            spid,
+            pname,
+            program,
+            background: background.as_deref() == Some("1"),
+            pga_used_mem,
+            pga_alloc_mem,
+            pga_max_mem,
+            con_id: None,
+        });
+    }
+    Ok(processes)
+}
+
+/// Connect to DB using OS auth and env vars, queries are interrupted at `deadline`
+///
+/// The summary (SGA, PGA, processes, large pages) is required. Other queries may fail, their
+/// errors are returned in `SmonInfo::errors`
+pub fn get_db_info(pid: i32, deadline: Instant) -> Result<SmonInfo, DbInfoError> {
+    let sid = std::env::var_os("ORACLE_SID")
+        .ok_or_else(|| DbInfoError::MissingEnv("ORACLE_SID".to_string()))?;
+    if std::env::var_os("ORACLE_HOME").is_none() {
+        Err(DbInfoError::MissingEnv("ORACLE_HOME".to_string()))?
+    }
+
+    let kind = InstanceKind::from_sid(&sid);
+
     let mut connector = Connector::new("", "", "");
     let mut connector = connector.external_auth(true);
-    connector = if std::env::var("ORACLE_SID").unwrap().contains("+ASM") {
-        connector.privilege(Privilege::Sysasm)
-    } else {
-        connector.privilege(Privilege::Sysdba)
+    connector = match kind {
+        InstanceKind::Asm | InstanceKind::AsmProxy => connector.privilege(Privilege::Sysasm),
+        InstanceKind::Database => connector.privilege(Privilege::Sysdba),
+    };
+    let conn = connector.connect().map_err(DbInfoError::connect)?;
+
+    // 0 would disable the call timeout
+    let set_timeout = |context: &str| {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.as_millis() == 0 {
+            Err(DbInfoError::Timeout(context.to_string()))?
+        }
+        conn.set_call_timeout(Some(remaining))
+            .map_err(|e| DbInfoError::query(context, e))
     };
-    let conn = connector.connect()?;
+
+    set_timeout("v$sga")?;
     let sql = "select sum(value) from v$sga where name in ('Variable Size', 'Database Buffers')";
-    let sga_size = conn.query_row_as::<u64>(sql, &[])?;
+    let sga_size = conn
+        .query_row_as::<u64>(sql, &[])
+        .map_err(|e| DbInfoError::query("v$sga", e))?;
 
+    set_timeout("v$process")?;
     let sql = "select count(1), sum(pga_alloc_mem) from v$process";
-    let (processes, pga) = conn.query_row_as::<(u64, u64)>(sql, &[])?;
+    let (processes, pga_size) = conn
+        .query_row_as::<(u64, u64)>(sql, &[])
+        .map_err(|e| DbInfoError::query("v$process", e))?;
 
+    set_timeout("v$parameter")?;
     let sql = "select value from v$parameter where name = 'use_large_pages'";
-    let large_pages: LargePages = conn.query_row_as::<String>(sql, &[])?.parse()?;
+    let large_pages: LargePages = conn
+        .query_row_as::<String>(sql, &[])
+        .map_err(|e| DbInfoError::query("v$parameter", e))?
+        .parse()
+        .map_err(|message| DbInfoError::Oracle {
+            context: "use_large_pages".to_string(),
+            code: None,
+            message,
+        })?;
+
+    let mut errors = Vec::new();
+
+    let mut pga_processes = set_timeout("v$process")
+        .and_then(|_| query_oracle_processes(&conn).map_err(|e| DbInfoError::query("v$process", e)))
+        .unwrap_or_else(|e| {
+            errors.push(e);
+            Vec::new()
+        });
+
+    // v$containers and v$session.con_id don't exist before 12c, nor in ASM
+    let containers = match kind {
+        InstanceKind::Database => set_timeout("v$containers")
+            .and_then(|_| {
+                pdb::query_containers(&conn).map_err(|e| DbInfoError::query("v$containers", e))
+            })
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                Vec::new()
+            }),
+        InstanceKind::Asm | InstanceKind::AsmProxy => Vec::new(),
+    };
+
+    let asm = match kind {
+        InstanceKind::Asm | InstanceKind::AsmProxy => set_timeout("v$asm_client")
+            .and_then(|_| {
+                asm::AsmInfo::query(&conn).map_err(|e| DbInfoError::query("v$asm_client", e))
+            })
+            .map(Some)
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                None
+            }),
+        InstanceKind::Database => None,
+    };
+    if !containers.is_empty() {
+        match set_timeout("v$session").and_then(|_| {
+            pdb::query_session_containers(&conn).map_err(|e| DbInfoError::query("v$session", e))
+        }) {
+            Ok(session_containers) => {
+                for process in &mut pga_processes {
+                    process.con_id = session_containers.get(&process.spid).copied();
+                }
+            }
+            Err(e) => errors.push(e),
+        }
+    }
 
-    Ok((sga_size, processes, pga, large_pages))
+    let sga_layout = set_timeout("v$sga_dynamic_components")
+        .and_then(|_| {
+            sga::SgaLayout::query(&conn)
+                .map_err(|e| DbInfoError::query("v$sga_dynamic_components", e))
+        })
+        .unwrap_or_else(|e| {
+            errors.push(e);
+            sga::SgaLayout::default()
+        });
+
+    Ok(SmonInfo {
+        pid,
+        sid,
+        kind,
+        sga_size,
+        large_pages,
+        processes,
+        pga_size,
+        pga_processes,
+        sga_layout,
+        containers,
+        asm,
+        errors,
+    })
+}
+
+/// pmon process of a database, ASM or ASM proxy instance
+pub fn is_pmon(cmdline: &[String]) -> bool {
+    cmdline.len() == 1
+        && ["ora_pmon_", "asm_pmon_", "apx_pmon_"]
+            .iter()
+            .any(|prefix| cmdline[0].starts_with(prefix))
 }
 
 /// Find smons processes
 /// For each, return (pid, uid, ORACLE_SID, ORACLE_HOME)
+/// ORACLE_SID defaults to the suffix of the process name, ORACLE_HOME is None if not set
 #[cfg(unix)]
-pub fn find_smons() -> Vec<(i32, u32, OsString, OsString)> {
+pub fn find_smons() -> Vec<(i32, u32, OsString, Option<OsString>)> {
     let smons: Vec<Process> = procfs::process::all_processes()
         .unwrap()
         .filter_map(|proc| {
             let cmdline = proc.as_ref().ok()?.cmdline().ok()?;
 
-            if cmdline.len() == 1
-                && (cmdline[0].starts_with("ora_pmon_") || cmdline[0].starts_with("asm_pmon_"))
-            {
+            if is_pmon(&cmdline) {
                 info!("Found smon {}", cmdline[0]);
                 Some(proc.ok()?)
             } else {
@@ -465,9 +706,17 @@ pub fn find_smons() -> Vec<(i32, u32, OsString, OsString)> {
         .filter_map(|smon| {
             let pid = smon.pid;
             let uid = smon.uid().ok()?;
-            let environ = smon.environ().ok()?;
-            let sid = environ.get(&OsString::from("ORACLE_SID"))?.to_os_string();
-            let home = environ.get(&OsString::from("ORACLE_HOME"))?.to_os_string();
+            let environ = smon.environ().unwrap_or_default();
+            let sid = match environ.get(&OsString::from("ORACLE_SID")) {
+                Some(sid) => sid.to_os_string(),
+                None => {
+                    let cmdline = smon.cmdline().ok()?;
+                    OsString::from(cmdline.first()?.splitn(3, '_').nth(2)?)
+                }
+            };
+            let home = environ
+                .get(&OsString::from("ORACLE_HOME"))
+                .map(|home| home.to_os_string());
 
             Some((pid, uid, sid, home))
         })
@@ -476,12 +725,16 @@ pub fn find_smons() -> Vec<(i32, u32, OsString, OsString)> {
     result
 }
 
+/// Pages of a shm segment: PFNs, swap entries, count of 4K and 2M pages
 #[cfg(unix)]
-pub type ShmsMetadata = HashMap<
-    procfs::Shm,
-    Option<(HashSet<Pfn>, HashSet<(u64, u64)>, usize, usize)>,
-    BuildHasherDefault<FxHasher>,
->;
+pub type ShmPages = (HashSet<Pfn>, HashSet<(u64, u64)>, usize, usize);
+
+#[cfg(unix)]
+pub type ShmsMetadata = HashMap<procfs::Shm, Option<ShmPages>, BuildHasherDefault<FxHasher>>;
+
+/// A mapping of a process, and its pages
+#[cfg(unix)]
+pub type MemoryMapPages = (MemoryMap, Vec<PageInfo>);
 
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone)]
 pub struct ShmReference {
@@ -545,10 +798,21 @@ impl Debug for ProcessGroupInfo {
 pub struct SmonInfo {
     pub pid: i32,
     pub sid: OsString,
+    pub kind: InstanceKind,
     pub sga_size: u64,
     pub large_pages: LargePages,
     pub processes: u64,
     pub pga_size: u64,
+    /// v$process rows, to compare with the memory measured for the same pids
+    pub pga_processes: Vec<OracleProcess>,
+    /// SGA areas and components, to split the shm segments of the instance
+    pub sga_layout: sga::SgaLayout,
+    /// PDBs, empty if the instance is not a CDB
+    pub containers: Vec<pdb::Container>,
+    /// Diskgroups and clients of an ASM instance
+    pub asm: Option<asm::AsmInfo>,
+    /// Queries that failed, the matching fields are empty
+    pub errors: Vec<DbInfoError>,
     //sga_shm: Shm,
     //sga_pfns: HashSet<Pfn>,
 }
@@ -726,17 +990,22 @@ pub fn get_processes_group_info(
 }
 
 /// Spawn new process with database user
-/// return smon info
+/// return smon info, the process is killed after `timeout`
 #[cfg(unix)]
 pub fn get_smon_info(
     pid: i32,
     uid: u32,
     sid: &OsStr,
     home: &OsStr,
-) -> Result<SmonInfo, Box<dyn std::error::Error>> {
-    let myself = std::env::current_exe()?;
+    timeout: Duration,
+) -> Result<SmonInfo, DbInfoError> {
+    use std::io::{Read, Write};
+
+    let myself = std::env::current_exe()
+        .map_err(|e| DbInfoError::Subprocess(format!("can't find memstats executable: {e}")))?;
 
-    let user = uzers::get_user_by_uid(uid).ok_or("Can't find user for uid {uid}")?;
+    let user = uzers::get_user_by_uid(uid)
+        .ok_or_else(|| DbInfoError::Subprocess(format!("can't find user for uid {uid}")))?;
     let gid = user.primary_group_id();
 
     let mut lib = home.to_os_string();
@@ -749,27 +1018,148 @@ pub fn get_smon_info(
         .uid(uid)
         .gid(gid)
         .arg("get-db-info")
-        .args(["--pid", &format!("{pid}")])
+        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
     if let Some(groups) = user.groups() {
         let groups: Vec<u32> = groups.iter().map(|g| g.gid()).collect();
         cmd.groups(&groups);
     }
-    let child = cmd
-        .spawn()
-        .map_err(|e| format!("{:?} can't exec {:?}: {:?}", user, &myself, e))?;
-    let output = child.wait_with_output()?;
-
-    if !output.status.success() {
-        Err(format!(
-            "Proces failed for DB {sid:?} {uid} {home:?}: {:?}",
-            output
-        ))?;
+    let mut child = cmd.spawn().map_err(|e| {
+        DbInfoError::Subprocess(format!("{:?} can't exec {:?}: {e}", user.name(), myself))
+    })?;
+
+    let request = DbInfoRequest {
+        version: db_info::PROTOCOL_VERSION,
+        pid,
+        timeout,
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    writeln!(stdin, "{}", serde_json::to_string(&request).unwrap())
+        .map_err(|e| DbInfoError::Subprocess(format!("can't send request: {e}")))?;
+    drop(stdin);
+
+    // read while the child runs, the response may not fit in the pipe
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut output = Vec::new();
+            let _ = pipe.read_to_end(&mut output);
+            String::from_utf8_lossy(&output).to_string()
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read(Box::new(child.stderr.take().expect("stderr is piped")));
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() < timeout => std::thread::sleep(Duration::from_millis(50)),
+            Ok(None) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(DbInfoError::Timeout(format!(
+                    "no response after {}s",
+                    timeout.as_secs_f64()
+                )))?
+            }
+            Err(e) => Err(DbInfoError::Subprocess(format!("can't wait: {e}")))?,
+        }
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    if !status.success() {
+        let last_line = stderr.lines().rfind(|line| !line.trim().is_empty());
+        Err(DbInfoError::Subprocess(format!(
+            "{status}: {}",
+            last_line.unwrap_or_default()
+        )))?;
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    db_info::parse_response(&stdout)
+}
 
-    let smon_info: SmonInfo = serde_json::from_str(&stdout)?;
-    Ok(smon_info)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+    const PAGE_SIZES: [u64; 3] = [4096, 16 * 1024, 64 * 1024];
+
+    /// 2 GiB from 1 GiB, a hole, then 1 GiB from 4 GiB, like an aarch64 host
+    fn iomem() -> Vec<PhysicalMemoryMap> {
+        [(GIB, 3 * GIB - 1), (4 * GIB, 5 * GIB - 1)]
+            .into_iter()
+            .map(|address| PhysicalMemoryMap {
+                address,
+                name: "System RAM".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pfn_count() {
+        let iomem = iomem();
+        for page_size in PAGE_SIZES {
+            assert_eq!(get_pfn_count(&iomem, page_size), 3 * GIB / page_size);
+        }
+    }
+
+    #[test]
+    fn pfn_ranges() {
+        let iomem = iomem();
+        for page_size in PAGE_SIZES {
+            assert_eq!(
+                pfn_range(&iomem[0], page_size),
+                (Pfn(GIB / page_size), Pfn(3 * GIB / page_size))
+            );
+            assert_eq!(
+                pfn_range(&iomem[1], page_size),
+                (Pfn(4 * GIB / page_size), Pfn(5 * GIB / page_size))
+            );
+        }
+    }
+
+    #[test]
+    fn pfn_index_roundtrip() {
+        let iomem = iomem();
+        for page_size in PAGE_SIZES {
+            let first_segment = 2 * GIB / page_size;
+            for (pfn, index) in [
+                (GIB / page_size, 0),
+                (3 * GIB / page_size - 1, first_segment - 1),
+                (4 * GIB / page_size, first_segment),
+                (5 * GIB / page_size - 1, 3 * GIB / page_size - 1),
+            ] {
+                assert_eq!(pfn_to_index(&iomem, page_size, Pfn(pfn)), Some(index));
+                assert_eq!(index_to_pfn(&iomem, page_size, index), Some(Pfn(pfn)));
+            }
+        }
+    }
+
+    #[test]
+    fn pfn_outside_ram() {
+        let iomem = iomem();
+        for page_size in PAGE_SIZES {
+            // before RAM, in the hole, after RAM
+            for address in [0, GIB - 1, 3 * GIB, 4 * GIB - 1, 5 * GIB] {
+                assert_eq!(
+                    pfn_to_index(&iomem, page_size, Pfn(address / page_size)),
+                    None
+                );
+            }
+            let pfn_count = get_pfn_count(&iomem, page_size);
+            assert_eq!(index_to_pfn(&iomem, page_size, pfn_count), None);
+        }
+    }
+
+    #[test]
+    fn hilbert_order() {
+        let iomem = iomem();
+        // 786432, 196608 and 49152 pages
+        for (page_size, order) in PAGE_SIZES.into_iter().zip([10, 9, 8]) {
+            assert_eq!(render::hilbert_order(&iomem, page_size), order);
+        }
+    }
 }
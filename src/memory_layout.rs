@@ -0,0 +1,371 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::warn;
+use procfs_core::{process::Pfn, PhysicalMemoryMap};
+use serde::{Deserialize, Serialize};
+
+/// Memory zone of a NUMA node, from /proc/zoneinfo
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Zone {
+    pub node: u32,
+    /// DMA, DMA32, Normal, Movable...
+    pub name: String,
+    pub start_pfn: Pfn,
+    /// Excluded. Zones can span holes
+    pub end_pfn: Pfn,
+}
+
+/// Hotpluggable memory block, from /sys/devices/system/memory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemoryBlock {
+    pub id: u64,
+    pub node: u32,
+    pub start_pfn: Pfn,
+    /// Excluded
+    pub end_pfn: Pfn,
+    pub online: bool,
+}
+
+/// Zones, NUMA nodes and memory blocks boundaries
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub zones: Vec<Zone>,
+    /// Empty if memory hotplug is not supported
+    pub memory_blocks: Vec<MemoryBlock>,
+}
+
+impl MemoryLayout {
+    /// Missing files give empty lists
+    pub fn read(page_size: u64) -> Self {
+        let zones = std::fs::read_to_string("/proc/zoneinfo")
+            .map(|content| parse_zoneinfo(&content))
+            .unwrap_or_else(|e| {
+                warn!("Can't read /proc/zoneinfo: {e}");
+                Vec::new()
+            });
+        let memory_blocks = read_memory_blocks(page_size).unwrap_or_else(|e| {
+            warn!("Can't read memory blocks: {e:?}");
+            Vec::new()
+        });
+
+        Self {
+            zones,
+            memory_blocks,
+        }
+    }
+
+    pub fn zone_at(&self, pfn: Pfn) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .find(|zone| pfn >= zone.start_pfn && pfn < zone.end_pfn)
+    }
+
+    pub fn block_at(&self, pfn: Pfn) -> Option<&MemoryBlock> {
+        self.memory_blocks
+            .iter()
+            .find(|block| pfn >= block.start_pfn && pfn < block.end_pfn)
+    }
+
+    /// (node, start, end) ranges, contiguous memory blocks of a node are merged
+    ///
+    /// Use zones if memory blocks are not available
+    pub fn nodes(&self) -> Vec<(u32, Pfn, Pfn)> {
+        let mut ranges: Vec<(u32, Pfn, Pfn)> = if self.memory_blocks.is_empty() {
+            self.zones
+                .iter()
+                .map(|zone| (zone.node, zone.start_pfn, zone.end_pfn))
+                .collect()
+        } else {
+            self.memory_blocks
+                .iter()
+                .map(|block| (block.node, block.start_pfn, block.end_pfn))
+                .collect()
+        };
+        ranges.sort_by_key(|&(_, start, _)| start);
+
+        let mut nodes: Vec<(u32, Pfn, Pfn)> = Vec::new();
+        for (node, start, end) in ranges {
+            match nodes.last_mut() {
+                Some(last) if last.0 == node && last.2 >= start => last.2 = last.2.max(end),
+                _ => nodes.push((node, start, end)),
+            }
+        }
+        nodes
+    }
+}
+
+/// Zones with pages, empty zones have no start_pfn
+pub fn parse_zoneinfo(content: &str) -> Vec<Zone> {
+    let mut zones = Vec::new();
+    // node, zone name, spanned pages
+    let mut current: Option<(u32, String, u64)> = None;
+
+    for line in content.lines() {
+        if let Some(rest) = line.strip_prefix("Node ") {
+            // Node 0, zone    DMA32
+            current = rest.split_once(", zone").and_then(|(node, name)| {
+                Some((node.trim().parse().ok()?, name.trim().to_string(), 0))
+            });
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        match (fields.next(), fields.next(), current.as_mut()) {
+            (Some("spanned"), Some(value), Some(current)) => {
+                current.2 = value.parse().unwrap_or(0);
+            }
+            (Some("start_pfn:"), Some(value), Some((node, name, spanned))) if *spanned > 0 => {
+                if let Ok(start_pfn) = value.parse::<u64>() {
+                    zones.push(Zone {
+                        node: *node,
+                        name: name.clone(),
+                        start_pfn: Pfn(start_pfn),
+                        end_pfn: Pfn(start_pfn + *spanned),
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    zones
+}
+
+/// Memory blocks of each node, from /sys/devices/system/node/node*/memory*
+pub fn read_memory_blocks(page_size: u64) -> Result<Vec<MemoryBlock>> {
+    let block_size = std::fs::read_to_string("/sys/devices/system/memory/block_size_bytes")
+        .with_context(|| "Can't read memory block size")?;
+    let block_size = u64::from_str_radix(block_size.trim(), 16)
+        .with_context(|| format!("Invalid memory block size {block_size:?}"))?;
+    let block_pages = block_size / page_size;
+
+    let mut blocks = Vec::new();
+    for node_entry in std::fs::read_dir("/sys/devices/system/node")? {
+        let node_entry = node_entry?;
+        let name = node_entry.file_name().to_string_lossy().to_string();
+        let Some(Ok(node)) = name.strip_prefix("node").map(|node| node.parse::<u32>()) else {
+            continue;
+        };
+
+        for block_entry in std::fs::read_dir(node_entry.path())? {
+            let block_entry = block_entry?;
+            let name = block_entry.file_name().to_string_lossy().to_string();
+            let Some(Ok(id)) = name.strip_prefix("memory").map(|id| id.parse::<u64>()) else {
+                continue;
+            };
+            let state = std::fs::read_to_string(Path::new(&block_entry.path()).join("state"))
+                .unwrap_or_default();
+
+            blocks.push(MemoryBlock {
+                id,
+                node,
+                start_pfn: Pfn(id * block_pages),
+                end_pfn: Pfn((id + 1) * block_pages),
+                online: state.trim() == "online",
+            });
+        }
+    }
+    blocks.sort_by_key(|block| block.id);
+
+    Ok(blocks)
+}
+
+/// Index of the range containing the PFN, ranges are sorted by start
+fn range_at(ranges: &[(Pfn, Pfn)], pfn: Pfn) -> Option<usize> {
+    let idx = ranges
+        .partition_point(|(start, _)| *start <= pfn)
+        .checked_sub(1)?;
+    (pfn < ranges[idx].1).then_some(idx)
+}
+
+/// Pixels on the border of each range, on the Hilbert curve of order `order`
+///
+/// With `level` > 0, each pixel is a block of 4^level pages, like the viewer overview
+pub fn outline_pixels(
+    iomem: &[PhysicalMemoryMap],
+    page_size: u64,
+    order: u8,
+    level: u8,
+    ranges: &[(Pfn, Pfn)],
+) -> Vec<(u32, u32)> {
+    let side = 2usize.pow((order - level) as u32);
+    // 0: not in any range
+    let mut ids = vec![0u32; side * side];
+    for (range_idx, &(start, end)) in ranges.iter().enumerate() {
+        // pixels of the RAM pages of the range, from its intersection with each RAM segment
+        let mut segment_index = 0;
+        for map in iomem {
+            let (segment_start, segment_end) = crate::pfn_range(map, page_size);
+            let (first, last) = (start.max(segment_start), end.min(segment_end));
+            if first < last {
+                let first = segment_index + first.0 - segment_start.0;
+                let last = segment_index + last.0 - segment_start.0 - 1;
+                for pixel in first >> (2 * level)..=last >> (2 * level) {
+                    let (x, y) = fast_hilbert::h2xy::<u64>(pixel.into(), order - level);
+                    ids[y as usize * side + x as usize] = range_idx as u32 + 1;
+                }
+            }
+            segment_index += segment_end.0 - segment_start.0;
+        }
+    }
+
+    let mut pixels = Vec::new();
+    for y in 0..side {
+        for x in 0..side {
+            let id = ids[y * side + x];
+            if id == 0 {
+                continue;
+            }
+            let border = [(0, -1), (0, 1), (-1, 0), (1, 0)].iter().any(|(dx, dy)| {
+                let (nx, ny) = (x as isize + dx, y as isize + dy);
+                nx < 0
+                    || ny < 0
+                    || nx >= side as isize
+                    || ny >= side as isize
+                    || ids[ny as usize * side + nx as usize] != id
+            });
+            if border {
+                pixels.push((x as u32, y as u32));
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Whether the page at (x, y) on the Hilbert curve of order `order` is on the border of a range
+///
+/// Same borders as `outline_pixels` at level 0, without a pass over the whole map. Ranges are
+/// sorted by start
+pub fn is_outline(
+    iomem: &[PhysicalMemoryMap],
+    page_size: u64,
+    order: u8,
+    ranges: &[(Pfn, Pfn)],
+    (x, y): (u32, u32),
+) -> bool {
+    let side = 2i64.pow(order as u32);
+    let range_of = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= side || y >= side {
+            return None;
+        }
+        let index = fast_hilbert::xy2h::<u64>(x as u64, y as u64, order) as u64;
+        crate::index_to_pfn(iomem, page_size, index).and_then(|pfn| range_at(ranges, pfn))
+    };
+
+    let (x, y) = (x as i64, y as i64);
+    match range_of(x, y) {
+        Some(id) => [(0, -1), (0, 1), (-1, 0), (1, 0)]
+            .iter()
+            .any(|(dx, dy)| range_of(x + dx, y + dy) != Some(id)),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: u64 = 4096;
+
+    /// 2 RAM segments with a hole: PFNs 0x1-0x110 and 0x200-0x300, 527 pages
+    fn iomem() -> Vec<PhysicalMemoryMap> {
+        [(0x1000, 0x10ffff), (0x200000, 0x2fffff)]
+            .into_iter()
+            .map(|address| PhysicalMemoryMap {
+                address,
+                name: "System RAM".to_string(),
+            })
+            .collect()
+    }
+
+    /// Zones, and ranges across the hole or outside RAM
+    fn ranges() -> Vec<(Pfn, Pfn)> {
+        vec![
+            (Pfn(0), Pfn(0x80)),
+            (Pfn(0x80), Pfn(0x250)),
+            (Pfn(0x250), Pfn(0x251)),
+            (Pfn(0x2f0), Pfn(0x400)),
+        ]
+    }
+
+    /// Page after page
+    fn outline_pixels_per_page(
+        iomem: &[PhysicalMemoryMap],
+        order: u8,
+        level: u8,
+        ranges: &[(Pfn, Pfn)],
+    ) -> Vec<(u32, u32)> {
+        let side = 2u32.pow((order - level) as u32);
+        let mut ids = vec![0u32; (side * side) as usize];
+        for (range_idx, (start, end)) in ranges.iter().enumerate() {
+            for pfn in start.0..end.0 {
+                if let Some(index) = crate::pfn_to_index(iomem, PAGE_SIZE, Pfn(pfn)) {
+                    let (x, y) =
+                        fast_hilbert::h2xy::<u64>((index >> (2 * level)).into(), order - level);
+                    ids[(y as u32 * side + x as u32) as usize] = range_idx as u32 + 1;
+                }
+            }
+        }
+        let id = |x: i64, y: i64| {
+            if x >= 0 && y >= 0 && x < side as i64 && y < side as i64 {
+                ids[(y * side as i64 + x) as usize]
+            } else {
+                0
+            }
+        };
+        let mut pixels = Vec::new();
+        for y in 0..side as i64 {
+            for x in 0..side as i64 {
+                let border = [(0, -1), (0, 1), (-1, 0), (1, 0)]
+                    .iter()
+                    .any(|(dx, dy)| id(x + dx, y + dy) != id(x, y));
+                if id(x, y) != 0 && border {
+                    pixels.push((x as u32, y as u32));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn outlines() {
+        let iomem = iomem();
+        let order = 5;
+        assert_eq!(crate::get_pfn_count(&iomem, PAGE_SIZE), 527);
+        for level in 0..=3 {
+            assert_eq!(
+                outline_pixels(&iomem, PAGE_SIZE, order, level, &ranges()),
+                outline_pixels_per_page(&iomem, order, level, &ranges()),
+                "level {level}"
+            );
+        }
+    }
+
+    #[test]
+    fn full_resolution_outlines() {
+        let iomem = iomem();
+        let order = 5;
+        let mut pixels = Vec::new();
+        for y in 0..32 {
+            for x in 0..32 {
+                if is_outline(&iomem, PAGE_SIZE, order, &ranges(), (x, y)) {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        assert_eq!(
+            outline_pixels(&iomem, PAGE_SIZE, order, 0, &ranges()),
+            pixels
+        );
+    }
+
+    #[test]
+    fn no_ranges() {
+        assert!(outline_pixels(&iomem(), PAGE_SIZE, 5, 1, &[]).is_empty());
+        // outside RAM
+        let ranges = [(Pfn(0x110), Pfn(0x200))];
+        assert!(outline_pixels(&iomem(), PAGE_SIZE, 5, 1, &ranges).is_empty());
+    }
+}
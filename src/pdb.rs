@@ -0,0 +1,131 @@
+//! Oracle multitenant containers: PDBs of a CDB, and the container of each server process
+
+use std::collections::HashMap;
+
+use oracle::Connection;
+use serde::{Deserialize, Serialize};
+
+/// Metrics of v$con_sysmetric displayed for each container
+pub const CONTAINER_METRICS: [&str; 3] =
+    ["Session Count", "CPU Usage Per Sec", "Total PGA Allocated"];
+
+/// A container of v$containers: CDB$ROOT, PDB$SEED and PDBs
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Container {
+    pub con_id: u32,
+    pub name: String,
+    pub open_mode: Option<String>,
+    /// CONTAINER_METRICS from v$con_sysmetric, by metric name
+    pub metrics: HashMap<String, f64>,
+}
+
+/// Containers of the instance, empty if not a CDB (or before 12c)
+pub fn query_containers(conn: &Connection) -> Result<Vec<Container>, oracle::Error> {
+    let sql = "select con_id, name, open_mode from v$containers where con_id > 0";
+    let mut containers: Vec<Container> = Vec::new();
+    for row in conn.query_as::<(u32, String, Option<String>)>(sql, &[])? {
+        let (con_id, name, open_mode) = row?;
+        containers.push(Container {
+            con_id,
+            name,
+            open_mode,
+            metrics: HashMap::new(),
+        });
+    }
+
+    let sql = format!(
+        "select con_id, metric_name, value from v$con_sysmetric where metric_name in ({})",
+        CONTAINER_METRICS
+            .iter()
+            .map(|metric| format!("'{metric}'"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for row in conn.query_as::<(u32, String, f64)>(&sql, &[])? {
+        let (con_id, metric, value) = row?;
+        if let Some(container) = containers.iter_mut().find(|c| c.con_id == con_id) {
+            container.metrics.insert(metric, value);
+        }
+    }
+
+    Ok(containers)
+}
+
+/// Container of the current session of each server process, by OS pid
+///
+/// Processes without a session, or with several sessions in different containers, are left out
+pub fn query_session_containers(conn: &Connection) -> Result<HashMap<i32, u32>, oracle::Error> {
+    let sql = "select p.spid, min(s.con_id), max(s.con_id)
+        from v$process p join v$session s on s.paddr = p.addr
+        where p.spid is not null group by p.spid";
+    let mut containers = HashMap::new();
+    for row in conn.query_as::<(String, u32, u32)>(sql, &[])? {
+        let (spid, min_con_id, max_con_id) = row?;
+        if let (Ok(pid), true) = (spid.parse(), min_con_id == max_con_id) {
+            containers.insert(pid, min_con_id);
+        }
+    }
+    Ok(containers)
+}
+
+/// PGA of the server processes of each container, next to v$con_sysmetric
+#[cfg(unix)]
+pub fn display(sid: &str, containers: &[Container], processes: &[crate::OracleProcess]) {
+    use crate::tmpfs::format_units_MiB;
+    use tabled::Tabled;
+
+    #[derive(Tabled)]
+    struct ContainerDisplayRow {
+        con_id: u32,
+        name: String,
+        open_mode: String,
+        processes: usize,
+        #[tabled(display = "format_units_MiB")]
+        pga_used: u64,
+        #[tabled(display = "format_units_MiB")]
+        pga_alloc: u64,
+        sessions: String,
+        #[tabled(rename = "cpu (centisec/s)")]
+        cpu: String,
+        #[tabled(rename = "pga allocated (sysmetric)")]
+        pga_metric: String,
+    }
+
+    let rows: Vec<ContainerDisplayRow> = containers
+        .iter()
+        .map(|container| {
+            let processes: Vec<&crate::OracleProcess> = processes
+                .iter()
+                .filter(|process| process.con_id == Some(container.con_id))
+                .collect();
+            let metric = |name: &str| {
+                container
+                    .metrics
+                    .get(name)
+                    .map(|value| format!("{value:.0}"))
+                    .unwrap_or_default()
+            };
+            ContainerDisplayRow {
+                con_id: container.con_id,
+                name: container.name.clone(),
+                open_mode: container.open_mode.clone().unwrap_or_default(),
+                processes: processes.len(),
+                pga_used: processes.iter().map(|p| p.pga_used_mem).sum(),
+                pga_alloc: processes.iter().map(|p| p.pga_alloc_mem).sum(),
+                sessions: metric("Session Count"),
+                cpu: metric("CPU Usage Per Sec"),
+                pga_metric: container
+                    .metrics
+                    .get("Total PGA Allocated")
+                    .map(|bytes| format_units_MiB(&(*bytes as u64)))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    println!("Oracle containers of {sid} (processes by the container of their session):");
+    let mut table = tabled::Table::new(&rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!();
+}
@@ -0,0 +1,214 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use procfs::{Current, FromRead, MemoryPressure};
+use tabled::Tabled;
+
+use crate::tmpfs::format_units_MiB;
+
+/// /proc/vmstat counters related to reclaim, swap, compaction and OOM
+/// Counters that don't exist on the running kernel are skipped
+pub const VMSTAT_COUNTERS: [&str; 16] = [
+    "pgscan_kswapd",
+    "pgscan_direct",
+    "pgscan_khugepaged",
+    "pgsteal_kswapd",
+    "pgsteal_direct",
+    "allocstall_normal",
+    "allocstall_movable",
+    "pswpin",
+    "pswpout",
+    "workingset_refault_anon",
+    "workingset_refault_file",
+    "compact_stall",
+    "compact_fail",
+    "compact_success",
+    "thp_fault_fallback",
+    "oom_kill",
+];
+
+/// Memory pressure over a sampling window
+pub struct PressureSample {
+    pub window: Duration,
+    /// PSI at the end of the window. None if PSI is not available (kernel < 4.20 or psi=0)
+    pub psi: Option<MemoryPressure>,
+    /// Stall time during the window (some, full)
+    pub stall: Option<(Duration, Duration)>,
+    /// Counter increase during the window
+    pub vmstat: Vec<(&'static str, i64)>,
+}
+
+/// Read PSI and vmstat, sleep for `window`, then read again
+pub fn sample_pressure(window: Duration) -> PressureSample {
+    let psi_before = MemoryPressure::current().ok();
+    let vmstat_before = procfs::vmstat().unwrap_or_default();
+
+    std::thread::sleep(window);
+
+    let psi = MemoryPressure::current().ok();
+    let vmstat_after = procfs::vmstat().unwrap_or_default();
+
+    let stall = match (&psi_before, &psi) {
+        (Some(before), Some(after)) => Some((
+            Duration::from_micros(after.some.total - before.some.total),
+            Duration::from_micros(after.full.total - before.full.total),
+        )),
+        _ => None,
+    };
+
+    let vmstat = VMSTAT_COUNTERS
+        .iter()
+        .filter_map(|&name| {
+            let before = vmstat_before.get(name)?;
+            let after = vmstat_after.get(name)?;
+            Some((name, after - before))
+        })
+        .collect();
+
+    PressureSample {
+        window,
+        psi,
+        stall,
+        vmstat,
+    }
+}
+
+pub fn display_pressure(sample: &PressureSample) {
+    #[derive(Tabled)]
+    struct PsiDisplayRow {
+        kind: &'static str,
+        avg10: f32,
+        avg60: f32,
+        avg300: f32,
+        #[tabled(rename = "window %")]
+        window: String,
+    }
+
+    #[derive(Tabled)]
+    struct VmstatDisplayRow {
+        counter: &'static str,
+        delta: i64,
+        #[tabled(rename = "per sec")]
+        rate: String,
+    }
+
+    println!("Memory pressure over {:?}:", sample.window);
+
+    match (&sample.psi, sample.stall) {
+        (Some(psi), Some((some, full))) => {
+            let percent = |stall: Duration| {
+                format!(
+                    "{:.2}",
+                    stall.as_secs_f64() / sample.window.as_secs_f64() * 100.
+                )
+            };
+            let rows = [
+                PsiDisplayRow {
+                    kind: "some",
+                    avg10: psi.some.avg10,
+                    avg60: psi.some.avg60,
+                    avg300: psi.some.avg300,
+                    window: percent(some),
+                },
+                PsiDisplayRow {
+                    kind: "full",
+                    avg10: psi.full.avg10,
+                    avg60: psi.full.avg60,
+                    avg300: psi.full.avg300,
+                    window: percent(full),
+                },
+            ];
+            let mut table = tabled::Table::new(rows);
+            table.with(tabled::settings::Style::sharp());
+            println!("{table}");
+        }
+        _ => println!("PSI not available (/proc/pressure/memory)"),
+    }
+
+    let rows: Vec<VmstatDisplayRow> = sample
+        .vmstat
+        .iter()
+        .map(|&(counter, delta)| VmstatDisplayRow {
+            counter,
+            delta,
+            rate: format!("{:.1}", delta as f64 / sample.window.as_secs_f64()),
+        })
+        .collect();
+    let mut table = tabled::Table::new(rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!();
+}
+
+/// Mount point of the cgroup v2 hierarchy, /sys/fs/cgroup or /sys/fs/cgroup/unified for hybrid setups
+pub fn cgroup2_mount_point() -> Option<PathBuf> {
+    procfs::process::Process::myself()
+        .ok()?
+        .mountinfo()
+        .ok()?
+        .into_iter()
+        .find(|mountinfo| mountinfo.fs_type == "cgroup2")
+        .map(|mountinfo| mountinfo.mount_point)
+}
+
+/// Path of the cgroup v2 of the process, relative to the cgroup2 mount point
+pub fn get_cgroup2_path(process: &procfs::process::Process) -> Option<String> {
+    process
+        .cgroups()
+        .ok()?
+        .0
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy == 0)
+        .map(|cgroup| cgroup.pathname)
+}
+
+/// Memory stats of a cgroup v2
+pub struct CgroupMemoryStats {
+    /// memory.current
+    pub current: Option<u64>,
+    /// memory.max, None if unlimited
+    pub max: Option<u64>,
+    /// memory.stat
+    pub stat: HashMap<String, u64>,
+    /// memory.pressure
+    pub pressure: Option<MemoryPressure>,
+}
+
+/// Return None if the memory controller is not enabled for this cgroup
+pub fn get_cgroup_memory_stats(mount_point: &Path, cgroup: &str) -> Option<CgroupMemoryStats> {
+    let dir = mount_point.join(cgroup.trim_start_matches('/'));
+
+    let stat: HashMap<String, u64> = std::fs::read_to_string(dir.join("memory.stat"))
+        .ok()?
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect();
+    let read_u64 = |name: &str| -> Option<u64> {
+        std::fs::read_to_string(dir.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+
+    Some(CgroupMemoryStats {
+        current: read_u64("memory.current"),
+        max: read_u64("memory.max"),
+        stat,
+        pressure: MemoryPressure::from_file(dir.join("memory.pressure")).ok(),
+    })
+}
+
+/// memory.max is "max" if unlimited
+pub fn format_option_units_MiB(value: &Option<u64>) -> String {
+    match value {
+        Some(value) => format_units_MiB(value),
+        None => "-".to_string(),
+    }
+}
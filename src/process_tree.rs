@@ -51,11 +51,8 @@ impl ProcessTree {
 
         while let Some(current) = pool.pop() {
             for &(ppid, pid) in &self.edges {
-                if ppid == current {
-                    if !descendants.contains(&pid) {
-                        descendants.insert(pid);
-                        pool.push(pid);
-                    }
+                if ppid == current && descendants.insert(pid) {
+                    pool.push(pid);
                 }
             }
         }
@@ -0,0 +1,259 @@
+//! Physical memory maps on a Hilbert curve, rendered to images
+//!
+//! Shared by the headless `memmap-render` tool: pixel placement, legends, scaling and animations
+
+use ab_glyph::{FontRef, PxScale};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use colorgrad::Gradient;
+use image::{imageops::FilterType, ImageBuffer, Rgb, RgbImage};
+use procfs_core::{process::Pfn, PhysicalMemoryMap};
+
+/// Top level "System RAM" segments of /proc/iomem
+pub fn system_ram(iomem: &[(usize, PhysicalMemoryMap)]) -> Vec<PhysicalMemoryMap> {
+    iomem
+        .iter()
+        .filter(|(ident, map)| *ident == 0 && map.name == "System RAM")
+        .map(|(_, map)| map.clone())
+        .collect()
+}
+
+/// Order of the smallest Hilbert curve containing all pages
+pub fn hilbert_order(iomem: &[PhysicalMemoryMap], page_size: u64) -> u8 {
+    let pfns = crate::get_pfn_count(iomem, page_size);
+    let order = (pfns as f64).log2() / 2.;
+    order.ceil() as u8
+}
+
+/// Position of RAM pages on the Hilbert curve
+pub struct HilbertMap<'a> {
+    pub iomem: &'a [PhysicalMemoryMap],
+    pub page_size: u64,
+    pub order: u8,
+}
+
+impl<'a> HilbertMap<'a> {
+    pub fn new(iomem: &'a [PhysicalMemoryMap], page_size: u64) -> Self {
+        Self {
+            iomem,
+            page_size,
+            order: hilbert_order(iomem, page_size),
+        }
+    }
+
+    /// Width and height of the map
+    pub fn side(&self) -> u32 {
+        2u32.pow(self.order as u32)
+    }
+
+    /// None if the page is not in RAM
+    pub fn xy(&self, pfn: Pfn) -> Option<(u32, u32)> {
+        let index = crate::pfn_to_index(self.iomem, self.page_size, pfn)?;
+        let (x, y) = fast_hilbert::h2xy::<u64>(index.into(), self.order);
+        if x >= self.side() as u64 || y >= self.side() as u64 {
+            return None;
+        }
+        Some((x as u32, y as u32))
+    }
+
+    /// (segment index, start, end) of each RAM segment
+    pub fn segments(&self) -> impl Iterator<Item = (usize, Pfn, Pfn)> + '_ {
+        self.iomem.iter().enumerate().map(|(idx, map)| {
+            let (start, end) = crate::pfn_range(map, self.page_size);
+            (idx, start, end)
+        })
+    }
+
+    /// Black image, with RAM pages filled with `ram_color`
+    pub fn background(&self, ram_color: Rgb<u8>) -> RgbImage {
+        let mut img: RgbImage = ImageBuffer::new(self.side(), self.side());
+        for (_, start, end) in self.segments() {
+            for pfn in start.0..end.0 {
+                if let Some((x, y)) = self.xy(Pfn(pfn)) {
+                    img.put_pixel(x, y, ram_color);
+                }
+            }
+        }
+        img
+    }
+}
+
+/// Colour at `idx` of `count` evenly spaced colours
+pub fn rainbow(idx: usize, count: usize) -> Rgb<u8> {
+    let color = colorgrad::preset::rainbow()
+        .at(idx as f32 / count.max(1) as f32)
+        .to_rgba8();
+    Rgb([color[0], color[1], color[2]])
+}
+
+/// `RRGGBB` or `#RRGGBB`
+pub fn parse_color(input: &str) -> Result<Rgb<u8>> {
+    let hex = input.strip_prefix('#').unwrap_or(input);
+    if hex.len() != 6 || !hex.is_ascii() {
+        bail!("Invalid colour {input:?}, expected RRGGBB");
+    }
+    let channel = |idx: usize| {
+        u8::from_str_radix(&hex[idx..idx + 2], 16)
+            .with_context(|| format!("Invalid colour {input:?}, expected RRGGBB"))
+    };
+    Ok(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// Nearest neighbour scaling, to keep pages sharp
+pub fn scale(img: &RgbImage, side: u32) -> RgbImage {
+    image::imageops::resize(img, side, side, FilterType::Nearest)
+}
+
+#[derive(Debug, Clone)]
+pub struct LegendEntry {
+    pub color: Rgb<u8>,
+    pub label: String,
+}
+
+fn font() -> FontRef<'static> {
+    FontRef::try_from_slice(include_bytes!(
+        "../fonts/dejavu-fonts-ttf-2.37/ttf/DejaVuSans.ttf"
+    ))
+    .unwrap()
+}
+
+/// Append a legend on the right of the map: a coloured disc and a label per entry
+pub fn draw_legend(map: &RgbImage, entries: &[LegendEntry], font_size: f32) -> RgbImage {
+    let font = font();
+    let scale = PxScale {
+        x: font_size,
+        y: font_size,
+    };
+    let line_height = font_size as u32 * 2;
+    let margin = font_size as u32 * 2;
+
+    let text_width = entries
+        .iter()
+        .map(|entry| imageproc::drawing::text_size(scale, &font, &entry.label).0)
+        .max()
+        .unwrap_or(0);
+    let width = map.width() + margin * 3 + font_size as u32 * 2 + text_width;
+    let height = map
+        .height()
+        .max(margin * 2 + line_height * entries.len() as u32);
+
+    let mut img: RgbImage = ImageBuffer::new(width, height);
+    image::imageops::replace(&mut img, map, 0, 0);
+
+    let radius = font_size as i32 / 2;
+    for (idx, entry) in entries.iter().enumerate() {
+        let x = (map.width() + margin) as i32 + radius;
+        let y = (margin + line_height * idx as u32) as i32;
+
+        imageproc::drawing::draw_filled_circle_mut(&mut img, (x, y + radius), radius, entry.color);
+        imageproc::drawing::draw_text_mut(
+            &mut img,
+            Rgb([255, 255, 255]),
+            x + font_size as i32,
+            y,
+            scale,
+            &font,
+            &entry.label,
+        );
+    }
+
+    img
+}
+
+/// White text on a black box, in the top left corner
+pub fn draw_caption(img: &mut RgbImage, text: &str, font_size: f32) {
+    let font = font();
+    let scale = PxScale {
+        x: font_size,
+        y: font_size,
+    };
+    let padding = font_size as u32 / 4;
+    let (width, height) = imageproc::drawing::text_size(scale, &font, text);
+
+    imageproc::drawing::draw_filled_rect_mut(
+        img,
+        imageproc::rect::Rect::at(0, 0).of_size(width + padding * 2, height + padding * 2),
+        Rgb([0, 0, 0]),
+    );
+    imageproc::drawing::draw_text_mut(
+        img,
+        Rgb([255, 255, 255]),
+        padding as i32,
+        padding as i32,
+        scale,
+        &font,
+        text,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AnimationFormat {
+    Gif,
+    Png,
+}
+
+/// Animated GIF (`.gif`) or APNG (`.png`), looping forever
+///
+/// The format is checked and the file is created before sampling frames, to fail early
+pub struct AnimationFile {
+    format: AnimationFormat,
+    file: std::fs::File,
+}
+
+impl AnimationFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let format = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gif") => AnimationFormat::Gif,
+            Some("png") => AnimationFormat::Png,
+            _ => bail!("Unknown animation format {path:?}, expected .gif or .png"),
+        };
+        let file = std::fs::File::create(path).with_context(|| format!("Can't create {path:?}"))?;
+        Ok(Self { format, file })
+    }
+
+    /// Frames can have different sizes because of legends, they are padded to the largest one
+    pub fn save(self, frames: &[RgbImage], frame_delay_ms: u16) -> Result<()> {
+        let width = frames.iter().map(|frame| frame.width()).max().unwrap_or(1);
+        let height = frames.iter().map(|frame| frame.height()).max().unwrap_or(1);
+        let frames = frames.iter().map(|frame| {
+            let mut padded: RgbImage = ImageBuffer::new(width, height);
+            image::imageops::replace(&mut padded, frame, 0, 0);
+            padded
+        });
+
+        let writer = std::io::BufWriter::new(self.file);
+
+        match self.format {
+            AnimationFormat::Gif => {
+                use image::codecs::gif::{GifEncoder, Repeat};
+
+                let mut encoder = GifEncoder::new_with_speed(writer, 10);
+                encoder.set_repeat(Repeat::Infinite)?;
+                for frame in frames {
+                    let frame = image::Frame::from_parts(
+                        image::DynamicImage::ImageRgb8(frame).into_rgba8(),
+                        0,
+                        0,
+                        image::Delay::from_numer_denom_ms(frame_delay_ms as u32, 1),
+                    );
+                    encoder.encode_frame(frame)?;
+                }
+            }
+            AnimationFormat::Png => {
+                let mut encoder = png::Encoder::new(writer, width, height);
+                encoder.set_color(png::ColorType::Rgb);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_animated(frames.len() as u32, 0)?;
+                encoder.set_frame_delay(frame_delay_ms, 1000)?;
+                let mut writer = encoder.write_header()?;
+                for frame in frames {
+                    writer.write_image_data(&frame)?;
+                }
+                writer.finish()?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+//! Oracle SGA layout: areas, components and granules, and the shm segments holding them
+
+#[cfg(unix)]
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use log::debug;
+use oracle::Connection;
+use serde::{Deserialize, Serialize};
+
+/// A dynamic SGA component, from v$sga_dynamic_components
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SgaComponent {
+    /// e.g. shared pool, DEFAULT buffer cache
+    pub name: String,
+    pub current_size: u64,
+    pub granule_size: u64,
+}
+
+impl SgaComponent {
+    pub fn granules(&self) -> u64 {
+        self.current_size
+            .checked_div(self.granule_size)
+            .unwrap_or(0)
+    }
+
+    /// Area of v$sga holding the component
+    pub fn area(&self) -> &'static str {
+        let name = self.name.to_lowercase();
+        if name.contains("buffer cache") || name == "shared io pool" {
+            "Database Buffers"
+        } else if name.contains("in-memory") || name.contains("in memory") {
+            "In-Memory Area"
+        } else {
+            "Variable Size"
+        }
+    }
+}
+
+/// An area in a shm segment, from x$ksmssinfo
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SgaSegment {
+    pub area: String,
+    pub shmid: Option<u64>,
+    pub size: u64,
+    pub page_size: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SgaLayout {
+    /// v$sga: Fixed Size, Variable Size, Database Buffers, Redo Buffers, In-Memory Area
+    pub areas: Vec<(String, u64)>,
+    /// v$sgainfo
+    pub info: Vec<(String, u64)>,
+    /// v$sga_dynamic_components, without empty components
+    pub components: Vec<SgaComponent>,
+    /// x$ksmssinfo, empty before 12.2
+    pub segments: Vec<SgaSegment>,
+}
+
+impl SgaLayout {
+    pub fn query(conn: &Connection) -> Result<Self, oracle::Error> {
+        let sql = "select name, value from v$sga";
+        let areas = conn
+            .query_as::<(String, u64)>(sql, &[])?
+            .collect::<Result<_, _>>()?;
+
+        let sql = "select name, bytes from v$sgainfo";
+        let info = conn
+            .query_as::<(String, Option<u64>)>(sql, &[])?
+            .filter_map_ok(|(name, bytes)| Some((name, bytes?)))
+            .collect::<Result<_, _>>()?;
+
+        let sql = "select component, current_size, granule_size
+            from v$sga_dynamic_components where current_size > 0";
+        let components = conn
+            .query_as::<(String, u64, u64)>(sql, &[])?
+            .map_ok(|(name, current_size, granule_size)| SgaComponent {
+                name,
+                current_size,
+                granule_size,
+            })
+            .collect::<Result<_, _>>()?;
+
+        let segments = query_segments(conn).unwrap_or_else(|e| {
+            debug!("Can't read x$ksmssinfo: {e}");
+            Vec::new()
+        });
+
+        Ok(Self {
+            areas,
+            info,
+            components,
+            segments,
+        })
+    }
+}
+
+/// `123`, `4K`, `2M`...
+fn parse_size(input: &str) -> Option<u64> {
+    let input = input.trim();
+    let (digits, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => input.split_at(idx),
+        None => (input, ""),
+    };
+    let multiplier = match unit.trim().to_uppercase().as_str() {
+        "" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    Some(digits.parse::<u64>().ok()? * multiplier)
+}
+
+/// x$ksmssinfo is undocumented and its columns change between versions, they are matched by name
+fn query_segments(conn: &Connection) -> Result<Vec<SgaSegment>, Box<dyn std::error::Error>> {
+    let rows = conn.query("select * from x$ksmssinfo", &[])?;
+    let names: Vec<String> = rows
+        .column_info()
+        .iter()
+        .map(|column| column.name().to_uppercase().replace([' ', '_'], ""))
+        .collect();
+    let column = |candidates: &[&str]| {
+        names
+            .iter()
+            .position(|name| candidates.contains(&name.as_str()))
+    };
+    let area = column(&["AREANAME", "AREA"]).ok_or("No area column")?;
+    let size = column(&["SIZE", "BYTES"]).ok_or("No size column")?;
+    let shmid = column(&["SHMID", "SEGMENTID"]);
+    let page_size = column(&["PAGESIZE"]);
+
+    let mut segments = Vec::new();
+    for row in rows {
+        let row = row?;
+        let text = |idx: usize| -> Result<Option<String>, oracle::Error> { row.get(idx) };
+        segments.push(SgaSegment {
+            area: text(area)?.unwrap_or_default().trim().to_string(),
+            shmid: shmid
+                .map(text)
+                .transpose()?
+                .flatten()
+                .and_then(|shmid| shmid.trim().parse().ok()),
+            size: text(size)?
+                .as_deref()
+                .and_then(parse_size)
+                .ok_or("Invalid size")?,
+            page_size: page_size
+                .map(text)
+                .transpose()?
+                .flatten()
+                .as_deref()
+                .and_then(parse_size),
+        });
+    }
+
+    Ok(segments)
+}
+
+/// SGA areas in each shm segment of an instance, by shmid
+///
+/// Exact with the shmids of x$ksmssinfo. Otherwise areas are assigned by size, largest first,
+/// to the segment with the least room left that can hold them. Return true if exact
+#[cfg(unix)]
+pub fn assign_areas(layout: &SgaLayout, shms: &[procfs::Shm]) -> (HashMap<u64, Vec<String>>, bool) {
+    let mut assigned: HashMap<u64, Vec<String>> = HashMap::new();
+
+    if !layout.segments.is_empty() && layout.segments.iter().all(|s| s.shmid.is_some()) {
+        for segment in &layout.segments {
+            let areas = assigned.entry(segment.shmid.unwrap()).or_default();
+            if !areas.contains(&segment.area) {
+                areas.push(segment.area.clone());
+            }
+        }
+        return (assigned, true);
+    }
+
+    let mut areas: Vec<(String, u64)> = if layout.segments.is_empty() {
+        layout.areas.clone()
+    } else {
+        layout
+            .segments
+            .iter()
+            .map(|segment| (segment.area.clone(), segment.size))
+            .collect()
+    };
+    areas.retain(|(_, size)| *size > 0);
+    areas.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+
+    let mut room: Vec<(u64, u64)> = shms.iter().map(|shm| (shm.shmid, shm.size)).collect();
+    for (area, size) in areas {
+        let best_fit = room
+            .iter_mut()
+            .filter(|(_, left)| *left >= size)
+            .min_by_key(|(_, left)| *left);
+        // sizes are rounded to granules and pages, fall back to the segment with most room
+        let segment = match best_fit {
+            Some(segment) => segment,
+            None => match room.iter_mut().max_by_key(|(_, left)| *left) {
+                Some(segment) => segment,
+                None => break,
+            },
+        };
+        segment.1 = segment.1.saturating_sub(size);
+        let areas = assigned.entry(segment.0).or_default();
+        if !areas.contains(&area) {
+            areas.push(area);
+        }
+    }
+
+    (assigned, false)
+}
+
+/// SGA components, and how the shm segments of the instance are backed
+#[cfg(unix)]
+pub fn display(
+    sid: &str,
+    layout: &SgaLayout,
+    shms: &[procfs::Shm],
+    shms_metadata: &crate::ShmsMetadata,
+) {
+    use crate::tmpfs::format_units_MiB;
+    use tabled::Tabled;
+
+    #[derive(Tabled)]
+    struct ComponentDisplayRow {
+        component: String,
+        area: &'static str,
+        #[tabled(display = "format_units_MiB")]
+        size: u64,
+        granules: u64,
+        #[tabled(display = "format_units_MiB")]
+        granule_size: u64,
+        shmid: String,
+    }
+
+    #[derive(Tabled)]
+    struct SegmentDisplayRow {
+        shmid: u64,
+        areas: String,
+        #[tabled(display = "format_units_MiB")]
+        size: u64,
+        #[tabled(display = "format_units_MiB")]
+        resident: u64,
+        #[tabled(display = "format_units_MiB")]
+        swap: u64,
+        /// Renamed after the base page size
+        #[tabled(rename = "base pages")]
+        pages_base: String,
+        #[tabled(rename = "huge pages")]
+        pages_huge: String,
+    }
+
+    let page_size = procfs::page_size();
+    let (assigned, exact) = assign_areas(layout, shms);
+    let shmids_of = |area: &str| {
+        let mut shmids: Vec<u64> = assigned
+            .iter()
+            .filter(|(_, areas)| areas.iter().any(|a| a.eq_ignore_ascii_case(area)))
+            .map(|(shmid, _)| *shmid)
+            .collect();
+        shmids.sort();
+        shmids.iter().map(|shmid| shmid.to_string()).join(" ")
+    };
+
+    let mut components = layout.components.clone();
+    components.sort_by_key(|component| std::cmp::Reverse(component.current_size));
+    let component_rows: Vec<ComponentDisplayRow> = components
+        .iter()
+        .map(|component| ComponentDisplayRow {
+            component: component.name.clone(),
+            area: component.area(),
+            size: component.current_size,
+            granules: component.granules(),
+            granule_size: component.granule_size,
+            shmid: shmids_of(component.area()),
+        })
+        .collect();
+
+    let mut shms = shms.to_vec();
+    shms.sort_by_key(|shm| std::cmp::Reverse(shm.size));
+    let segment_rows: Vec<SegmentDisplayRow> = shms
+        .iter()
+        .map(|shm| {
+            let (resident, swap, pages_base, pages_huge) = match shms_metadata.get(shm) {
+                // huge pages are counted by 512 base pages, whatever the huge page size
+                Some(Some((pfns, swap_pages, pages_base, pages_huge))) => (
+                    pfns.len() as u64 * page_size,
+                    swap_pages.len() as u64 * page_size,
+                    format_units_MiB(&(*pages_base as u64 * page_size)),
+                    format_units_MiB(&(*pages_huge as u64 * 512 * page_size)),
+                ),
+                // not read because of swap
+                _ => (shm.rss, shm.swap, "-".to_string(), "-".to_string()),
+            };
+            SegmentDisplayRow {
+                shmid: shm.shmid,
+                areas: assigned
+                    .get(&shm.shmid)
+                    .map(|areas| areas.join(", "))
+                    .unwrap_or_default(),
+                size: shm.size,
+                resident,
+                swap,
+                pages_base,
+                pages_huge,
+            }
+        })
+        .collect();
+
+    println!("SGA components of {sid}:");
+    let mut table = tabled::Table::new(&component_rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+
+    let info = layout
+        .info
+        .iter()
+        .filter(|(name, _)| {
+            [
+                "Granule Size",
+                "Maximum SGA Size",
+                "Free SGA Memory Available",
+            ]
+            .contains(&name.as_str())
+        })
+        .map(|(name, bytes)| format!("{name}: {}", format_units_MiB(bytes)))
+        .join(", ");
+    if !info.is_empty() {
+        println!("{info}");
+    }
+
+    println!(
+        "SGA shm segments of {sid} (areas {}):",
+        if exact {
+            "from x$ksmssinfo"
+        } else {
+            "guessed from their size"
+        }
+    );
+    let mut table = tabled::Table::new(&segment_rows);
+    table.with(tabled::settings::Style::sharp());
+    // 4K, 16K, 64K
+    let base_pages = format!("{}K pages", page_size / 1024);
+    table.modify(
+        (0, 5),
+        tabled::settings::Format::content(|_| base_pages.clone()),
+    );
+    println!("{table}");
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn shm(shmid: u64, size: u64) -> procfs::Shm {
+        procfs::Shm {
+            key: 0,
+            shmid,
+            perms: 0o600,
+            size,
+            cpid: 1,
+            lpid: 1,
+            nattch: 1,
+            uid: 0,
+            gid: 0,
+            cuid: 0,
+            cgid: 0,
+            atime: 0,
+            dtime: 0,
+            ctime: 0,
+            rss: 0,
+            swap: 0,
+        }
+    }
+
+    fn segment(area: &str, shmid: Option<u64>, size: u64) -> SgaSegment {
+        SgaSegment {
+            area: area.to_string(),
+            shmid,
+            size,
+            page_size: None,
+        }
+    }
+
+    fn sorted(mut areas: Vec<String>) -> Vec<String> {
+        areas.sort();
+        areas
+    }
+
+    #[test]
+    fn size() {
+        assert_eq!(parse_size("123"), Some(123));
+        assert_eq!(parse_size(" 4K "), Some(4096));
+        assert_eq!(parse_size("2M"), Some(2 * MIB));
+        assert_eq!(parse_size("2 MB"), Some(2 * MIB));
+        assert_eq!(parse_size("1g"), Some(1024 * MIB));
+        assert_eq!(parse_size("1T"), None);
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size(""), None);
+    }
+
+    #[test]
+    fn exact_areas() {
+        let layout = SgaLayout {
+            segments: vec![
+                segment("Fixed Size", Some(1), 8 * MIB),
+                segment("Variable Size", Some(2), 500 * MIB),
+                segment("Database Buffers", Some(2), 1000 * MIB),
+                segment("Variable Size", Some(2), 100 * MIB),
+            ],
+            ..Default::default()
+        };
+        let (assigned, exact) = assign_areas(&layout, &[shm(1, 8 * MIB), shm(2, 1600 * MIB)]);
+        assert!(exact);
+        assert_eq!(assigned[&1], ["Fixed Size"]);
+        assert_eq!(assigned[&2], ["Variable Size", "Database Buffers"]);
+    }
+
+    #[test]
+    fn guessed_areas() {
+        // no x$ksmssinfo, v$sga sizes: each area goes to the smallest segment that can hold it
+        let layout = SgaLayout {
+            areas: vec![
+                ("Fixed Size".to_string(), 8 * MIB),
+                ("Variable Size".to_string(), 600 * MIB),
+                ("Database Buffers".to_string(), 1000 * MIB),
+                ("Redo Buffers".to_string(), 16 * MIB),
+                ("In-Memory Area".to_string(), 0),
+            ],
+            ..Default::default()
+        };
+        let shms = [shm(1, 10 * MIB), shm(2, 1600 * MIB), shm(3, 20 * MIB)];
+        let (assigned, exact) = assign_areas(&layout, &shms);
+        assert!(!exact);
+        assert_eq!(assigned[&1], ["Fixed Size"]);
+        assert_eq!(
+            sorted(assigned[&2].clone()),
+            ["Database Buffers", "Variable Size"]
+        );
+        assert_eq!(assigned[&3], ["Redo Buffers"]);
+        assert_eq!(assigned.len(), 3);
+    }
+
+    #[test]
+    fn guessed_areas_rounding() {
+        // segments without shmid, an area a bit larger than the segments: most room left wins
+        let layout = SgaLayout {
+            segments: vec![
+                segment("Variable Size", None, 105 * MIB),
+                segment("Fixed Size", None, 8 * MIB),
+            ],
+            ..Default::default()
+        };
+        let (assigned, exact) = assign_areas(&layout, &[shm(1, 100 * MIB), shm(2, 12 * MIB)]);
+        assert!(!exact);
+        assert_eq!(assigned[&1], ["Variable Size"]);
+        assert_eq!(assigned[&2], ["Fixed Size"]);
+    }
+
+    #[test]
+    fn no_segment() {
+        let layout = SgaLayout {
+            areas: vec![("Fixed Size".to_string(), 8 * MIB)],
+            ..Default::default()
+        };
+        let (assigned, exact) = assign_areas(&layout, &[]);
+        assert!(!exact);
+        assert!(assigned.is_empty());
+    }
+}
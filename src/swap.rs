@@ -0,0 +1,438 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use log::warn;
+use procfs::Current;
+use tabled::Tabled;
+
+use crate::tmpfs::format_units_MiB;
+use crate::ProcessInfo;
+
+/// Swap area from /proc/swaps
+///
+/// The kernel lists swap areas in `swap_info[]` order but skips unused slots, so the position of
+/// a device is its `swap_type` only if no lower-numbered device was removed by `swapoff`.
+/// See [`verify_swap_types`]
+#[derive(Debug, Clone)]
+pub struct SwapDevice {
+    /// Index in /proc/swaps, assumed to be the `swap_type` of pagemap swap entries
+    pub swap_type: u64,
+    pub filename: String,
+    /// "partition" or "file"
+    pub kind: String,
+    /// Size in Bytes
+    pub size: u64,
+    /// Used in Bytes
+    pub used: u64,
+    pub priority: i64,
+    /// Compression stats if the device is a zram device
+    pub zram: Option<ZramStats>,
+}
+
+impl SwapDevice {
+    /// zram devices are compressed in RAM, everything else is on disk
+    pub fn is_compressed(&self) -> bool {
+        self.zram.is_some()
+    }
+}
+
+/// Stats from /sys/block/zram<N>/mm_stat
+#[derive(Debug, Clone, Copy)]
+pub struct ZramStats {
+    /// Uncompressed size of data stored in the device
+    pub orig_data_size: u64,
+    /// Compressed size of data stored in the device
+    pub compr_data_size: u64,
+    /// Memory allocated by the device, including fragmentation and metadata
+    pub mem_used_total: u64,
+}
+
+/// Global zswap stats from /proc/meminfo (kernel 5.19+)
+#[derive(Debug, Clone, Copy)]
+pub struct ZswapStats {
+    /// Memory used by the zswap pool (compressed)
+    pub pool_size: u64,
+    /// Anonymous memory stored in zswap (uncompressed)
+    pub stored: u64,
+}
+
+/// Parse /proc/swaps content. Sizes are converted from kiB to Bytes
+pub fn parse_swaps(content: &str) -> Result<Vec<SwapDevice>> {
+    content
+        .lines()
+        .skip(1) // header
+        .filter(|line| !line.trim().is_empty())
+        .enumerate()
+        .map(|(swap_type, line)| {
+            let mut fields = line.split_whitespace();
+            let filename = fields.next().context("Missing filename")?.to_string();
+            let kind = fields.next().context("Missing type")?.to_string();
+            let size: u64 = fields.next().context("Missing size")?.parse()?;
+            let used: u64 = fields.next().context("Missing used")?.parse()?;
+            let priority: i64 = fields.next().context("Missing priority")?.parse()?;
+
+            Ok(SwapDevice {
+                swap_type: swap_type as u64,
+                filename,
+                kind,
+                size: size * 1024,
+                used: used * 1024,
+                priority,
+                zram: None,
+            })
+        })
+        .collect()
+}
+
+/// Check pagemap swap entries (type, offset) against the devices they are attributed to
+///
+/// The real `swap_type` of a device is not exposed to userspace, the position in /proc/swaps can
+/// only be contradicted: a type without a device, an offset beyond the end of the device, or more
+/// pages than the device uses
+pub fn verify_swap_types<'a>(
+    devices: &[SwapDevice],
+    swap_pages: impl IntoIterator<Item = &'a (u64, u64)>,
+) -> Result<(), String> {
+    let page_size = procfs::page_size();
+
+    // type -> (pages, max offset)
+    let mut per_type: std::collections::BTreeMap<u64, (u64, u64)> = Default::default();
+    for (swap_type, offset) in swap_pages {
+        let entry = per_type.entry(*swap_type).or_default();
+        entry.0 += 1;
+        entry.1 = entry.1.max(*offset);
+    }
+
+    for (swap_type, (pages, max_offset)) in per_type {
+        let Some(device) = devices.iter().find(|device| device.swap_type == swap_type) else {
+            return Err(format!("no device at position {swap_type} of /proc/swaps"));
+        };
+        if max_offset >= device.size / page_size {
+            return Err(format!(
+                "offset {max_offset} of swap type {swap_type} is beyond the end of {}",
+                device.filename
+            ));
+        }
+        if pages > device.used.div_ceil(page_size) {
+            return Err(format!(
+                "{pages} pages of swap type {swap_type}, but {} uses {}",
+                device.filename,
+                format_units_MiB(&device.used)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse /sys/block/zram<N>/mm_stat content
+pub fn parse_zram_mm_stat(content: &str) -> Result<ZramStats> {
+    let mut fields = content.split_whitespace();
+    let orig_data_size = fields.next().context("Missing orig_data_size")?.parse()?;
+    let compr_data_size = fields.next().context("Missing compr_data_size")?.parse()?;
+    let mem_used_total = fields.next().context("Missing mem_used_total")?.parse()?;
+
+    Ok(ZramStats {
+        orig_data_size,
+        compr_data_size,
+        mem_used_total,
+    })
+}
+
+/// Read /proc/swaps, and zram stats for zram devices
+pub fn get_swap_devices() -> Result<Vec<SwapDevice>> {
+    let content = std::fs::read_to_string("/proc/swaps").context("Can't read /proc/swaps")?;
+    let mut devices = parse_swaps(&content)?;
+
+    for device in devices.iter_mut() {
+        let Some(name) = device.filename.strip_prefix("/dev/") else {
+            continue;
+        };
+        if !name.starts_with("zram") {
+            continue;
+        }
+        let mm_stat = Path::new("/sys/block").join(name).join("mm_stat");
+        match std::fs::read_to_string(&mm_stat)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| parse_zram_mm_stat(&content))
+        {
+            Ok(stats) => device.zram = Some(stats),
+            Err(e) => warn!("Can't read {mm_stat:?}: {e:?}"),
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Return None if zswap is not reported by the kernel
+pub fn get_zswap_stats() -> Option<ZswapStats> {
+    let meminfo = procfs::Meminfo::current().ok()?;
+
+    Some(ZswapStats {
+        pool_size: meminfo.z_swap?,
+        stored: meminfo.z_swapped?,
+    })
+}
+
+/// Fraction of on-disk swap that is actually held compressed in RAM by zswap
+///
+/// zswap sits in front of the swap devices, so swap entries don't tell if a page is in the zswap pool
+/// or on disk. We assume zswap is spread evenly over disk devices
+pub fn zswap_ratio(devices: &[SwapDevice], zswap: Option<ZswapStats>) -> f64 {
+    let Some(zswap) = zswap else {
+        return 0.;
+    };
+    let disk_used: u64 = devices
+        .iter()
+        .filter(|device| !device.is_compressed())
+        .map(|device| device.used)
+        .sum();
+    if disk_used == 0 {
+        return 0.;
+    }
+
+    (zswap.stored as f64 / disk_used as f64).min(1.)
+}
+
+pub fn display_swap_devices(devices: &[SwapDevice], zswap: Option<ZswapStats>) {
+    #[derive(Tabled)]
+    struct SwapDeviceDisplayRow {
+        swap_type: u64,
+        device: String,
+        kind: String,
+        backend: &'static str,
+        #[tabled(display = "format_units_MiB")]
+        size: u64,
+        #[tabled(display = "format_units_MiB")]
+        used: u64,
+        /// Swapped data held compressed in RAM (uncompressed size)
+        #[tabled(display = "format_units_MiB")]
+        compressed: u64,
+        priority: i64,
+    }
+
+    if devices.is_empty() {
+        println!("Can't locate any swap device");
+        println!();
+        return;
+    }
+
+    let ratio = zswap_ratio(devices, zswap);
+    let display_info: Vec<SwapDeviceDisplayRow> = devices
+        .iter()
+        .map(|device| {
+            let (backend, compressed) = match (device.zram, zswap) {
+                (Some(zram), _) => ("zram", zram.orig_data_size),
+                (None, Some(_)) => ("disk+zswap", (device.used as f64 * ratio) as u64),
+                (None, None) => ("disk", 0),
+            };
+            SwapDeviceDisplayRow {
+                swap_type: device.swap_type,
+                device: device.filename.clone(),
+                kind: device.kind.clone(),
+                backend,
+                size: device.size,
+                used: device.used,
+                compressed,
+                priority: device.priority,
+            }
+        })
+        .collect();
+
+    println!("Swap devices:");
+    let mut table = tabled::Table::new(&display_info);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+
+    for device in devices {
+        if let Some(zram) = device.zram {
+            println!(
+                "{}: {} compressed to {}, {} used",
+                device.filename,
+                format_units_MiB(&zram.orig_data_size),
+                format_units_MiB(&zram.compr_data_size),
+                format_units_MiB(&zram.mem_used_total)
+            );
+        }
+    }
+    if let Some(zswap) = zswap {
+        println!(
+            "zswap: {} stored in a {} pool",
+            format_units_MiB(&zswap.stored),
+            format_units_MiB(&zswap.pool_size)
+        );
+    }
+    println!();
+}
+
+/// Processes with the most swapped anonymous memory
+/// Partially swapped processes are a common source of latency
+pub fn display_top_swapped_processes(processes_info: &[ProcessInfo], count: usize) {
+    #[derive(Tabled)]
+    struct SwappedProcessDisplayRow {
+        pid: i32,
+        uid: u32,
+        comm: String,
+        #[tabled(display = "format_units_MiB")]
+        rss: u64,
+        #[tabled(display = "format_units_MiB")]
+        swap_anon: u64,
+        #[tabled(display = "format_units_MiB")]
+        swap: u64,
+        #[tabled(rename = "swapped %")]
+        swapped: String,
+    }
+
+    let page_size = procfs::page_size();
+
+    let display_info: Vec<SwappedProcessDisplayRow> = processes_info
+        .iter()
+        .filter(|process_info| !process_info.anon_swap_pages.is_empty())
+        .map(|process_info| {
+            let comm = process_info
+                .process
+                .stat()
+                .map(|stat| stat.comm)
+                .unwrap_or_default();
+            let swap_anon = process_info.anon_swap_pages.len() as u64 * page_size;
+            let anon = process_info.anon_pfns.len() as u64 * page_size;
+            let swapped = swap_anon as f32 / (swap_anon + anon) as f32 * 100.;
+
+            SwappedProcessDisplayRow {
+                pid: process_info.process.pid,
+                uid: process_info.uid,
+                comm,
+                rss: process_info.rss,
+                swap_anon,
+                swap: process_info.swap_pages.len() as u64 * page_size,
+                swapped: format!("{swapped:.1}"),
+            }
+        })
+        .sorted_by_key(|row| std::cmp::Reverse(row.swap_anon))
+        .take(count)
+        .collect();
+
+    if display_info.is_empty() {
+        println!("No process uses swap");
+        println!();
+        return;
+    }
+
+    println!("Top {count} processes by anonymous swap:");
+    let mut table = tabled::Table::new(&display_info);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SWAPS: &str = "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority
+/dev/zram0                              partition\t8388604\t\t1048576\t\t100
+/swapfile                               file\t\t2097148\t\t0\t\t-2
+
+";
+
+    fn pages(bytes: u64) -> u64 {
+        bytes / procfs::page_size()
+    }
+
+    #[test]
+    fn swaps() {
+        let devices = parse_swaps(SWAPS).unwrap();
+        assert_eq!(devices.len(), 2);
+
+        assert_eq!(devices[0].swap_type, 0);
+        assert_eq!(devices[0].filename, "/dev/zram0");
+        assert_eq!(devices[0].kind, "partition");
+        assert_eq!(devices[0].size, 8388604 * 1024);
+        assert_eq!(devices[0].used, 1048576 * 1024);
+        assert_eq!(devices[0].priority, 100);
+        assert!(!devices[0].is_compressed());
+
+        assert_eq!(devices[1].swap_type, 1);
+        assert_eq!(devices[1].filename, "/swapfile");
+        assert_eq!(devices[1].kind, "file");
+        assert_eq!(devices[1].used, 0);
+        assert_eq!(devices[1].priority, -2);
+    }
+
+    #[test]
+    fn no_swap() {
+        let devices = parse_swaps("Filename\tType\tSize\tUsed\tPriority\n").unwrap();
+        assert!(devices.is_empty());
+    }
+
+    #[test]
+    fn invalid_swaps() {
+        assert!(parse_swaps("Filename Type Size Used Priority\n/swapfile file 1024 0\n").is_err());
+        assert!(parse_swaps("Filename Type Size Used Priority\n/swapfile file 1k 0 -2\n").is_err());
+    }
+
+    #[test]
+    fn zram_mm_stat() {
+        let stats = parse_zram_mm_stat(
+            "  4194304   1048576   1310720        0  1310720      12        0       0\n",
+        )
+        .unwrap();
+        assert_eq!(stats.orig_data_size, 4194304);
+        assert_eq!(stats.compr_data_size, 1048576);
+        assert_eq!(stats.mem_used_total, 1310720);
+
+        assert!(parse_zram_mm_stat("4194304 1048576").is_err());
+        assert!(parse_zram_mm_stat("").is_err());
+    }
+
+    #[test]
+    fn swap_types() {
+        let devices = parse_swaps(SWAPS).unwrap();
+        let used = pages(devices[0].used);
+        let swap_pages: Vec<(u64, u64)> = (0..used).map(|offset| (0, offset)).collect();
+        assert!(verify_swap_types(&devices, &swap_pages).is_ok());
+        assert!(verify_swap_types(&devices, &[]).is_ok());
+
+        // more pages than the device uses
+        let swap_pages: Vec<(u64, u64)> = (0..=used).map(|offset| (0, offset)).collect();
+        assert!(verify_swap_types(&devices, &swap_pages).is_err());
+
+        // offset beyond the end of the device
+        let end = pages(devices[0].size);
+        assert!(verify_swap_types(&devices, &[(0, end)]).is_err());
+        assert!(verify_swap_types(&devices, &[(0, end - 1)]).is_ok());
+
+        // pages on a device that uses nothing
+        assert!(verify_swap_types(&devices, &[(1, 0)]).is_err());
+    }
+
+    #[test]
+    fn swap_types_after_swapoff() {
+        // swapoff of type 0 frees its slot: /dev/zram0 keeps type 1, but is listed first
+        let devices = parse_swaps(
+            "Filename Type Size Used Priority
+/dev/zram0 partition 8388604 1048576 100
+",
+        )
+        .unwrap();
+        assert_eq!(devices[0].swap_type, 0);
+        let err = verify_swap_types(&devices, &[(1, 0), (1, 1)]).unwrap_err();
+        assert!(err.contains("no device at position 1"), "{err}");
+    }
+
+    #[test]
+    fn swap_types_reordered() {
+        // the large device has type 1 but is listed first: its offsets are beyond the end of the
+        // small device found at position 1
+        let devices = parse_swaps(
+            "Filename Type Size Used Priority
+/dev/sdb1 partition 1024000 1024000 -2
+/dev/sda2 partition 16384 16384 -3
+",
+        )
+        .unwrap();
+        let large_offset = pages(devices[0].size) - 1;
+        assert!(verify_swap_types(&devices, &[(1, large_offset)]).is_err());
+    }
+}
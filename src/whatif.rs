@@ -0,0 +1,213 @@
+use procfs::{process::Pfn, PhysicalPageFlags, Shm};
+use std::collections::HashMap;
+use tabled::Tabled;
+
+use crate::tmpfs::format_units_MiB;
+use crate::ProcessGroupInfo;
+
+/// SHM_DEST (0o1000) from linux/shm.h: segment will be destroyed on last detach
+///
+/// The kernel prints perms in octal, but procfs parses them as decimal,
+/// so SHM_DEST is the lowest bit of the 4th digit
+fn is_marked_for_destruction(shm: &Shm) -> bool {
+    (shm.perms / 1000) & 1 == 1
+}
+
+/// Memory released if a group of processes is killed or restarted
+/// All sizes in Bytes
+#[derive(Debug, Default)]
+pub struct WhatIf {
+    pub processes: usize,
+    /// Anon pages not referenced by remaining processes: freed
+    pub exclusive_anon: u64,
+    /// Anon pages also referenced by remaining processes (CoW after fork): not freed
+    pub shared_anon: u64,
+    /// File pages not mapped by remaining processes: stay in page cache, but become reclaimable
+    pub exclusive_file: u64,
+    /// File pages also mapped by remaining processes: not freed
+    pub shared_file: u64,
+    /// Shmem pages (tmpfs, /dev/shm, memfd, shared anonymous mappings) not mapped by remaining
+    /// processes: swap backed, they stay in memory until the file is removed or they are swapped out
+    pub exclusive_shmem: u64,
+    /// Shmem pages also mapped by remaining processes: not freed
+    pub shared_shmem: u64,
+    /// Swap slots not referenced by remaining processes: released
+    pub exclusive_swap: u64,
+    pub shared_swap: u64,
+    /// Shm segments only attached by removed processes
+    pub exclusive_shms: Vec<Shm>,
+    /// Shm segments also attached by remaining processes
+    pub shared_shms: Vec<Shm>,
+}
+
+/// Compare the group to remove with all remaining processes
+///
+/// Only pages that are not referenced by any remaining process are accounted as freed
+pub fn what_if_remove(
+    removed: &ProcessGroupInfo,
+    remaining: &ProcessGroupInfo,
+    all_physical_pages: &HashMap<Pfn, PhysicalPageFlags>,
+) -> WhatIf {
+    let page_size = procfs::page_size();
+
+    let mut what_if = WhatIf {
+        processes: removed.processes_info.len(),
+        ..Default::default()
+    };
+
+    for pfn in &removed.pfns {
+        if pfn.0 == 0 {
+            continue;
+        }
+        let shared = remaining.pfns.contains(pfn);
+        let flags = all_physical_pages.get(pfn);
+        let anon = removed.anon_pfns.contains(pfn)
+            && flags.is_none_or(|flags| flags.contains(PhysicalPageFlags::ANON));
+        // shmem pages are swap backed, but not anon
+        let shmem =
+            !anon && flags.is_some_and(|flags| flags.contains(PhysicalPageFlags::SWAPBACKED));
+        let counter = match (anon, shmem, shared) {
+            (true, _, false) => &mut what_if.exclusive_anon,
+            (true, _, true) => &mut what_if.shared_anon,
+            (false, true, false) => &mut what_if.exclusive_shmem,
+            (false, true, true) => &mut what_if.shared_shmem,
+            (false, false, false) => &mut what_if.exclusive_file,
+            (false, false, true) => &mut what_if.shared_file,
+        };
+        *counter += page_size;
+    }
+
+    for swap_page in &removed.swap_pages {
+        if remaining.swap_pages.contains(swap_page) {
+            what_if.shared_swap += page_size;
+        } else {
+            what_if.exclusive_swap += page_size;
+        }
+    }
+
+    for shm in &removed.referenced_shm {
+        if remaining.referenced_shm.contains(shm) {
+            what_if.shared_shms.push(*shm);
+        } else {
+            what_if.exclusive_shms.push(*shm);
+        }
+    }
+    what_if
+        .exclusive_shms
+        .sort_by_key(|shm| std::cmp::Reverse(shm.size));
+    what_if
+        .shared_shms
+        .sort_by_key(|shm| std::cmp::Reverse(shm.size));
+
+    what_if
+}
+
+pub fn display_what_if(name: &str, what_if: &WhatIf) {
+    #[derive(Tabled)]
+    struct WhatIfDisplayRow {
+        memory: &'static str,
+        #[tabled(display = "format_units_MiB")]
+        released: u64,
+        #[tabled(display = "format_units_MiB")]
+        kept: u64,
+        comment: &'static str,
+    }
+
+    #[derive(Tabled)]
+    struct ShmDisplayRow {
+        key: i32,
+        shmid: u64,
+        #[tabled(display = "format_units_MiB")]
+        rss: u64,
+        #[tabled(display = "format_units_MiB")]
+        swap: u64,
+        nattch: u32,
+        status: &'static str,
+    }
+
+    let exclusive_shm_rss: u64 = what_if.exclusive_shms.iter().map(|shm| shm.rss).sum();
+    let exclusive_shm_swap: u64 = what_if.exclusive_shms.iter().map(|shm| shm.swap).sum();
+    let shared_shm_rss: u64 = what_if.shared_shms.iter().map(|shm| shm.rss).sum();
+    let shared_shm_swap: u64 = what_if.shared_shms.iter().map(|shm| shm.swap).sum();
+
+    let rows = [
+        WhatIfDisplayRow {
+            memory: "anon",
+            released: what_if.exclusive_anon,
+            kept: what_if.shared_anon,
+            comment: "freed",
+        },
+        WhatIfDisplayRow {
+            memory: "file",
+            released: what_if.exclusive_file,
+            kept: what_if.shared_file,
+            comment: "stays in page cache, becomes reclaimable",
+        },
+        WhatIfDisplayRow {
+            memory: "shmem",
+            released: what_if.exclusive_shmem,
+            kept: what_if.shared_shmem,
+            comment: "tmpfs/memfd: kept until the file is removed, can only be swapped out",
+        },
+        WhatIfDisplayRow {
+            memory: "swap",
+            released: what_if.exclusive_swap,
+            kept: what_if.shared_swap,
+            comment: "swap slots released, not RAM",
+        },
+        WhatIfDisplayRow {
+            memory: "shm mem",
+            released: exclusive_shm_rss,
+            kept: shared_shm_rss,
+            comment: "freed when segments are removed",
+        },
+        WhatIfDisplayRow {
+            memory: "shm swap",
+            released: exclusive_shm_swap,
+            kept: shared_shm_swap,
+            comment: "freed when segments are removed",
+        },
+    ];
+
+    println!(
+        "What if {name} is removed ({} processes):",
+        what_if.processes
+    );
+    let mut table = tabled::Table::new(rows);
+    table.with(tabled::settings::Style::sharp());
+    println!("{table}");
+    println!(
+        "Immediately freed RAM: {}",
+        format_units_MiB(&what_if.exclusive_anon)
+    );
+    println!(
+        "Released swap slots: {}",
+        format_units_MiB(&what_if.exclusive_swap)
+    );
+
+    if !what_if.exclusive_shms.is_empty() {
+        // sysv shm outlives processes, unless it was marked for destruction (IPC_RMID)
+        let rows: Vec<ShmDisplayRow> = what_if
+            .exclusive_shms
+            .iter()
+            .map(|shm| ShmDisplayRow {
+                key: shm.key,
+                shmid: shm.shmid,
+                rss: shm.rss,
+                swap: shm.swap,
+                nattch: shm.nattch,
+                status: if is_marked_for_destruction(shm) {
+                    "destroyed on last detach"
+                } else {
+                    "kept until removed (IPC_RMID)"
+                },
+            })
+            .collect();
+
+        println!("Shm segments only attached by removed processes:");
+        let mut table = tabled::Table::new(rows);
+        table.with(tabled::settings::Style::sharp());
+        println!("{table}");
+    }
+    println!();
+}